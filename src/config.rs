@@ -0,0 +1,545 @@
+//! Building a formatter and its transport from a configuration file.
+//!
+//! [`Config`](struct.Config.html) holds the same settings as
+//! [`Rfc5424Builder`](../struct.Rfc5424Builder.html), plus where to send the
+//! messages. With the `serde` feature it can be deserialized from any format
+//! serde supports, such as TOML, JSON or YAML. Every field is optional.
+//!
+//! [`Rfc5424::from_config`](../struct.Rfc5424.html#method.from_config) checks the
+//! whole config, and reports every invalid field at once.
+//!
+//! # Examples
+//! ```ignore
+//! // facility = "local0"
+//! // enterprise_id = "32473"
+//! // hostname = { static = "web1.example.com" }
+//! // app_name = "checkout"
+//! // write_format = "RFC5425"
+//! // origin = [{ software = "checkout" }, { swVersion = "1.4.2" }]
+//! // transport = { type = "tcp", address = "logs.example.com:601" }
+//! let config: Config = toml::from_str(&std::fs::read_to_string("syslog.toml")?)?;
+//!
+//! let formatter = Rfc5424::from_config(&config)?;
+//! let transport = config.transport.expect("no transport configured").connect()?;
+//! let mut sender = Sender::new(formatter, transport);
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use discovery;
+use iana::{Origin, TimeQuality};
+use rfc3164::SdFlattening;
+use sanitize::{ControlChars, Sanitize};
+use sender::Transport;
+use types::*;
+use {Bom, Error, Rfc5424, WriteFormat};
+
+/// Settings for a [`Rfc5424`](../struct.Rfc5424.html) formatter and its
+/// transport. Header fields that aren't set are written as NILVALUE.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Config {
+    pub facility: Facility,
+    /// See [`Rfc5424Builder::new`](../struct.Rfc5424Builder.html#method.new).
+    /// Must be a private enterprise number, optionally followed by
+    /// sub-identifiers, such as `32473` or `32473.1.2`.
+    pub enterprise_id: String,
+    pub hostname: HostnameSource,
+    /// The name of the executable is used if this is `"auto"`, see
+    /// [`discovery::app_name`](../discovery/fn.app_name.html)
+    pub app_name: Option<String>,
+    pub pid: PidSource,
+    pub msg_id: Option<String>,
+    pub write_format: WriteFormat,
+    /// See [`Rfc5424Builder::sd_flattening`](../struct.Rfc5424Builder.html#method.sd_flattening),
+    /// only used with `WriteFormat::RFC3164`
+    pub sd_flattening: Option<SdFlattening>,
+    /// See [`Rfc5424Builder::max_len`](../struct.Rfc5424Builder.html#method.max_len)
+    pub max_len: Option<usize>,
+    /// See [`Rfc5424Builder::sanitize`](../struct.Rfc5424Builder.html#method.sanitize)
+    pub sanitize: Sanitize,
+    /// See [`Rfc5424Builder::control_chars`](../struct.Rfc5424Builder.html#method.control_chars)
+    pub control_chars: ControlChars,
+    /// See [`Rfc5424Builder::bom`](../struct.Rfc5424Builder.html#method.bom)
+    pub bom: Bom,
+    pub origin: Vec<Origin>,
+    pub time_quality: Vec<TimeQuality>,
+    pub transport: Option<TransportConfig>,
+}
+
+/// Where the HOSTNAME comes from
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum HostnameSource {
+    /// Write NILVALUE
+    #[default]
+    Nil,
+    Static(String),
+    /// See [`discovery::hostname`](../discovery/fn.hostname.html)
+    Auto,
+}
+
+/// Where the PROCID comes from
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum PidSource {
+    /// Write NILVALUE
+    #[default]
+    Nil,
+    Static(String),
+    /// The id of this process, read every time a message is formatted
+    Auto,
+}
+
+/// Where messages are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", rename_all = "lowercase")
+)]
+pub enum TransportConfig {
+    /// `address` is a `host:port` pair
+    Udp { address: String },
+    /// `address` is a `host:port` pair
+    Tcp { address: String },
+    /// A Unix datagram socket, usually `/dev/log`
+    #[cfg(unix)]
+    Unix { path: PathBuf },
+}
+
+impl TransportConfig {
+    /// Connect to the configured receiver.
+    ///
+    /// # Errors
+    /// Errors when the address can't be resolved, or connecting fails (`io::Error`)
+    pub fn connect(&self) -> io::Result<Box<dyn Transport + Send>> {
+        match self {
+            TransportConfig::Udp { address } => {
+                let mut last_error = None;
+                for addr in address.to_socket_addrs()? {
+                    let local = if addr.is_ipv4() {
+                        "0.0.0.0:0"
+                    } else {
+                        "[::]:0"
+                    };
+                    let socket = UdpSocket::bind(local)?;
+                    match socket.connect(addr) {
+                        Ok(()) => return Ok(Box::new(socket)),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")
+                }))
+            }
+            TransportConfig::Tcp { address } => Ok(Box::new(TcpStream::connect(address.as_str())?)),
+            #[cfg(unix)]
+            TransportConfig::Unix { path } => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Box::new(socket))
+            }
+        }
+    }
+}
+
+/// An invalid field of a [`Config`](struct.Config.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Name of the field in the config, such as `app_name` or `transport.address`
+    pub field: &'static str,
+    pub value: String,
+    pub error: Error,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?}: {}", self.field, self.value, self.error)
+    }
+}
+
+/// Every invalid field of a [`Config`](struct.Config.html). Never empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid syslog config")?;
+        for (i, e) in self.errors.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { "," }, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// Collects the errors of the fields checked so far
+struct Checker {
+    errors: Vec<FieldError>,
+}
+
+impl Checker {
+    fn check<T>(
+        &mut self,
+        field: &'static str,
+        value: &str,
+        result: Result<T, Error>,
+    ) -> Option<T> {
+        match result {
+            Ok(v) => Some(v),
+            Err(error) => {
+                self.errors.push(FieldError {
+                    field,
+                    value: value.to_string(),
+                    error,
+                });
+                None
+            }
+        }
+    }
+
+    fn optional<T>(
+        &mut self,
+        field: &'static str,
+        value: &Option<String>,
+        new: fn(&str) -> Result<T, Error>,
+    ) -> Option<T> {
+        let value = value.as_ref()?;
+        self.check(field, value, new(value))
+    }
+}
+
+fn check_enterprise_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() {
+        return Ok(());
+    }
+    if id
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidCharacters)
+    }
+}
+
+fn check_address(address: &str) -> Result<(), Error> {
+    if address.is_empty() {
+        return Err(Error::FieldEmpty);
+    }
+    // the port is required
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(Error::InvalidAddress),
+    }
+}
+
+fn check_origin(checker: &mut Checker, origin: &Origin) {
+    match origin {
+        Origin::Ip(ip) => {
+            let valid = ip.parse::<IpAddr>().map_err(|_| Error::InvalidAddress);
+            checker.check("origin.ip", ip, valid);
+        }
+        Origin::EnterpriseId(id) => {
+            let valid = if id.is_empty() {
+                Err(Error::FieldEmpty)
+            } else {
+                check_enterprise_id(id)
+            };
+            checker.check("origin.enterpriseId", id, valid);
+        }
+        Origin::Software(_) | Origin::Version(_) => {}
+    }
+}
+
+impl Rfc5424 {
+    /// Create a formatter from the settings in `config`. The transport isn't
+    /// connected, use [`TransportConfig::connect`](config/enum.TransportConfig.html#method.connect)
+    /// for that.
+    ///
+    /// # Errors
+    /// Returns all fields that fail the checks of their
+    /// [`Rfc5424Builder`](struct.Rfc5424Builder.html) setter, an `enterprise_id`
+    /// that isn't a private enterprise number, an `origin` with an invalid `ip` or
+    /// `enterpriseId`, and a transport address without a port.
+    pub fn from_config(config: &Config) -> Result<Rfc5424, ConfigError> {
+        let mut checker = Checker { errors: Vec::new() };
+
+        checker.check(
+            "enterprise_id",
+            &config.enterprise_id,
+            check_enterprise_id(&config.enterprise_id),
+        );
+        let hostname = match &config.hostname {
+            HostnameSource::Nil => None,
+            HostnameSource::Static(name) => checker.check("hostname", name, HostName::new(name)),
+            HostnameSource::Auto => discovery::hostname(),
+        };
+        let app_name = match config.app_name.as_deref() {
+            Some("auto") => discovery::app_name(),
+            _ => checker.optional("app_name", &config.app_name, AppName::new),
+        };
+        let pid = match &config.pid {
+            PidSource::Nil => None,
+            PidSource::Static(pid) => checker
+                .check("pid", pid, ProcessId::new(pid))
+                .map(ProcessIdSource::Static),
+            PidSource::Auto => Some(ProcessIdSource::Current),
+        };
+        let msg_id = checker.optional("msg_id", &config.msg_id, MessageId::new);
+        for origin in &config.origin {
+            check_origin(&mut checker, origin);
+        }
+        match &config.transport {
+            Some(TransportConfig::Udp { address }) | Some(TransportConfig::Tcp { address }) => {
+                checker.check("transport.address", address, check_address(address));
+            }
+            #[cfg(unix)]
+            Some(TransportConfig::Unix { path }) => {
+                let empty = if path.as_os_str().is_empty() {
+                    Err(Error::FieldEmpty)
+                } else {
+                    Ok(())
+                };
+                checker.check("transport.path", &path.to_string_lossy(), empty);
+            }
+            None => {}
+        }
+
+        if !checker.errors.is_empty() {
+            return Err(ConfigError {
+                errors: checker.errors,
+            });
+        }
+
+        Ok(Rfc5424 {
+            version: 1,
+            hostname: hostname.unwrap_or_default(),
+            app_name: app_name.unwrap_or_default(),
+            pid: pid.unwrap_or_default(),
+            msg_id: msg_id.unwrap_or_default(),
+            facility: config.facility,
+            enterprise_id: config.enterprise_id.clone(),
+            iana_time_quality: config.time_quality.clone(),
+            iana_origin: config.origin.clone(),
+            write_format: config.write_format,
+            sd_flattening: config.sd_flattening.unwrap_or_default(),
+            max_len: config.max_len,
+            sanitize: config.sanitize,
+            control_chars: config.control_chars,
+            bom: config.bom,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use Rfc5424Data;
+    use StructuredData;
+
+    struct Event;
+
+    impl Rfc5424Data for Event {
+        fn severity(&self) -> Severity {
+            Severity::Notice
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text("configured".into()))
+        }
+    }
+
+    #[test]
+    fn builds_formatter() {
+        let config = Config {
+            facility: Facility::Local0,
+            enterprise_id: "32473.1".into(),
+            hostname: HostnameSource::Static("web1.example.com".into()),
+            app_name: Some("checkout".into()),
+            pid: PidSource::Static("77".into()),
+            msg_id: Some("ORDER".into()),
+            origin: vec![Origin::Software("checkout".into())],
+            ..Default::default()
+        };
+        let formatter = Rfc5424::from_config(&config).unwrap();
+
+        let mut out = Vec::new();
+        formatter.format(&mut out, &Event).unwrap();
+        let message = parse(&out).unwrap();
+        assert_eq!(message.facility, Facility::Local0);
+        assert_eq!(message.hostname, HostName::new("web1.example.com").unwrap());
+        assert_eq!(message.app_name, AppName::new("checkout").unwrap());
+        assert_eq!(message.pid, ProcessId::new("77").unwrap());
+        assert_eq!(message.msg_id, MessageId::new("ORDER").unwrap());
+        assert_eq!(message.structured_data[0].id, "origin");
+    }
+
+    #[test]
+    fn flattens_sd() {
+        let config = Config {
+            write_format: WriteFormat::RFC3164,
+            origin: vec![Origin::Software("checkout".into())],
+            ..Default::default()
+        };
+        let format = |config: &Config| {
+            let mut out = Vec::new();
+            let formatter = Rfc5424::from_config(config).unwrap();
+            formatter.format(&mut out, &Event).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(format(&config).contains("[origin software=\"checkout\"] configured"));
+
+        let config = Config {
+            sd_flattening: Some(SdFlattening::Discard),
+            ..config
+        };
+        assert!(format(&config).ends_with(" - configured"));
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let config = Config {
+            enterprise_id: "acme".into(),
+            hostname: HostnameSource::Static("".into()),
+            app_name: Some("check out".into()),
+            msg_id: Some("x".repeat(33)),
+            origin: vec![
+                Origin::Ip("192.0.2".into()),
+                Origin::EnterpriseId("32473.x".into()),
+            ],
+            transport: Some(TransportConfig::Udp {
+                address: "localhost".into(),
+            }),
+            ..Default::default()
+        };
+        let errors = Rfc5424::from_config(&config).unwrap_err().errors;
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "enterprise_id",
+                "hostname",
+                "app_name",
+                "msg_id",
+                "origin.ip",
+                "origin.enterpriseId",
+                "transport.address"
+            ]
+        );
+        assert_eq!(errors[1].error, Error::FieldEmpty);
+        assert_eq!(errors[2].value, "check out");
+        assert_eq!(errors[3].error, Error::FieldTooLong);
+        assert_eq!(errors[4].error, Error::InvalidAddress);
+        assert_eq!(errors[6].error, Error::InvalidAddress);
+    }
+
+    #[test]
+    fn checks_addresses() {
+        assert_eq!(check_address("logs.example.com:601"), Ok(()));
+        assert_eq!(check_address("[2001:db8::1]:514"), Ok(()));
+        assert_eq!(check_address(""), Err(Error::FieldEmpty));
+        assert_eq!(check_address(":514"), Err(Error::InvalidAddress));
+        assert_eq!(
+            check_address("localhost:syslog"),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(check_address("localhost:65536"), Err(Error::InvalidAddress));
+    }
+
+    #[test]
+    fn discovers() {
+        let config = Config {
+            app_name: Some("auto".into()),
+            pid: PidSource::Auto,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        Rfc5424::from_config(&config)
+            .unwrap()
+            .format(&mut out, &Event)
+            .unwrap();
+        let message = parse(&out).unwrap();
+        assert_eq!(message.pid, discovery::pid());
+        assert_eq!(message.app_name, discovery::app_name().unwrap());
+    }
+
+    #[test]
+    fn connects_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = TransportConfig::Udp {
+            address: receiver.local_addr().unwrap().to_string(),
+        };
+        let mut transport = transport.connect().unwrap();
+        transport.send(b"<13>1 - - - - - -").unwrap();
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"<13>1 - - - - - -");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializes() {
+        let config: Config = ::serde_json::from_str(
+            r#"{
+                "facility": "local4",
+                "enterprise_id": "32473",
+                "hostname": { "static": "web1.example.com" },
+                "app_name": "checkout",
+                "write_format": "RFC5425",
+                "sd_flattening": "key_value",
+                "origin": [{ "ip": "192.0.2.1" }, { "swVersion": "1.4.2" }],
+                "time_quality": [{ "tzKnown": true }],
+                "transport": { "type": "tcp", "address": "logs.example.com:601" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.facility, Facility::Local4);
+        assert_eq!(config.pid, PidSource::Nil);
+        assert_eq!(config.write_format, WriteFormat::RFC5425);
+        assert_eq!(config.sd_flattening, Some(SdFlattening::KeyValue));
+        assert_eq!(
+            config.transport,
+            Some(TransportConfig::Tcp {
+                address: "logs.example.com:601".into()
+            })
+        );
+        assert!(Rfc5424::from_config(&config).is_ok());
+
+        assert!(::serde_json::from_str::<Config>(r#"{ "app": "typo" }"#).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Header values found from the environment of the running process.
+//!
+//! These are used by the `discover_*` functions of
+//! [`Rfc5424Builder`](../struct.Rfc5424Builder.html), but can also be called
+//! directly, for example to log the values once at startup.
+
+use std::env;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::process;
+#[cfg(unix)]
+use std::{mem, ptr};
+
+use types::*;
+
+/// The hostname of this machine, as configured on it. This is the FQDN when
+/// the configured name has a domain.
+///
+/// The name is read from `/proc/sys/kernel/hostname`, with `gethostname`, from
+/// `/etc/hostname`, or the `HOSTNAME` environment variable (`COMPUTERNAME` on
+/// Windows), whichever is found first. Characters that aren't printable ASCII
+/// are removed. Returns `None` if there is no usable hostname, in which case
+/// NILVALUE should be used.
+///
+/// No DNS lookups are done, see [`resolved_hostname`](fn.resolved_hostname.html)
+/// for that.
+pub fn hostname() -> Option<HostName> {
+    let clean = |contents: Option<String>| clean_hostname(&contents?);
+    clean(fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| clean(gethostname()))
+        .or_else(|| clean(fs::read_to_string("/etc/hostname").ok()))
+        .or_else(|| clean(env::var(HOSTNAME_VAR).ok()))
+}
+
+/// The HOSTNAME of this machine, in the order of preference of
+/// [RFC5424 Section 6.2.4](https://tools.ietf.org/html/rfc5424#section-6.2.4):
+///
+/// 1. the FQDN, when the [`hostname`](fn.hostname.html) has a domain or
+///    resolves to a canonical name with one
+/// 2. an IP address the hostname resolves to, other than a loopback address
+/// 3. the hostname
+///
+/// Resolving the hostname can block for as long as the system resolver takes
+/// to answer.
+pub fn resolved_hostname() -> Option<HostName> {
+    hostname().map(|name| preferred_hostname(name, canonical_name, host_ip))
+}
+
+/// The APP-NAME, taken from the name of the executable without its extension.
+/// Characters that aren't printable ASCII are removed, and the name is
+/// truncated to 48 characters.
+pub fn app_name() -> Option<AppName> {
+    let exe = env::current_exe()
+        .ok()
+        .or_else(|| env::args_os().next().map(Into::into))?;
+    let stem = Path::new(&exe).file_stem()?.to_string_lossy().into_owned();
+    AppName::new(&sanitize(&stem, 48)).ok()
+}
+
+/// The PROCID, which is the id of this process (`std::process::id()`)
+pub fn pid() -> ProcessId {
+    ProcessId(process::id().to_string())
+}
+
+fn sanitize(val: &str, max_length: usize) -> String {
+    val.chars()
+        .filter(char::is_ascii_graphic)
+        .take(max_length)
+        .collect()
+}
+
+#[cfg(not(windows))]
+const HOSTNAME_VAR: &str = "HOSTNAME";
+#[cfg(windows)]
+const HOSTNAME_VAR: &str = "COMPUTERNAME";
+
+/// The hostname in the contents of a hostname file, or `None` if it's empty
+fn clean_hostname(contents: &str) -> Option<HostName> {
+    let name = sanitize(contents.lines().next()?, 255);
+    HostName::new(&name).ok()
+}
+
+/// The first of the FQDN, IP address and `name` that is found with the lookups
+fn preferred_hostname(
+    name: HostName,
+    canonical_name: fn(&str) -> Option<String>,
+    host_ip: fn(&str) -> Option<IpAddr>,
+) -> HostName {
+    if name.0.contains('.') {
+        return name;
+    }
+    let fqdn = canonical_name(&name.0)
+        .filter(|fqdn| fqdn.contains('.'))
+        .and_then(|fqdn| HostName::new(&fqdn).ok());
+    let ip = || host_ip(&name.0).and_then(|ip| HostName::new(&ip.to_string()).ok());
+    fqdn.or_else(ip).unwrap_or(name)
+}
+
+fn host_ip(name: &str) -> Option<IpAddr> {
+    (name, 0)
+        .to_socket_addrs()
+        .ok()?
+        .map(|addr| addr.ip())
+        .find(|ip| !ip.is_loopback() && !ip.is_unspecified())
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn gethostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the length passed is the length of `buf`
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    // the name isn't NUL terminated if it was truncated
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn gethostname() -> Option<String> {
+    None
+}
+
+/// The canonical name of `host` according to the resolver (`getaddrinfo` with
+/// `AI_CANONNAME`), which is the FQDN on a correctly configured machine.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn canonical_name(host: &str) -> Option<String> {
+    let host = CString::new(host).ok()?;
+    // SAFETY: an all zero `addrinfo` is a valid hints argument, with null pointers
+    let mut hints: libc::addrinfo = unsafe { mem::zeroed() };
+    hints.ai_family = libc::AF_UNSPEC;
+    hints.ai_flags = libc::AI_CANONNAME;
+    let mut res: *mut libc::addrinfo = ptr::null_mut();
+
+    // SAFETY: `host` is NUL terminated, and `res` is only read when the call succeeds
+    let rc = unsafe { libc::getaddrinfo(host.as_ptr(), ptr::null(), &hints, &mut res) };
+    if rc != 0 || res.is_null() {
+        return None;
+    }
+    // SAFETY: `res` points to the list returned by `getaddrinfo`, which is freed
+    // only after the name is copied out of it
+    unsafe {
+        let canonname = (*res).ai_canonname;
+        let name = if canonname.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(canonname).to_string_lossy().into_owned())
+        };
+        libc::freeaddrinfo(res);
+        name
+    }
+}
+
+#[cfg(not(unix))]
+fn canonical_name(_host: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes() {
+        assert_eq!(sanitize("my app\u{e9}", 48), "myapp");
+        assert_eq!(sanitize(&"a".repeat(60), 48).len(), 48);
+    }
+
+    #[test]
+    fn process_values() {
+        assert_eq!(pid().0, process::id().to_string());
+
+        // the test binary is named after the crate, plus a hash
+        let app_name = app_name().unwrap();
+        assert!(app_name.0.starts_with("syslog5424"), "{:?}", app_name);
+    }
+
+    #[test]
+    fn cleans_hostname() {
+        fn name(contents: &str) -> Option<String> {
+            clean_hostname(contents).map(|name| name.0)
+        }
+        assert_eq!(name("web1.example.com\n"), Some("web1.example.com".into()));
+        assert_eq!(name(" web1 \nsecond line"), Some("web1".into()));
+        assert_eq!(name("h\u{f6}st-1"), Some("hst-1".into()));
+        assert_eq!(name(&"a".repeat(300)).unwrap().len(), 255);
+        assert_eq!(name("\n"), None);
+        assert_eq!(name(""), None);
+    }
+
+    #[test]
+    fn prefers_fqdn() {
+        fn fqdn(_: &str) -> Option<String> {
+            Some("web1.example.com".into())
+        }
+        fn short(name: &str) -> Option<String> {
+            Some(name.into())
+        }
+        fn none(_: &str) -> Option<String> {
+            None
+        }
+        fn ip(_: &str) -> Option<IpAddr> {
+            Some("192.0.2.1".parse().unwrap())
+        }
+        fn no_ip(_: &str) -> Option<IpAddr> {
+            None
+        }
+        let preferred = |canonical, host_ip| {
+            preferred_hostname(HostName::new("web1").unwrap(), canonical, host_ip).0
+        };
+        assert_eq!(preferred(fqdn, ip), "web1.example.com");
+        assert_eq!(preferred(short, ip), "192.0.2.1");
+        assert_eq!(preferred(none, ip), "192.0.2.1");
+        assert_eq!(preferred(none, no_ip), "web1");
+        let configured = HostName::new("db.example.org").unwrap();
+        assert_eq!(preferred_hostname(configured, fqdn, ip).0, "db.example.org");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_hostname() {
+        let name = gethostname().unwrap();
+        assert_eq!(clean_hostname(&name), hostname());
+        if let Some(name) = resolved_hostname() {
+            assert_eq!(HostName::new(&name.0).unwrap(), name);
+        }
+    }
+}
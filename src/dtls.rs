@@ -0,0 +1,792 @@
+//! Syslog over DTLS ([RFC6012](https://tools.ietf.org/html/rfc6012)).
+//!
+//! [`DtlsTransport`](struct.DtlsTransport.html) sends every formatted message
+//! as its own DTLS record over UDP, to port [`DEFAULT_PORT`](constant.DEFAULT_PORT.html)
+//! by default. Like TLS, each message is octet counted inside the record
+//! (`WriteFormat::RFC5425`), which
+//! [`DtlsTransport::into_sender`](struct.DtlsTransport.html#method.into_sender)
+//! sets on the formatter.
+//!
+//! The transport is configured with an `SslConnector` built for
+//! `SslMethod::dtls()`: certificate verification, trusted CAs and the client
+//! certificate are all set there. When the association fails, the next message
+//! starts a new handshake. With the `dtls-resumption` feature that handshake
+//! offers the previous session, so reconnecting to the same receiver is cheap.
+//! It's a separate feature because offering a session needs `unsafe` code:
+//! OpenSSL requires the session to come from the same `SslContext`, which the
+//! type system can't check.
+//!
+//! [`Server::serve_dtls`](../server/struct.Server.html#method.serve_dtls)
+//! receives messages over DTLS, and [`cookie_exchange`](fn.cookie_exchange.html)
+//! makes it check that clients own their address before it does any expensive
+//! work: ClientHellos are answered without keeping state, and a thread and
+//! connection are only used once a client echoes its cookie.
+//!
+//! # Examples
+//! ```ignore
+//! let mut connector = SslConnector::builder(SslMethod::dtls())?;
+//! connector.set_ca_file("ca.pem")?;
+//! let transport = DtlsTransport::connect(connector.build(), "logs.example.com:6514", "logs.example.com")?;
+//! let mut sender = transport.into_sender(formatter);
+//! sender.send(&my_message)?;
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender as Notify};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use openssl::ex_data::Index;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::ssl::{
+    HandshakeError, Ssl, SslAcceptor, SslConnector, SslContext, SslContextBuilder, SslOptions,
+    SslStream,
+};
+#[cfg(feature = "dtls-resumption")]
+use openssl::ssl::{SslRef, SslSession};
+
+use sender::{Sender, Transport};
+use {Rfc5424, WriteFormat};
+
+/// Port assigned to syslog over DTLS
+pub const DEFAULT_PORT: u16 = 6514;
+
+/// Payload of the largest datagram sent during handshakes, which fits the
+/// minimum IPv6 MTU of 1280 bytes
+const MTU: u32 = 1232;
+
+/// How long to wait for a handshake flight before sending ours again
+const RETRANSMIT: Duration = Duration::from_secs(1);
+
+/// How long a handshake may take, retransmissions included
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn ssl_error<E: ToString>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Start the handshake of `ssl` over `stream`, keeping datagrams within `MTU`.
+///
+/// `Ssl::set_mtu` alone is reset when the handshake starts, unless the context
+/// has `SslOptions::NO_QUERY_MTU`, which can't be set on a connector that's
+/// already built. OpenSSL then falls back to its 256 byte minimum, and splits a
+/// ClientHello offering a session into fragments `Server::serve_dtls` can't
+/// check the cookie of. The MTU the stream reports when queried is kept.
+#[allow(deprecated)]
+fn start<S: Read + Write>(ssl: Ssl, stream: S) -> Result<SslStream<S>, HandshakeError<S>> {
+    let mut builder = ::openssl::ssl::SslStreamBuilder::new(ssl, stream);
+    builder.set_dtls_mtu_size(MTU as usize);
+    builder.handshake()
+}
+
+/// Drive a blocking handshake to the end, letting OpenSSL retransmit its
+/// flight every time a read times out.
+fn handshake<S: Read + Write + fmt::Debug>(
+    mut result: Result<SslStream<S>, HandshakeError<S>>,
+) -> io::Result<SslStream<S>> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    loop {
+        result = match result {
+            Ok(stream) => return Ok(stream),
+            Err(HandshakeError::WouldBlock(mid)) if Instant::now() < deadline => mid.handshake(),
+            Err(e) => return Err(ssl_error(e)),
+        };
+    }
+}
+
+/// A connected UDP socket, where every write is a datagram.
+#[derive(Debug)]
+struct Connected(UdpSocket);
+
+impl Read for Connected {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Connected {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sends messages over DTLS. See the [module documentation](index.html).
+///
+/// Each message must fit in a single DTLS record, 16 KiB of plaintext.
+pub struct DtlsTransport {
+    connector: SslConnector,
+    address: SocketAddr,
+    domain: String,
+    stream: Option<SslStream<Connected>>,
+    #[cfg(feature = "dtls-resumption")]
+    session: Option<SslSession>,
+}
+
+impl DtlsTransport {
+    /// Perform a handshake with the receiver at `address`, whose certificate is
+    /// checked against `domain` when `connector` verifies certificates.
+    ///
+    /// # Errors
+    /// Errors when `address` doesn't resolve or the handshake fails.
+    pub fn connect<A: ToSocketAddrs>(
+        connector: SslConnector,
+        address: A,
+        domain: &str,
+    ) -> io::Result<DtlsTransport> {
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+        })?;
+        let mut transport = DtlsTransport {
+            connector,
+            address,
+            domain: domain.to_string(),
+            stream: None,
+            #[cfg(feature = "dtls-resumption")]
+            session: None,
+        };
+        transport.stream = Some(transport.handshake()?);
+        Ok(transport)
+    }
+
+    /// Send a close_notify alert, and perform a new handshake. With the
+    /// `dtls-resumption` feature the handshake offers to resume the current session.
+    ///
+    /// # Errors
+    /// Errors when the handshake fails. The next message sent tries again.
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.shutdown();
+        }
+        self.stream = Some(self.handshake()?);
+        Ok(())
+    }
+
+    /// Whether the current association resumed the session of the previous
+    /// one, instead of performing a full handshake. Always `false` without the
+    /// `dtls-resumption` feature.
+    pub fn session_reused(&self) -> bool {
+        self.stream
+            .as_ref()
+            .is_some_and(|stream| stream.ssl().session_reused())
+    }
+
+    /// The address messages are sent to
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Pair the transport with `formatter`, switching it to
+    /// `WriteFormat::RFC5425` as RFC6012 requires.
+    pub fn into_sender(self, mut formatter: Rfc5424) -> Sender<DtlsTransport> {
+        formatter.write_format = WriteFormat::RFC5425;
+        Sender::new(formatter, self)
+    }
+
+    fn handshake(&mut self) -> io::Result<SslStream<Connected>> {
+        let local: SocketAddr = if self.address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(self.address)?;
+        socket.set_read_timeout(Some(RETRANSMIT))?;
+
+        let mut ssl = self
+            .connector
+            .configure()
+            .and_then(|config| config.into_ssl(&self.domain))
+            .map_err(ssl_error)?;
+        ssl.set_mtu(MTU).map_err(ssl_error)?;
+        #[cfg(feature = "dtls-resumption")]
+        self.offer_session(&mut ssl)?;
+
+        ssl.set_connect_state();
+        let stream = handshake(start(ssl, Connected(socket)))?;
+        #[cfg(feature = "dtls-resumption")]
+        {
+            self.session = stream.ssl().session().map(|session| session.to_owned());
+        }
+        Ok(stream)
+    }
+
+    /// Offer the session of the previous association in the handshake of `ssl`.
+    /// This is the only `unsafe` code of the crate.
+    #[cfg(feature = "dtls-resumption")]
+    #[allow(unsafe_code)]
+    fn offer_session(&self, ssl: &mut SslRef) -> io::Result<()> {
+        if let Some(ref session) = self.session {
+            // SAFETY: `SSL_set_session` needs the session to belong to the
+            // `SslContext` of `ssl`. `ssl` is configured from `self.connector`,
+            // and `self.session` is only set from streams configured from it too.
+            // The connector can't be replaced after `connect`, so both always
+            // come from the same context.
+            unsafe { ssl.set_session(session) }.map_err(ssl_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for DtlsTransport {
+    /// Sends `message` as one record, performing a new handshake first if the
+    /// previous send failed.
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.handshake()?,
+        };
+        let written = stream
+            .ssl_write(message)
+            .map_err(|e| e.into_io_error().unwrap_or_else(ssl_error))?;
+        self.stream = Some(stream);
+        if written < message.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message doesn't fit in a DTLS record",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DtlsTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DtlsTransport")
+            .field("address", &self.address)
+            .field("domain", &self.domain)
+            .field("connected", &self.stream.is_some())
+            .finish()
+    }
+}
+
+fn peer_index() -> Index<Ssl, SocketAddr> {
+    static INDEX: OnceLock<Index<Ssl, SocketAddr>> = OnceLock::new();
+    *INDEX.get_or_init(|| Ssl::new_ex_index().expect("no ex_data index left"))
+}
+
+fn cookies_index() -> Index<SslContext, Cookies> {
+    static INDEX: OnceLock<Index<SslContext, Cookies>> = OnceLock::new();
+    *INDEX.get_or_init(|| SslContext::new_ex_index().expect("no ex_data index left"))
+}
+
+/// The key cookies are made with, shared by the callbacks of the acceptor and
+/// the listener of [`Server::serve_dtls`](../server/struct.Server.html#method.serve_dtls)
+#[derive(Clone)]
+struct Cookies(Arc<PKey<Private>>);
+
+impl Cookies {
+    /// The cookie sent to `peer`: an HMAC of its address
+    fn cookie(&self, peer: &SocketAddr) -> Option<Vec<u8>> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.0).ok()?;
+        signer.update(peer.to_string().as_bytes()).ok()?;
+        signer.sign_to_vec().ok()
+    }
+
+    fn verify(&self, peer: &SocketAddr, received: &[u8]) -> bool {
+        self.cookie(peer)
+            .is_some_and(|cookie| cookie.len() == received.len() && memcmp::eq(&cookie, received))
+    }
+}
+
+/// Make the acceptor built from `context` answer the first ClientHello of every
+/// client with a HelloVerifyRequest, and only go on once the client echoes the
+/// cookie it was sent ([RFC6347 Section 4.2.1](https://tools.ietf.org/html/rfc6347#section-4.2.1)).
+///
+/// Cookies are an HMAC of the client's address, keyed with a secret drawn when
+/// this is called. They only work for associations accepted by
+/// [`Server::serve_dtls`](../server/struct.Server.html#method.serve_dtls),
+/// which answers ClientHellos without a valid cookie itself, before it starts
+/// an association.
+///
+/// # Errors
+/// Errors when no random secret can be drawn.
+pub fn cookie_exchange(context: &mut SslContextBuilder) -> io::Result<()> {
+    let mut secret = [0; 32];
+    rand_bytes(&mut secret).map_err(ssl_error)?;
+    let cookies = Cookies(Arc::new(PKey::hmac(&secret).map_err(ssl_error)?));
+    let generate = cookies.clone();
+    let verify = cookies.clone();
+
+    context.set_options(SslOptions::COOKIE_EXCHANGE);
+    context.set_cookie_generate_cb(move |ssl, buf| {
+        match ssl
+            .ex_data(peer_index())
+            .and_then(|peer| generate.cookie(peer))
+        {
+            Some(cookie) if cookie.len() <= buf.len() => {
+                buf[..cookie.len()].copy_from_slice(&cookie);
+                Ok(cookie.len())
+            }
+            _ => Err(::openssl::error::ErrorStack::get()),
+        }
+    });
+    context.set_cookie_verify_cb(move |ssl, received| {
+        ssl.ex_data(peer_index())
+            .is_some_and(|peer| verify.verify(peer, received))
+    });
+    context.set_ex_data(cookies_index(), cookies);
+    Ok(())
+}
+
+/// What the listener does with the first datagram of a peer without an association
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Hello {
+    /// Start an association, handing it these datagrams
+    Accept(Vec<Vec<u8>>),
+    /// Send this HelloVerifyRequest to the peer, and keep no state for it
+    Verify(Vec<u8>),
+    /// Drop the datagram
+    Ignore,
+}
+
+/// Length of the DTLS record header
+const RECORD_HEADER: usize = 13;
+/// Length of the DTLS handshake message header
+const HANDSHAKE_HEADER: usize = 12;
+const CONTENT_HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const HELLO_VERIFY_REQUEST: u8 = 3;
+/// DTLS 1.0, the version of HelloVerifyRequests whatever version is negotiated
+const DTLS1_VERSION: [u8; 2] = [0xfe, 0xff];
+
+/// Decide what to do with `datagram`, the first one of `peer`, without keeping
+/// any state: with [`cookie_exchange`](fn.cookie_exchange.html) only a
+/// ClientHello with a valid cookie starts an association, the way
+/// `DTLSv1_listen` works. Without it every datagram does.
+///
+/// The association is handed a copy of the first ClientHello, rebuilt from the
+/// second one, followed by the second one. OpenSSL answers the copy with a
+/// HelloVerifyRequest the client ignores as a retransmission, and then goes on
+/// with the handshake as if it had sent the cookie itself.
+pub(crate) fn hello(acceptor: &SslAcceptor, peer: &SocketAddr, datagram: &[u8]) -> Hello {
+    let cookies = match acceptor.context().ex_data(cookies_index()) {
+        Some(cookies) => cookies,
+        None => return Hello::Accept(vec![datagram.to_vec()]),
+    };
+    let hello = match ClientHello::parse(datagram) {
+        Some(hello) => hello,
+        None => return Hello::Ignore,
+    };
+    if !cookies.verify(peer, hello.cookie()) {
+        return match cookies.cookie(peer) {
+            Some(cookie) => Hello::Verify(hello.verify_request(&cookie)),
+            None => Hello::Ignore,
+        };
+    }
+    match hello.without_cookie() {
+        Some(first) => Hello::Accept(vec![first, datagram.to_vec()]),
+        None => Hello::Ignore,
+    }
+}
+
+/// A ClientHello sent in a single record and fragment
+struct ClientHello<'a> {
+    /// The record, headers included
+    record: &'a [u8],
+    /// Offset of the cookie length in `record`
+    cookie_at: usize,
+}
+
+impl<'a> ClientHello<'a> {
+    fn parse(datagram: &'a [u8]) -> Option<ClientHello<'a>> {
+        let header = datagram.get(..RECORD_HEADER + HANDSHAKE_HEADER)?;
+        let record_len = u16::from_be_bytes([header[11], header[12]]) as usize;
+        let record = datagram.get(..RECORD_HEADER + record_len)?;
+        let message_len = u24(&header[14..17]);
+        let unfragmented = u24(&header[19..22]) == 0 && u24(&header[22..25]) == message_len;
+        if header[0] != CONTENT_HANDSHAKE
+            || header[3..5] != [0, 0]
+            || header[13] != CLIENT_HELLO
+            || !unfragmented
+            || HANDSHAKE_HEADER + message_len != record_len
+        {
+            return None;
+        }
+        // client_version and random, then the session id
+        let session_at = RECORD_HEADER + HANDSHAKE_HEADER + 2 + 32;
+        let cookie_at = session_at + 1 + *record.get(session_at)? as usize;
+        let cookie_len = *record.get(cookie_at)? as usize;
+        record.get(cookie_at + 1..cookie_at + 1 + cookie_len)?;
+        Some(ClientHello { record, cookie_at })
+    }
+
+    fn cookie(&self) -> &'a [u8] {
+        let len = self.record[self.cookie_at] as usize;
+        &self.record[self.cookie_at + 1..self.cookie_at + 1 + len]
+    }
+
+    fn record_sequence(&self) -> &'a [u8] {
+        &self.record[5..11]
+    }
+
+    /// A HelloVerifyRequest sending `cookie`, in a record with the same
+    /// sequence number as the ClientHello
+    fn verify_request(&self, cookie: &[u8]) -> Vec<u8> {
+        let body_len = 2 + 1 + cookie.len();
+        let mut out = vec![CONTENT_HANDSHAKE];
+        out.extend_from_slice(&DTLS1_VERSION);
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(self.record_sequence());
+        out.extend_from_slice(&((HANDSHAKE_HEADER + body_len) as u16).to_be_bytes());
+        out.push(HELLO_VERIFY_REQUEST);
+        out.extend_from_slice(&(body_len as u32).to_be_bytes()[1..]);
+        // message_seq and fragment_offset
+        out.extend_from_slice(&[0; 5]);
+        out.extend_from_slice(&(body_len as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(&DTLS1_VERSION);
+        out.push(cookie.len() as u8);
+        out.extend_from_slice(cookie);
+        out
+    }
+
+    /// The first ClientHello the client sent, before it had a cookie: the same
+    /// message with an empty cookie, the first message_seq, and the previous
+    /// record sequence number. `None` if this isn't the second ClientHello.
+    fn without_cookie(&self) -> Option<Vec<u8>> {
+        let header = &self.record[..RECORD_HEADER + HANDSHAKE_HEADER];
+        if header[17..19] != [0, 1] {
+            return None;
+        }
+        let mut sequence = [0; 8];
+        sequence[2..].copy_from_slice(self.record_sequence());
+        let sequence = u64::from_be_bytes(sequence).checked_sub(1)?.to_be_bytes();
+
+        let cookie_len = self.cookie().len();
+        let record_len = self.record.len() - RECORD_HEADER - cookie_len;
+        let message_len = (record_len - HANDSHAKE_HEADER) as u32;
+        let mut out = header[..5].to_vec();
+        out.extend_from_slice(&sequence[2..]);
+        out.extend_from_slice(&(record_len as u16).to_be_bytes());
+        out.push(CLIENT_HELLO);
+        out.extend_from_slice(&message_len.to_be_bytes()[1..]);
+        out.extend_from_slice(&[0, 0, 0, 0, 0]);
+        out.extend_from_slice(&message_len.to_be_bytes()[1..]);
+        out.extend_from_slice(&self.record[RECORD_HEADER + HANDSHAKE_HEADER..self.cookie_at]);
+        out.push(0);
+        out.extend_from_slice(&self.record[self.cookie_at + 1 + cookie_len..]);
+        Some(out)
+    }
+}
+
+/// A 24 bit big endian number
+fn u24(bytes: &[u8]) -> usize {
+    (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize
+}
+
+/// The datagrams a server received from one peer, and the socket to answer it
+/// with. `closed` is told the peer and `id` when the association is dropped.
+pub(crate) struct Association {
+    pub(crate) socket: Arc<UdpSocket>,
+    pub(crate) peer: SocketAddr,
+    pub(crate) id: u64,
+    pub(crate) datagrams: Receiver<Vec<u8>>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) closed: Notify<(SocketAddr, u64)>,
+}
+
+impl Read for Association {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let datagram = match self.timeout {
+            Some(timeout) => match self.datagrams.recv_timeout(timeout) {
+                Ok(datagram) => datagram,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            },
+            None => match self.datagrams.recv() {
+                Ok(datagram) => datagram,
+                Err(_) => return Ok(0),
+            },
+        };
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for Association {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(buf, self.peer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Association {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Association")
+            .field("peer", &self.peer)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Drop for Association {
+    fn drop(&mut self) {
+        let _ = self.closed.send((self.peer, self.id));
+    }
+}
+
+/// Accept the association with `acceptor`. Reads time out after `idle` once
+/// the handshake is done.
+pub(crate) fn accept(
+    acceptor: &SslAcceptor,
+    mut association: Association,
+    idle: Option<Duration>,
+) -> io::Result<SslStream<Association>> {
+    let mut ssl = Ssl::new(acceptor.context()).map_err(ssl_error)?;
+    ssl.set_mtu(MTU).map_err(ssl_error)?;
+    ssl.set_ex_data(peer_index(), association.peer);
+    association.timeout = Some(RETRANSMIT);
+    ssl.set_accept_state();
+    let mut stream = handshake(start(ssl, association))?;
+    stream.get_mut().timeout = idle;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::pkey::Private;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{MidHandshakeSslStream, SslMethod};
+    use openssl::x509::{X509NameBuilder, X509};
+    use parser::parse;
+    use server::{Received, Server};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use types::*;
+    use {Rfc5424Builder, Rfc5424Data, StructuredData};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    struct Line(&'static str);
+
+    impl Rfc5424Data for Line {
+        fn severity(&self) -> Severity {
+            Severity::Notice
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.0.to_string()))
+        }
+    }
+
+    fn certificate() -> (PKey<Private>, X509) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (key, cert.build())
+    }
+
+    /// An acceptor with cookie exchange on, and a connector trusting its certificate
+    fn acceptor() -> (SslAcceptor, SslConnector) {
+        let (key, cert) = certificate();
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::dtls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        cookie_exchange(&mut acceptor).unwrap();
+
+        let mut connector = SslConnector::builder(SslMethod::dtls()).unwrap();
+        connector.cert_store_mut().add_cert(cert).unwrap();
+        (acceptor.build(), connector.build())
+    }
+
+    /// A server using `acceptor()`, and a connector trusting it
+    fn listener() -> (SocketAddr, Receiver<Received>, SslConnector) {
+        let (acceptor, connector) = acceptor();
+        let (tx, rx) = channel();
+        let server = Server::new(tx);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || server.serve_dtls(&socket, acceptor));
+        (addr, rx, connector)
+    }
+
+    /// The datagram `receiver` got after the client's handshake stopped to wait
+    /// for an answer
+    fn sent(
+        receiver: &UdpSocket,
+        result: Result<SslStream<Connected>, HandshakeError<Connected>>,
+    ) -> (MidHandshakeSslStream<Connected>, Vec<u8>) {
+        let mut buf = [0; 2048];
+        match result {
+            Err(HandshakeError::WouldBlock(mid)) => {
+                let n = receiver.recv(&mut buf).unwrap();
+                (mid, buf[..n].to_vec())
+            }
+            _ => panic!("the handshake didn't wait for an answer"),
+        }
+    }
+
+    /// A client handshaking with `receiver`, and the first ClientHello it sent
+    fn client_hello(
+        connector: &SslConnector,
+        receiver: &UdpSocket,
+    ) -> (SocketAddr, MidHandshakeSslStream<Connected>, Vec<u8>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = socket.local_addr().unwrap();
+        socket.connect(receiver.local_addr().unwrap()).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let ssl = connector
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let (mid, hello) = sent(receiver, ssl.connect(Connected(socket)));
+        (client, mid, hello)
+    }
+
+    fn formatter() -> Rfc5424 {
+        Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("dtls")
+            .unwrap()
+            .build()
+    }
+
+    fn assert_received(rx: &Receiver<Received>, text: &str) {
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text(text.to_string()))
+        );
+    }
+
+    #[test]
+    fn sends_octet_counted_records() {
+        let (addr, rx, connector) = listener();
+        let transport = DtlsTransport::connect(connector, addr, "localhost").unwrap();
+        assert!(!transport.session_reused());
+
+        let mut sender = transport.into_sender(formatter());
+        assert_eq!(sender.formatter().write_format, WriteFormat::RFC5425);
+        sender.send(&Line("first")).unwrap();
+        sender.send(&Line("second")).unwrap();
+        assert_received(&rx, "first");
+        assert_received(&rx, "second");
+
+        let mut message = Vec::new();
+        sender.formatter().format(&mut message, &Line("x")).unwrap();
+        let space = message.iter().position(|&b| b == b' ').unwrap();
+        assert!(parse(&message[space + 1..]).is_ok());
+    }
+
+    #[cfg(feature = "dtls-resumption")]
+    #[test]
+    fn resumes_session() {
+        let (addr, rx, connector) = listener();
+        let mut transport = DtlsTransport::connect(connector, addr, "localhost").unwrap();
+        transport.reconnect().unwrap();
+        assert!(transport.session_reused());
+
+        let mut sender = transport.into_sender(formatter());
+        sender.send(&Line("resumed")).unwrap();
+        assert_received(&rx, "resumed");
+    }
+
+    #[cfg(not(feature = "dtls-resumption"))]
+    #[test]
+    fn reconnects() {
+        let (addr, rx, connector) = listener();
+        let mut transport = DtlsTransport::connect(connector, addr, "localhost").unwrap();
+        transport.reconnect().unwrap();
+        assert!(!transport.session_reused());
+
+        let mut sender = transport.into_sender(formatter());
+        sender.send(&Line("reconnected")).unwrap();
+        assert_received(&rx, "reconnected");
+    }
+
+    #[test]
+    fn verifies_cookies_statelessly() {
+        let (acceptor, connector) = acceptor();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (client, mid, first) = client_hello(&connector, &receiver);
+        let verify = match hello(&acceptor, &client, &first) {
+            Hello::Verify(verify) => verify,
+            other => panic!("{:?}", other),
+        };
+        receiver.send_to(&verify, client).unwrap();
+
+        // the association gets the first ClientHello back, rebuilt from the second
+        let (_, second) = sent(&receiver, mid.handshake());
+        assert_eq!(
+            hello(&acceptor, &client, &second),
+            Hello::Accept(vec![first, second.clone()])
+        );
+
+        // the cookie is only valid for the address it was sent to
+        let spoofed: SocketAddr = "192.0.2.1:6514".parse().unwrap();
+        assert!(matches!(
+            hello(&acceptor, &spoofed, &second),
+            Hello::Verify(_)
+        ));
+        assert_eq!(hello(&acceptor, &client, b"not a hello"), Hello::Ignore);
+    }
+
+    #[test]
+    fn spoofed_hellos_use_no_connection() {
+        let (acceptor, connector) = acceptor();
+        let server = Server::new(|_: Received| {});
+        let metrics = server.metrics();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || server.serve_dtls(&socket, acceptor));
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (_, _, hello) = client_hello(&connector, &receiver);
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        spoofer.set_read_timeout(Some(TIMEOUT)).unwrap();
+        spoofer.send_to(&hello, addr).unwrap();
+        let mut buf = [0; 2048];
+        let n = spoofer.recv(&mut buf).unwrap();
+        assert_eq!(buf[RECORD_HEADER], HELLO_VERIFY_REQUEST);
+        assert!(n > RECORD_HEADER + HANDSHAKE_HEADER);
+        assert_eq!(metrics.connections_accepted(), 0);
+
+        DtlsTransport::connect(connector, addr, "localhost").unwrap();
+        assert_eq!(metrics.connections_accepted(), 1);
+    }
+
+    #[test]
+    fn verifies_certificate() {
+        let (addr, _rx, connector) = listener();
+        assert!(DtlsTransport::connect(connector, addr, "example.com").is_err());
+
+        let untrusted = SslConnector::builder(SslMethod::dtls()).unwrap().build();
+        assert!(DtlsTransport::connect(untrusted, addr, "localhost").is_err());
+    }
+}
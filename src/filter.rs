@@ -0,0 +1,276 @@
+//! Dropping messages by severity, facility, MSGID or structured data.
+//!
+//! A [`Filter`](struct.Filter.html) is set on a
+//! [`Sender`](../sender/struct.Sender.html) with
+//! [`Sender::filter`](../sender/struct.Sender.html#method.filter). Clones of a
+//! filter share its rules, so a clone kept elsewhere can change them while the
+//! sender is running, for example to raise the verbosity of a live process.
+//!
+//! # Examples
+//! ```ignore
+//! let filter = Filter::new();
+//! filter.set_min_severity(Severity::Warning);
+//! filter.set_msg_ids(Rule::Deny(vec!["HEALTHCHECK".into()]));
+//! let mut sender = Sender::new(formatter, socket).filter(filter.clone());
+//!
+//! // later, from a signal handler thread or an admin endpoint
+//! filter.set_min_severity(Severity::Debug);
+//! ```
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+
+use types::*;
+use {Rfc5424, Rfc5424Data};
+
+/// Which values of a field are let through
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Rule<T> {
+    /// Every value
+    #[default]
+    Any,
+    /// Only these values
+    Allow(Vec<T>),
+    /// Every value except these
+    Deny(Vec<T>),
+}
+
+impl<T: PartialEq> Rule<T> {
+    fn allows(&self, value: &T) -> bool {
+        match self {
+            Rule::Any => true,
+            Rule::Allow(values) => values.contains(value),
+            Rule::Deny(values) => !values.contains(value),
+        }
+    }
+}
+
+/// Rules deciding which messages are sent. See the [module documentation](index.html).
+///
+/// A new filter lets every message through.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    rules: Arc<Rules>,
+}
+
+#[derive(Debug)]
+struct Rules {
+    min_severity: AtomicU8,
+    /// One bit for each facility code
+    facilities: AtomicU32,
+    msg_ids: RwLock<Rule<String>>,
+    sd_ids: RwLock<Rule<String>>,
+}
+
+const ALL_FACILITIES: u32 = (1 << 24) - 1;
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new()
+    }
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter {
+            rules: Arc::new(Rules {
+                min_severity: AtomicU8::new(Severity::Debug as u8),
+                facilities: AtomicU32::new(ALL_FACILITIES),
+                msg_ids: RwLock::new(Rule::Any),
+                sd_ids: RwLock::new(Rule::Any),
+            }),
+        }
+    }
+
+    /// Drop messages that are less severe than `severity`. `Severity::Debug`
+    /// lets every message through.
+    pub fn set_min_severity(&self, severity: Severity) {
+        self.rules
+            .min_severity
+            .store(severity as u8, Ordering::Relaxed);
+    }
+
+    pub fn min_severity(&self) -> Severity {
+        let code = self.rules.min_severity.load(Ordering::Relaxed);
+        Severity::try_from(code).unwrap_or(Severity::Debug)
+    }
+
+    /// Set which facilities are let through. The facility of a message is the
+    /// one it overrides, or the one of the formatter.
+    pub fn set_facilities(&self, rule: Rule<Facility>) {
+        let mask =
+            |facilities: &[Facility]| facilities.iter().fold(0, |mask, f| mask | 1 << *f as u32);
+        let allowed = match rule {
+            Rule::Any => ALL_FACILITIES,
+            Rule::Allow(facilities) => mask(&facilities),
+            Rule::Deny(facilities) => ALL_FACILITIES & !mask(&facilities),
+        };
+        self.rules.facilities.store(allowed, Ordering::Relaxed);
+    }
+
+    /// Set which MSGIDs are let through. The MSGID of a message is the one it
+    /// overrides, or the one of the formatter. NILVALUE is matched by `"-"`.
+    pub fn set_msg_ids(&self, rule: Rule<String>) {
+        *self.rules.msg_ids.write().unwrap() = rule;
+    }
+
+    /// Set which SD-IDs a message must, or must not, have. `Rule::Allow` lets
+    /// through messages with at least one of the SD-IDs, and `Rule::Deny` drops
+    /// messages with any of them.
+    ///
+    /// SD-IDs match both with and without the `@enterprise_id` suffix, and the
+    /// IANA elements added by the formatter (`origin`, `timeQuality`) count too.
+    pub fn set_sd_ids(&self, rule: Rule<String>) {
+        *self.rules.sd_ids.write().unwrap() = rule;
+    }
+
+    /// Check if `message`, formatted with `formatter`, passes the filter.
+    pub fn allows(&self, formatter: &Rfc5424, message: &impl Rfc5424Data) -> bool {
+        if message.severity() > self.min_severity() {
+            return false;
+        }
+
+        let header = formatter.header(message);
+        let facilities = self.rules.facilities.load(Ordering::Relaxed);
+        if facilities & 1 << header.facility as u32 == 0 {
+            return false;
+        }
+        if !self.rules.msg_ids.read().unwrap().allows(&header.msg_id.0) {
+            return false;
+        }
+
+        let sd_ids = self.rules.sd_ids.read().unwrap();
+        let (ids, allow) = match &*sd_ids {
+            Rule::Any => return true,
+            Rule::Allow(ids) => (ids, true),
+            Rule::Deny(ids) => (ids, false),
+        };
+        let present = formatter.sd_elements(message).iter().any(|element| {
+            let name = element.id.split('@').next().unwrap_or("");
+            ids.iter().any(|id| *id == element.id || id == name)
+        });
+        present == allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iana::Origin;
+    use std::thread;
+    use {Rfc5424Builder, StructuredData};
+
+    struct Event {
+        severity: Severity,
+        msg_id: Option<&'static str>,
+        sd_id: Option<&'static str>,
+    }
+
+    impl Event {
+        fn new(severity: Severity) -> Event {
+            Event {
+                severity,
+                msg_id: None,
+                sd_id: None,
+            }
+        }
+    }
+
+    impl Rfc5424Data for Event {
+        fn severity(&self) -> Severity {
+            self.severity
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            self.sd_id
+                .map(|id| vec![(id, vec![("a".to_string(), "1".to_string())])])
+                .map(|sd| sd.into_iter().collect())
+        }
+
+        fn message(&self) -> Option<Message> {
+            None
+        }
+
+        fn msg_id(&self) -> Option<MessageId> {
+            self.msg_id.map(|id| MessageId::new(id).unwrap())
+        }
+    }
+
+    fn formatter() -> Rfc5424 {
+        Rfc5424Builder::new("32473", Facility::Local0).build()
+    }
+
+    #[test]
+    fn severity() {
+        let f = formatter();
+        let filter = Filter::new();
+        assert!(filter.allows(&f, &Event::new(Severity::Debug)));
+
+        filter.set_min_severity(Severity::Warning);
+        assert!(filter.allows(&f, &Event::new(Severity::Error)));
+        assert!(filter.allows(&f, &Event::new(Severity::Warning)));
+        assert!(!filter.allows(&f, &Event::new(Severity::Notice)));
+
+        // a clone changes the same rules, even from another thread
+        let handle = filter.clone();
+        thread::spawn(move || handle.set_min_severity(Severity::Debug))
+            .join()
+            .unwrap();
+        assert!(filter.allows(&f, &Event::new(Severity::Notice)));
+    }
+
+    #[test]
+    fn facility_and_msg_id() {
+        let filter = Filter::new();
+        let local0 = formatter();
+        let local1 = Rfc5424Builder::new("", Facility::Local1).build();
+        let event = Event::new(Severity::Error);
+
+        filter.set_facilities(Rule::Allow(vec![Facility::Local1]));
+        assert!(!filter.allows(&local0, &event));
+        assert!(filter.allows(&local1, &event));
+        filter.set_facilities(Rule::Deny(vec![Facility::Local1]));
+        assert!(filter.allows(&local0, &event));
+        assert!(!filter.allows(&local1, &event));
+        filter.set_facilities(Rule::Any);
+
+        filter.set_msg_ids(Rule::Deny(vec!["HEALTHCHECK".into()]));
+        let mut health = Event::new(Severity::Error);
+        health.msg_id = Some("HEALTHCHECK");
+        assert!(!filter.allows(&local0, &health));
+        assert!(filter.allows(&local0, &event));
+
+        filter.set_msg_ids(Rule::Allow(vec!["-".into()]));
+        assert!(filter.allows(&local0, &event));
+        assert!(!filter.allows(&local0, &health));
+    }
+
+    #[test]
+    fn sd_ids() {
+        let filter = Filter::new();
+        let f = formatter();
+        let mut audit = Event::new(Severity::Error);
+        audit.sd_id = Some("audit");
+        let plain = Event::new(Severity::Error);
+
+        filter.set_sd_ids(Rule::Allow(vec!["audit".into()]));
+        assert!(filter.allows(&f, &audit));
+        assert!(!filter.allows(&f, &plain));
+
+        filter.set_sd_ids(Rule::Deny(vec!["audit@32473".into()]));
+        assert!(!filter.allows(&f, &audit));
+        assert!(filter.allows(&f, &plain));
+
+        let with_origin = Rfc5424Builder::new("32473", Facility::Local0)
+            .origin(Origin::Software("app".into()))
+            .build();
+        filter.set_sd_ids(Rule::Allow(vec!["origin".into()]));
+        assert!(filter.allows(&with_origin, &plain));
+        assert!(!filter.allows(&f, &plain));
+    }
+}
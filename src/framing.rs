@@ -0,0 +1,399 @@
+//! Splitting a stream of bytes back into syslog messages.
+//!
+//! [`FrameDecoder`](struct.FrameDecoder.html) works incrementally on a byte buffer,
+//! in the style of `tokio_util::codec::Decoder`, and
+//! [`FrameReader`](struct.FrameReader.html) uses it to read frames from any
+//! `io::Read`.
+//!
+//! The framing is detected for every frame: a frame starting with a digit is
+//! octet counted (`MSG-LEN SP SYSLOG-MSG`, written by `WriteFormat::RFC5425`),
+//! and a frame starting with `<` is read up to the next LF (written by
+//! `WriteFormat::RFC6587`). See
+//! [RFC6587 Section 3.4](https://tools.ietf.org/html/rfc6587#section-3.4).
+//!
+//! # Examples
+//! ```ignore
+//! let stream = TcpStream::connect("127.0.0.1:601")?;
+//! for frame in FrameReader::new(stream, 8192) {
+//!     match frame {
+//!         Ok(frame) => println!("{:?}", parser::parse_any(&frame)),
+//!         Err(FrameError::Io(e)) => return Err(e),
+//!         Err(e) => eprintln!("skipped bad data: {:?}", e),
+//!     }
+//! }
+//! ```
+
+use std::io::{self, Read};
+use std::mem;
+
+/// Longest MSG-LEN accepted, in digits
+const MAX_LEN_DIGITS: usize = 10;
+
+/// Errors returned while decoding frames.
+///
+/// Only `Io` ends a [`FrameReader`](struct.FrameReader.html). After the other
+/// errors the bad data has been skipped, and decoding can continue.
+#[derive(Debug)]
+pub enum FrameError {
+    /// Reading from the underlying reader failed
+    Io(io::Error),
+    /// A frame of this many bytes was larger than the maximum, and is skipped
+    TooLong(usize),
+    /// This many bytes couldn't be the start of a frame, and were skipped to get
+    /// back in sync with the stream
+    Garbage(usize),
+    /// The stream ended partway through a frame, leaving this many bytes undecoded
+    Incomplete(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// At the start of a frame
+    Start,
+    /// Dropping the rest of an oversized octet counted frame
+    SkipBytes(usize),
+    /// Dropping the rest of an oversized LF terminated frame
+    SkipLine,
+}
+
+/// Incremental decoder for octet counted and LF terminated frames.
+/// See the [module documentation](index.html).
+#[derive(Debug, Clone, Copy)]
+pub struct FrameDecoder {
+    max_frame_len: usize,
+    state: State,
+}
+
+impl FrameDecoder {
+    /// Create a decoder that skips frames larger than `max_frame_len` bytes.
+    pub fn new(max_frame_len: usize) -> FrameDecoder {
+        FrameDecoder {
+            max_frame_len,
+            state: State::Start,
+        }
+    }
+
+    /// Remove the next whole frame from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` when `buf` doesn't hold a whole frame yet. Append more
+    /// data to `buf` and call this again.
+    ///
+    /// # Errors
+    /// * `FrameError::TooLong` when a frame is larger than the maximum
+    /// * `FrameError::Garbage` when data was skipped to find the start of a frame
+    pub fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FrameError> {
+        match self.state {
+            State::Start => {}
+            State::SkipBytes(n) => {
+                let skip = n.min(buf.len());
+                buf.drain(..skip);
+                if skip < n {
+                    self.state = State::SkipBytes(n - skip);
+                    return Ok(None);
+                }
+                self.state = State::Start;
+            }
+            State::SkipLine => match buf.iter().position(|b| *b == b'\n') {
+                Some(lf) => {
+                    buf.drain(..=lf);
+                    self.state = State::Start;
+                }
+                None => {
+                    buf.clear();
+                    return Ok(None);
+                }
+            },
+        }
+
+        // blank lines between frames are allowed
+        let blank = buf.iter().take_while(|b| **b == b'\n').count();
+        buf.drain(..blank);
+
+        match buf.first() {
+            None => Ok(None),
+            Some(b'1'..=b'9') => self.decode_octet_counted(buf),
+            Some(b'<') => self.decode_line(buf),
+            Some(_) => Err(resync(buf)),
+        }
+    }
+
+    /// Like [`decode`](#method.decode), but for when no more data will be added to
+    /// `buf`. A last frame that isn't followed by a LF is returned as is.
+    ///
+    /// # Errors
+    /// As `decode`, and `FrameError::Incomplete` when `buf` ends partway
+    /// through a frame. `buf` is empty afterwards.
+    pub fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FrameError> {
+        if let Some(frame) = self.decode(buf)? {
+            return Ok(Some(frame));
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        let state = self.state;
+        self.state = State::Start;
+        let rest = mem::take(buf);
+        if state == State::Start && rest[0] == b'<' {
+            Ok(Some(rest))
+        } else {
+            Err(FrameError::Incomplete(rest.len()))
+        }
+    }
+
+    fn decode_octet_counted(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FrameError> {
+        let digits = buf.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits > MAX_LEN_DIGITS || (digits < buf.len() && buf[digits] != b' ') {
+            return Err(resync(buf));
+        }
+        if digits == buf.len() {
+            // MSG-LEN isn't complete yet
+            return Ok(None);
+        }
+
+        let len: usize = match String::from_utf8_lossy(&buf[..digits]).parse() {
+            Ok(len) => len,
+            Err(_) => return Err(resync(buf)),
+        };
+        if len > self.max_frame_len {
+            buf.drain(..=digits);
+            self.state = State::SkipBytes(len);
+            return Err(FrameError::TooLong(len));
+        }
+        if buf.len() < digits + 1 + len {
+            return Ok(None);
+        }
+
+        let frame = buf[digits + 1..digits + 1 + len].to_vec();
+        buf.drain(..digits + 1 + len);
+        Ok(Some(frame))
+    }
+
+    fn decode_line(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FrameError> {
+        match buf.iter().position(|b| *b == b'\n') {
+            Some(lf) if lf > self.max_frame_len => {
+                buf.drain(..=lf);
+                Err(FrameError::TooLong(lf))
+            }
+            Some(lf) => {
+                let frame = buf[..lf].to_vec();
+                buf.drain(..=lf);
+                Ok(Some(frame))
+            }
+            None if buf.len() > self.max_frame_len => {
+                let len = buf.len();
+                buf.clear();
+                self.state = State::SkipLine;
+                Err(FrameError::TooLong(len))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Drop bytes from the front of `buf` up to the next place a frame could start:
+/// the start of a line, or what looks like an octet count followed by a PRI.
+fn resync(buf: &mut Vec<u8>) -> FrameError {
+    let start = (1..buf.len())
+        .find(|i| {
+            buf[i - 1] == b'\n' || (!buf[i - 1].is_ascii_digit() && looks_octet_counted(&buf[*i..]))
+        })
+        .unwrap_or(buf.len());
+    buf.drain(..start);
+    FrameError::Garbage(start)
+}
+
+/// Check if `bytes` starts with `MSG-LEN SP <`, or could once more data arrives.
+fn looks_octet_counted(bytes: &[u8]) -> bool {
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 || bytes[0] == b'0' || digits > MAX_LEN_DIGITS {
+        return false;
+    }
+    matches!(&bytes[digits..], [] | [b' '] | [b' ', b'<', ..])
+}
+
+/// Reads frames from an `io::Read` with a [`FrameDecoder`](struct.FrameDecoder.html).
+///
+/// Iterating yields every frame, along with the errors of the decoder. Iteration
+/// ends at the end of the stream, or after the first `FrameError::Io`.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    decoder: FrameDecoder,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Read frames from `reader`, skipping frames larger than `max_frame_len` bytes.
+    pub fn new(reader: R, max_frame_len: usize) -> FrameReader<R> {
+        FrameReader {
+            reader,
+            decoder: FrameDecoder::new(max_frame_len),
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Get back the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<Vec<u8>, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = [0; 8192];
+        loop {
+            match self.decoder.decode(&mut self.buf) {
+                Ok(Some(frame)) => return Some(Ok(frame)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    return self.decoder.decode_eof(&mut self.buf).transpose();
+                }
+                Ok(n) => self.buf.extend(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(FrameError::Io(e)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut FrameDecoder, buf: &mut Vec<u8>) -> Vec<Result<Vec<u8>, String>> {
+        let mut out = Vec::new();
+        loop {
+            match decoder.decode(buf) {
+                Ok(Some(frame)) => out.push(Ok(frame)),
+                Ok(None) => return out,
+                Err(e) => out.push(Err(format!("{:?}", e))),
+            }
+        }
+    }
+
+    fn frame(s: &str) -> Result<Vec<u8>, String> {
+        Ok(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn mixed_framing() {
+        let mut buf = b"5 <1>1 <2>2\n\n7 <3>3\nx\n<4>4".to_vec();
+        let mut decoder = FrameDecoder::new(100);
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![frame("<1>1 "), frame("<2>2"), frame("<3>3\nx\n")]
+        );
+        assert_eq!(buf, b"<4>4");
+        assert_eq!(
+            decoder.decode_eof(&mut buf).unwrap(),
+            Some(b"<4>4".to_vec())
+        );
+    }
+
+    #[test]
+    fn partial_reads() {
+        let input = b"11 <34>1 hello<13>world\n";
+        let mut decoder = FrameDecoder::new(100);
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        for b in input.iter() {
+            buf.push(*b);
+            frames.extend(decode_all(&mut decoder, &mut buf));
+        }
+        assert_eq!(frames, vec![frame("<34>1 hello"), frame("<13>world")]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn too_long() {
+        let mut decoder = FrameDecoder::new(5);
+        let mut buf = b"6 <1>1 ".to_vec();
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![Err("TooLong(6)".into())]
+        );
+        // the rest of the oversized frame is dropped as it arrives
+        buf.extend(b"x5 <2>2 ");
+        assert_eq!(decode_all(&mut decoder, &mut buf), vec![frame("<2>2 ")]);
+
+        buf.extend(b"<1>1 too long");
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![Err("TooLong(13)".into())]
+        );
+        buf.extend(b" still going\n<2>2\n");
+        assert_eq!(decode_all(&mut decoder, &mut buf), vec![frame("<2>2")]);
+    }
+
+    #[test]
+    fn resyncs_on_garbage() {
+        let mut decoder = FrameDecoder::new(100);
+        let mut buf = b"junk 12 junk 5 <1>1 ".to_vec();
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![Err("Garbage(13)".into()), frame("<1>1 ")]
+        );
+
+        let mut buf = b"junk\n<1>1\n".to_vec();
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![Err("Garbage(5)".into()), frame("<1>1")]
+        );
+
+        let mut buf = b"123x <1>1 hello".to_vec();
+        assert_eq!(
+            decode_all(&mut decoder, &mut buf),
+            vec![Err("Garbage(15)".into())]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn reader() {
+        /// Reader returning a few bytes at a time
+        struct Trickle<'a>(&'a [u8]);
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(3);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let frames: Vec<_> = FrameReader::new(Trickle(b"11 <34>1 hello<13>a\n<13>last"), 100)
+            .map(|f| f.unwrap())
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                b"<34>1 hello".to_vec(),
+                b"<13>a".to_vec(),
+                b"<13>last".to_vec()
+            ]
+        );
+
+        let mut reader = FrameReader::new(Trickle(b"11 <34>1 hel"), 100);
+        match reader.next() {
+            Some(Err(FrameError::Incomplete(12))) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+}
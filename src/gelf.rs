@@ -0,0 +1,519 @@
+//! GELF 1.1, the Graylog Extended Log Format.
+//!
+//! [`document`](fn.document.html) converts `Rfc5424Data` into a GELF JSON
+//! document, taking the header values a [`Rfc5424`](../struct.Rfc5424.html)
+//! formatter would write: `host` is the HOSTNAME, `level` the severity, and
+//! every PARAM of the structured data becomes an additional `_sdid_param` field.
+//!
+//! [`UdpSink`](struct.UdpSink.html) sends documents to a GELF UDP input,
+//! splitting them into chunks when they don't fit in one datagram. With the
+//! `compression` feature it can also compress them with gzip or zlib.
+//!
+//! # Examples
+//! ```ignore
+//! let socket = UdpSocket::bind("0.0.0.0:0")?;
+//! socket.connect(("graylog.example.com", gelf::DEFAULT_PORT))?;
+//! let mut sink = UdpSink::new(formatter, socket);
+//! sink.send(&my_message)?;
+//! ```
+
+use std::collections::HashSet;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "compression")]
+use flate2;
+#[cfg(feature = "compression")]
+use flate2::write::{GzEncoder, ZlibEncoder};
+#[cfg(feature = "compression")]
+use std::io::Write;
+
+use parser::valid_timestamp;
+use rfc3164::days_from_civil;
+use types::*;
+use {Rfc5424, Rfc5424Data, NILVALUE};
+
+/// Port of Graylog's GELF inputs
+pub const DEFAULT_PORT: u16 = 12201;
+
+/// Most chunks a document may be split into
+pub const MAX_CHUNKS: usize = 128;
+
+/// Magic bytes, message id, sequence number and sequence count
+const CHUNK_HEADER_LEN: usize = 12;
+
+/// The GELF document of `data`, with the header values `formatter` would write.
+///
+/// The first line of the MSG is the `short_message`, and the whole MSG is the
+/// `full_message` when it has more than one line. The APP-NAME, PROCID, MSGID
+/// and facility are written as `_app_name`, `_procid`, `_msgid` and
+/// `_facility`, unless they are the NILVALUE. The `timestamp` is left out when
+/// the message has none, in which case Graylog uses the time it was received.
+///
+/// Characters of field names other than ASCII letters, digits, `'_'`, `'.'` and
+/// `'-'` are replaced with `'_'`. Field names of PARAMs that would repeat another
+/// field get a `_sd` prefix: the PARAM `name` of the SD-ID `app` is written as
+/// `_sd_app_name`, since `_app_name` is the APP-NAME.
+pub fn document(formatter: &Rfc5424, data: &impl Rfc5424Data) -> String {
+    let header = formatter.header(data);
+    let text = match data.message() {
+        Some(Message::Text(text)) => text,
+        Some(Message::Binary(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+        None => String::new(),
+    };
+    let short_message = text
+        .lines()
+        .next()
+        .filter(|line| !line.is_empty())
+        .unwrap_or("-");
+
+    let mut json = String::from("{\"version\":\"1.1\"");
+    string_field(&mut json, "host", &header.hostname.0);
+    string_field(&mut json, "short_message", short_message);
+    if text.trim_end().contains('\n') {
+        string_field(&mut json, "full_message", &text);
+    }
+    if let Some(timestamp) = data.timestamp().as_deref().and_then(epoch) {
+        let _ = write!(json, ",\"timestamp\":{}", timestamp);
+    }
+    let _ = write!(json, ",\"level\":{}", data.severity() as u8);
+    string_field(&mut json, "_facility", header.facility.keyword());
+    let nil = NILVALUE.to_string();
+    let fields = [
+        ("_app_name", &header.app_name.0),
+        ("_procid", &header.pid.0),
+        ("_msgid", &header.msg_id.0),
+    ];
+    for &(name, value) in &fields {
+        if *value != nil {
+            string_field(&mut json, name, value);
+        }
+    }
+    // the names are taken even when their value is NILVALUE, so the name of a
+    // PARAM doesn't depend on the header
+    let mut keys: HashSet<String> = fields.iter().map(|(name, _)| name.to_string()).collect();
+    keys.insert("_facility".into());
+
+    let mut elements: Vec<_> = data.structured_data().into_iter().flatten().collect();
+    elements.sort_by_key(|&(id, _)| id);
+    for (id, params) in elements {
+        for (name, value) in params {
+            let mut key: String = format!("_{}_{}", id, name)
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+                    _ => '_',
+                })
+                .collect();
+            while !keys.insert(key.clone()) {
+                key.insert_str(0, "_sd");
+            }
+            string_field(&mut json, &key, &value);
+        }
+    }
+    json.push('}');
+    json
+}
+
+fn string_field(json: &mut String, name: &str, value: &str) {
+    json.push(',');
+    json_string(json, name);
+    json.push(':');
+    json_string(json, value);
+}
+
+fn json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Seconds since the UNIX epoch of a RFC5424 TIMESTAMP, with milliseconds when
+/// it has a fraction.
+fn epoch(timestamp: &str) -> Option<String> {
+    if !valid_timestamp(timestamp.as_bytes()) {
+        return None;
+    }
+    let number = |range: ::std::ops::Range<usize>| timestamp.get(range)?.parse::<i64>().ok();
+    let days = days_from_civil(number(0..4)?, number(5..7)? as u32, number(8..10)? as u32);
+    let mut secs = days * 86_400 + number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+
+    let mut rest = &timestamp[19..];
+    let mut millis = None;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        let mut ms = fraction[..digits].to_string();
+        ms.truncate(3);
+        while ms.len() < 3 {
+            ms.push('0');
+        }
+        millis = Some(ms.parse::<i64>().ok()?);
+        rest = &fraction[digits..];
+    }
+    if rest != "Z" {
+        let sign = match rest.get(..1)? {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        let hours: i64 = rest.get(1..3)?.parse().ok()?;
+        let minutes: i64 = rest.get(4..6)?.parse().ok()?;
+        secs -= sign * (hours * 3600 + minutes * 60);
+    }
+    Some(match millis {
+        // The fraction counts forward even before the epoch, so the sign goes
+        // on the sum rather than on the seconds alone.
+        Some(millis) => {
+            let total = secs * 1000 + millis;
+            let sign = if total < 0 { "-" } else { "" };
+            format!("{}{}.{:03}", sign, total.abs() / 1000, total.abs() % 1000)
+        }
+        None => secs.to_string(),
+    })
+}
+
+/// Compression of the documents sent by a [`UdpSink`](struct.UdpSink.html).
+/// Graylog detects it on its own.
+/// Gzip and zlib need the `compression` feature: without it, sending with them
+/// fails with `ErrorKind::Unsupported`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zlib,
+}
+
+impl Compression {
+    fn apply(self, document: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(document.to_vec()),
+            #[cfg(feature = "compression")]
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(document)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "compression")]
+            Compression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(document)?;
+                encoder.finish()
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Gzip | Compression::Zlib => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compression needs the `compression` feature",
+            )),
+        }
+    }
+}
+
+/// Sends GELF documents over UDP. See the [module documentation](index.html).
+///
+/// Documents larger than the chunk size are split into chunks sharing a message
+/// id, as many as [`MAX_CHUNKS`](constant.MAX_CHUNKS.html). Message ids count up
+/// from a value drawn from the clock and the process id.
+#[derive(Debug)]
+pub struct UdpSink {
+    formatter: Rfc5424,
+    socket: UdpSocket,
+    compression: Compression,
+    chunk_size: usize,
+    next_id: u64,
+}
+
+impl UdpSink {
+    /// `socket` must be connected to the GELF input, on port
+    /// [`DEFAULT_PORT`](constant.DEFAULT_PORT.html) by default.
+    pub fn new(formatter: Rfc5424, socket: UdpSocket) -> UdpSink {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        UdpSink {
+            formatter,
+            socket,
+            compression: Compression::None,
+            chunk_size: 1420,
+            next_id: seed ^ u64::from(::std::process::id()).rotate_left(32),
+        }
+    }
+
+    pub fn compression(mut self, compression: Compression) -> UdpSink {
+        self.compression = compression;
+        self
+    }
+
+    /// Size of the largest datagram sent, chunk header included. 1420 bytes by
+    /// default, which fits most networks; Graylog recommends 8154 on LANs.
+    pub fn chunk_size(mut self, chunk_size: usize) -> UdpSink {
+        self.chunk_size = chunk_size.max(CHUNK_HEADER_LEN + 1);
+        self
+    }
+
+    pub fn formatter(&self) -> &Rfc5424 {
+        &self.formatter
+    }
+
+    /// Convert `data` to a GELF [`document`](fn.document.html) and send it.
+    ///
+    /// # Errors
+    /// Errors when sending fails, or when the document needs more than
+    /// `MAX_CHUNKS` chunks.
+    pub fn send(&mut self, data: &impl Rfc5424Data) -> io::Result<()> {
+        let document = document(&self.formatter, data);
+        self.send_document(document.as_bytes())
+    }
+
+    /// Compress `document` and send it, in chunks if needed.
+    ///
+    /// # Errors
+    /// Errors when sending fails, or when the document needs more than
+    /// `MAX_CHUNKS` chunks.
+    pub fn send_document(&mut self, document: &[u8]) -> io::Result<()> {
+        let payload = self.compression.apply(document)?;
+        if payload.len() <= self.chunk_size {
+            return self.socket.send(&payload).map(|_| ());
+        }
+
+        let chunk_len = self.chunk_size - CHUNK_HEADER_LEN;
+        let count = payload.len().div_ceil(chunk_len);
+        if count > MAX_CHUNKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "GELF document needs {} chunks, more than {}",
+                    count, MAX_CHUNKS
+                ),
+            ));
+        }
+        let id = self.next_id.to_be_bytes();
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut datagram = Vec::with_capacity(self.chunk_size);
+        for (i, chunk) in payload.chunks(chunk_len).enumerate() {
+            datagram.clear();
+            datagram.extend_from_slice(&[0x1e, 0x0f]);
+            datagram.extend_from_slice(&id);
+            datagram.extend_from_slice(&[i as u8, count as u8]);
+            datagram.extend_from_slice(chunk);
+            self.socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use {Rfc5424Builder, StructuredData};
+
+    struct Event {
+        text: &'static str,
+        timestamp: Option<&'static str>,
+    }
+
+    impl Rfc5424Data for Event {
+        fn severity(&self) -> Severity {
+            Severity::Error
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            self.timestamp.map(str::to_string)
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            let mut sd: StructuredData = HashMap::new();
+            sd.insert("req@32473", vec![("path".into(), "/a\"b".into())]);
+            sd.insert("auth", vec![("user id".into(), "alice".into())]);
+            Some(sd)
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.text.to_string()))
+        }
+    }
+
+    fn formatter() -> Rfc5424 {
+        Rfc5424Builder::new("32473", Facility::Local0)
+            .hostname("web-1")
+            .unwrap()
+            .app_name("my_app")
+            .unwrap()
+            .build()
+    }
+
+    #[test]
+    fn documents() {
+        let event = Event {
+            text: "request failed",
+            timestamp: Some("2024-01-01T00:00:01.5+01:00"),
+        };
+        assert_eq!(
+            document(&formatter(), &event),
+            "{\"version\":\"1.1\",\"host\":\"web-1\",\"short_message\":\"request failed\",\
+             \"timestamp\":1704063601.500,\"level\":3,\"_facility\":\"local0\",\
+             \"_app_name\":\"my_app\",\"_auth_user_id\":\"alice\",\"_req_32473_path\":\"/a\\\"b\"}"
+        );
+
+        let event = Event {
+            text: "first\nsecond\u{1}",
+            timestamp: None,
+        };
+        let json = document(&formatter(), &event);
+        assert!(json.contains("\"short_message\":\"first\""));
+        assert!(json.contains("\"full_message\":\"first\\nsecond\\u0001\""));
+        assert!(!json.contains("timestamp"));
+    }
+
+    #[test]
+    fn sd_keys_are_unique() {
+        struct Clashing;
+
+        impl Rfc5424Data for Clashing {
+            fn severity(&self) -> Severity {
+                Severity::Error
+            }
+
+            fn timestamp(&self) -> Option<String> {
+                None
+            }
+
+            fn structured_data(&self) -> Option<StructuredData<'_>> {
+                let mut sd: StructuredData = HashMap::new();
+                sd.insert("app", vec![("name".into(), "sd".into())]);
+                sd.insert("sd_app", vec![("name".into(), "sd again".into())]);
+                Some(sd)
+            }
+
+            fn message(&self) -> Option<Message> {
+                None
+            }
+        }
+
+        let json = document(&formatter(), &Clashing);
+        assert_eq!(
+            json,
+            "{\"version\":\"1.1\",\"host\":\"web-1\",\"short_message\":\"-\",\
+             \"level\":3,\"_facility\":\"local0\",\"_app_name\":\"my_app\",\
+             \"_sd_app_name\":\"sd\",\"_sd_sd_app_name\":\"sd again\"}"
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(epoch("1970-01-01T00:00:00Z"), Some("0".to_string()));
+        assert_eq!(
+            epoch("2024-01-01T00:00:00.123456-00:30"),
+            Some("1704069000.123".to_string())
+        );
+        assert_eq!(epoch("2024-01-01"), None);
+        assert_eq!(epoch("1969-12-31T23:59:59.5Z"), Some("-0.500".to_string()));
+        assert_eq!(
+            epoch("1969-12-31T23:59:58.250Z"),
+            Some("-1.750".to_string())
+        );
+    }
+
+    fn sink() -> (UdpSink, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(receiver.local_addr().unwrap()).unwrap();
+        (UdpSink::new(formatter(), socket), receiver)
+    }
+
+    /// Receive one document, putting its chunks back together
+    fn receive(receiver: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0; 2048];
+        let mut chunks: Vec<Option<Vec<u8>>> = Vec::new();
+        loop {
+            let n = receiver.recv(&mut buf).unwrap();
+            if buf[..2] != [0x1e, 0x0f] {
+                return buf[..n].to_vec();
+            }
+            let (seq, count) = (buf[10] as usize, buf[11] as usize);
+            chunks.resize(count, None);
+            chunks[seq] = Some(buf[CHUNK_HEADER_LEN..n].to_vec());
+            if chunks.iter().all(Option::is_some) {
+                return chunks.into_iter().flatten().flatten().collect();
+            }
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let (sink, receiver) = sink();
+        let mut sink = sink.chunk_size(100);
+        let long = "x".repeat(1000);
+        sink.send_document(long.as_bytes()).unwrap();
+        assert_eq!(receive(&receiver), long.as_bytes());
+
+        sink.send_document(b"{}").unwrap();
+        assert_eq!(receive(&receiver), b"{}");
+
+        let too_long = "x".repeat(88 * MAX_CHUNKS + 1);
+        assert!(sink.send_document(too_long.as_bytes()).is_err());
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn compression_unsupported() {
+        let event = Event {
+            text: "compressed",
+            timestamp: None,
+        };
+        let (sink, _receiver) = sink();
+        let mut sink = sink.compression(Compression::Gzip);
+        let error = sink.send(&event).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression() {
+        use flate2::read::{GzDecoder, ZlibDecoder};
+        use std::io::Read;
+
+        let event = Event {
+            text: "compressed",
+            timestamp: None,
+        };
+        let expected = document(&formatter(), &event);
+        let (sink, receiver) = sink();
+        let mut sink = sink.compression(Compression::Gzip);
+        sink.send(&event).unwrap();
+        let mut json = String::new();
+        GzDecoder::new(&receive(&receiver)[..])
+            .read_to_string(&mut json)
+            .unwrap();
+        assert_eq!(json, expected);
+
+        let mut sink = sink.compression(Compression::Zlib).chunk_size(20);
+        sink.send(&event).unwrap();
+        let mut json = String::new();
+        ZlibDecoder::new(&receive(&receiver)[..])
+            .read_to_string(&mut json)
+            .unwrap();
+        assert_eq!(json, expected);
+    }
+}
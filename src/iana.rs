@@ -2,6 +2,26 @@
 
 use std::fmt;
 
+use types::Severity;
+
+/// SD-IDs registered with IANA
+/// ([RFC5424 Section 9.2](https://tools.ietf.org/html/rfc5424#section-9.2)).
+/// These are written without the `@enterprise_id` suffix.
+pub const RESERVED_SD_IDS: [&str; 7] = [
+    "timeQuality",
+    "origin",
+    "meta",
+    "ssign",
+    "ssign-cert",
+    "alarm",
+    "snmp",
+];
+
+/// Check if `sd_id` is one of the [`RESERVED_SD_IDS`](constant.RESERVED_SD_IDS.html)
+pub fn is_reserved(sd_id: &str) -> bool {
+    RESERVED_SD_IDS.contains(&sd_id)
+}
+
 /// The SD-ID "origin" MAY be used to indicate the origin of a syslog
 /// message.  The following parameters can be used.  All parameters are
 /// OPTIONAL.
@@ -11,10 +31,11 @@ use std::fmt;
 /// 
 /// Notes: 
 /// * Documentation text copied from: 
-/// [RFC5424 Section 7.2](https://tools.ietf.org/html/rfc5424#section-7.2)
+///   [RFC5424 Section 7.2](https://tools.ietf.org/html/rfc5424#section-7.2)
 /// * No automatic bounds checking is currently done for these fields. 
-/// Check the documentation for the length limits.
-#[derive(Debug)]
+///   Check the documentation for the length limits.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Origin {
     /// The "ip" parameter denotes an IP address that the originator knows it
     /// had at the time of originating the message.  It MUST contain the
@@ -30,6 +51,7 @@ pub enum Origin {
     /// If an originator has multiple IP addresses, it MAY either list one of
     /// its IP addresses in the "ip" parameter or it MAY include multiple
     /// "ip" parameters in a single "origin" structured data element.
+    #[cfg_attr(feature = "serde", serde(rename = "ip"))]
     Ip(String),
     /// The "enterpriseId" parameter MUST be a 'SMI Network Management
     /// Private Enterprise Code', maintained by IANA, whose prefix is
@@ -53,6 +75,7 @@ pub enum Origin {
     /// 
     /// By specifying a private enterprise number, the vendor allows more
     /// specific processing of the message.
+    #[cfg_attr(feature = "serde", serde(rename = "enterpriseId"))]
     EnterpriseId(String),
     /// The "software" parameter uniquely identifies the software that
     /// generated the message.  If it is used, "enterpriseId" SHOULD also be
@@ -64,6 +87,7 @@ pub enum Origin {
     /// 
     /// The "software" parameter is a string.  It MUST NOT be longer than 48
     /// characters.
+    #[cfg_attr(feature = "serde", serde(rename = "software"))]
     Software(String),
     /// The "swVersion" parameter uniquely identifies the version of the
     /// software that generated the message.  If it is used, the "software"
@@ -71,19 +95,28 @@ pub enum Origin {
     /// 
     /// The "swVersion" parameter is a string.  It MUST NOT be longer than 32
     /// characters.
+    #[cfg_attr(feature = "serde", serde(rename = "swVersion"))]
     Version(String),
 }
 
-impl fmt::Display for Origin {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Origin {
+    /// The PARAM-NAME and PARAM-VALUE of this parameter
+    pub fn param(&self) -> (String, String) {
         use self::Origin::*;
-        let val = match self {
-            Ip(s) => format!(r#" ip="{}""#, s),
-            EnterpriseId(s) => format!(r#" enterpriseId="{}""#, s),
-            Software(s) => format!(r#" software="{}""#, s),
-            Version(s) => format!(r#" swVersion="{}""#, s),
+        let (name, val) = match self {
+            Ip(s) => ("ip", s),
+            EnterpriseId(s) => ("enterpriseId", s),
+            Software(s) => ("software", s),
+            Version(s) => ("swVersion", s),
         };
-        write!(f, "{}", val)
+        (name.to_string(), val.to_string())
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, val) = self.param();
+        write!(f, r#" {}="{}""#, name, val)
     }
 }
 
@@ -99,20 +132,23 @@ impl fmt::Display for Origin {
 /// 
 /// Notes: 
 /// * Documentation text copied from: 
-/// [RFC5424 Section 7.1](https://tools.ietf.org/html/rfc5424#section-7.1)
+///   [RFC5424 Section 7.1](https://tools.ietf.org/html/rfc5424#section-7.1)
 /// * No automatic bounds checking is currently done for these fields. 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TimeQuality {
     /// The "tzKnown" parameter indicates whether the originator knows its
     /// time zone.  If it does, the value "1" MUST be used.  If the time zone
     /// information is in doubt, the value "0" MUST be used.  If the
     /// originator knows its time zone but decides to emit time in UTC, the
     /// value "1" MUST be used (because the time zone is known).
+    #[cfg_attr(feature = "serde", serde(rename = "tzKnown"))]
     TzKnown(bool),
     /// The "isSynced" parameter indicates whether the originator is
     /// synchronized to a reliable external time source, e.g., via NTP.  If
     /// the originator is time synchronized, the value "1" MUST be used.  If
     /// not, the value "0" MUST be used.
+    #[cfg_attr(feature = "serde", serde(rename = "isSynced"))]
     IsSynced(bool),
     /// The "syncAccuracy" parameter indicates how accurate the originator
     /// thinks its time synchronization is.  It is an integer describing the
@@ -127,25 +163,373 @@ pub enum TimeQuality {
     /// only if the originator actually has knowledge of the reliability of
     /// the external time source.  In most cases, it will gain this in-depth
     /// knowledge through operator configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "syncAccuracy"))]
     SyncAccuracy(u32),
 }
 
+impl TimeQuality {
+    /// The PARAM-NAME and PARAM-VALUE of this parameter
+    pub fn param(&self) -> (String, String) {
+        use self::TimeQuality::*;
+        let bool_val = |b: bool| if b { "1" } else { "0" }.to_string();
+        match self {
+            TzKnown(b) => ("tzKnown".to_string(), bool_val(*b)),
+            IsSynced(b) => ("isSynced".to_string(), bool_val(*b)),
+            SyncAccuracy(s) => ("syncAccuracy".to_string(), s.to_string()),
+        }
+    }
+}
+
 impl fmt::Display for TimeQuality {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use self::TimeQuality::*;
-        let val = match self {
-            TzKnown(s) => if *s {
-                r#" tzKnown="1""#.to_string()
-            } else {
-                r#" tz_known="0""#.to_string()
-            },
-            IsSynced(s) => if *s {
-                r#" isSynced="1""#.to_string()
-            } else {
-                r#" isSynced="0""#.to_string()
-            },
-            SyncAccuracy(s) => format!(r#" syncAccuracy="{}""#, s),
+        let (name, val) = self.param();
+        write!(f, r#" {}="{}""#, name, val)
+    }
+}
+
+/// The ITU perceived severity of an [`Alarm`](struct.Alarm.html)
+/// ([RFC5674 Section 3.2](https://tools.ietf.org/html/rfc5674#section-3.2)).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum PerceivedSeverity {
+    Cleared,
+    Indeterminate,
+    Critical,
+    Major,
+    Minor,
+    Warning,
+}
+
+impl PerceivedSeverity {
+    pub fn all() -> &'static [PerceivedSeverity] {
+        use self::PerceivedSeverity::*;
+        &[Cleared, Indeterminate, Critical, Major, Minor, Warning]
+    }
+
+    /// The value with this keyword
+    pub fn from_keyword(keyword: &str) -> Option<PerceivedSeverity> {
+        PerceivedSeverity::all()
+            .iter()
+            .cloned()
+            .find(|v| v.keyword() == keyword)
+    }
+
+    pub fn keyword(self) -> &'static str {
+        use self::PerceivedSeverity::*;
+        match self {
+            Cleared => "cleared",
+            Indeterminate => "indeterminate",
+            Critical => "critical",
+            Major => "major",
+            Minor => "minor",
+            Warning => "warning",
+        }
+    }
+
+    /// The syslog severity of alarms with this perceived severity, as mapped by
+    /// [RFC5674 Section 3.3](https://tools.ietf.org/html/rfc5674#section-3.3)
+    pub fn severity(self) -> Severity {
+        use self::PerceivedSeverity::*;
+        match self {
+            Critical => Severity::Alert,
+            Major => Severity::Critical,
+            Minor => Severity::Error,
+            Warning => Severity::Warning,
+            Indeterminate | Cleared => Severity::Notice,
+        }
+    }
+}
+
+impl From<PerceivedSeverity> for Severity {
+    fn from(severity: PerceivedSeverity) -> Severity {
+        severity.severity()
+    }
+}
+
+/// The ITU event type of an [`Alarm`](struct.Alarm.html), as in the
+/// IANAItuEventType textual convention
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+pub enum EventType {
+    Other,
+    CommunicationsAlarm,
+    QualityOfServiceAlarm,
+    ProcessingErrorAlarm,
+    EquipmentAlarm,
+    EnvironmentalAlarm,
+    IntegrityViolation,
+    OperationalViolation,
+    PhysicalViolation,
+    SecurityServiceOrMechanismViolation,
+    TimeDomainViolation,
+}
+
+impl EventType {
+    pub fn all() -> &'static [EventType] {
+        use self::EventType::*;
+        &[
+            Other,
+            CommunicationsAlarm,
+            QualityOfServiceAlarm,
+            ProcessingErrorAlarm,
+            EquipmentAlarm,
+            EnvironmentalAlarm,
+            IntegrityViolation,
+            OperationalViolation,
+            PhysicalViolation,
+            SecurityServiceOrMechanismViolation,
+            TimeDomainViolation,
+        ]
+    }
+
+    /// The value with this keyword
+    pub fn from_keyword(keyword: &str) -> Option<EventType> {
+        EventType::all()
+            .iter()
+            .cloned()
+            .find(|v| v.keyword() == keyword)
+    }
+
+    pub fn keyword(self) -> &'static str {
+        use self::EventType::*;
+        match self {
+            Other => "other",
+            CommunicationsAlarm => "communicationsAlarm",
+            QualityOfServiceAlarm => "qualityOfServiceAlarm",
+            ProcessingErrorAlarm => "processingErrorAlarm",
+            EquipmentAlarm => "equipmentAlarm",
+            EnvironmentalAlarm => "environmentalAlarm",
+            IntegrityViolation => "integrityViolation",
+            OperationalViolation => "operationalViolation",
+            PhysicalViolation => "physicalViolation",
+            SecurityServiceOrMechanismViolation => "securityServiceOrMechanismViolation",
+            TimeDomainViolation => "timeDomainViolation",
+        }
+    }
+}
+
+/// Whether an [`Alarm`](struct.Alarm.html) is getting worse
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+pub enum TrendIndication {
+    MoreSevere,
+    NoChange,
+    LessSevere,
+}
+
+impl TrendIndication {
+    pub fn all() -> &'static [TrendIndication] {
+        use self::TrendIndication::*;
+        &[MoreSevere, NoChange, LessSevere]
+    }
+
+    /// The value with this keyword
+    pub fn from_keyword(keyword: &str) -> Option<TrendIndication> {
+        TrendIndication::all()
+            .iter()
+            .cloned()
+            .find(|v| v.keyword() == keyword)
+    }
+
+    pub fn keyword(self) -> &'static str {
+        use self::TrendIndication::*;
+        match self {
+            MoreSevere => "moreSevere",
+            NoChange => "noChange",
+            LessSevere => "lessSevere",
+        }
+    }
+}
+
+/// The SD-ID "alarm" carries the state of an alarm, for network management
+/// systems that take alarms from syslog
+/// ([RFC5674](https://tools.ietf.org/html/rfc5674)).
+///
+/// It's attached to a message by
+/// [`Rfc5424Data::alarm`](../trait.Rfc5424Data.html#method.alarm). The severity of
+/// the message SHOULD be the one of the perceived severity, see
+/// [`PerceivedSeverity::severity`](enum.PerceivedSeverity.html#method.severity).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct Alarm {
+    /// The resource under alarm, such as an interface name
+    pub resource: String,
+    /// A value of the IANAItuProbableCause textual convention
+    pub probable_cause: u32,
+    pub perceived_severity: PerceivedSeverity,
+    pub event_type: Option<EventType>,
+    pub trend_indication: Option<TrendIndication>,
+    /// URI of the resource under alarm, in the Alarm MIB
+    #[cfg_attr(feature = "serde", serde(rename = "resourceURI"))]
+    pub resource_uri: Option<String>,
+}
+
+impl Alarm {
+    /// An alarm with the parameters that are REQUIRED
+    pub fn new(
+        resource: &str,
+        probable_cause: u32,
+        perceived_severity: PerceivedSeverity,
+    ) -> Alarm {
+        Alarm {
+            resource: resource.to_string(),
+            probable_cause,
+            perceived_severity,
+            event_type: None,
+            trend_indication: None,
+            resource_uri: None,
+        }
+    }
+
+    /// The PARAM-NAMEs and PARAM-VALUEs of the parameters that are set
+    pub fn params(&self) -> Vec<(String, String)> {
+        let mut params = vec![
+            ("resource".to_string(), self.resource.clone()),
+            ("probableCause".to_string(), self.probable_cause.to_string()),
+            (
+                "perceivedSeverity".to_string(),
+                self.perceived_severity.keyword().to_string(),
+            ),
+        ];
+        if let Some(event_type) = self.event_type {
+            params.push(("eventType".to_string(), event_type.keyword().to_string()));
+        }
+        if let Some(trend) = self.trend_indication {
+            params.push(("trendIndication".to_string(), trend.keyword().to_string()));
+        }
+        if let Some(uri) = &self.resource_uri {
+            params.push(("resourceURI".to_string(), uri.clone()));
+        }
+        params
+    }
+
+    /// Read an alarm back from the parameters of a parsed `alarm` SD-ELEMENT.
+    /// Returns `None` when a REQUIRED parameter is missing, or a value is unknown.
+    pub fn from_params(params: &[(String, String)]) -> Option<Alarm> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
         };
-        write!(f, "{}", val)
+        // `Some(None)` when the parameter isn't there, `None` when it's unknown
+        fn optional<T>(val: Option<&str>, parse: fn(&str) -> Option<T>) -> Option<Option<T>> {
+            match val {
+                Some(val) => parse(val).map(Some),
+                None => Some(None),
+            }
+        }
+        Some(Alarm {
+            resource: param("resource")?.to_string(),
+            probable_cause: param("probableCause")?.parse().ok()?,
+            perceived_severity: PerceivedSeverity::from_keyword(param("perceivedSeverity")?)?,
+            event_type: optional(param("eventType"), EventType::from_keyword)?,
+            trend_indication: optional(param("trendIndication"), TrendIndication::from_keyword)?,
+            resource_uri: param("resourceURI").map(String::from),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use types::{Facility, Message};
+    use {Rfc5424Builder, Rfc5424Data, StructuredData};
+
+    struct LinkDown(Alarm);
+
+    impl Rfc5424Data for LinkDown {
+        fn severity(&self) -> Severity {
+            self.0.perceived_severity.into()
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text("link down".into()))
+        }
+
+        fn alarm(&self) -> Option<Alarm> {
+            Some(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn perceived_severity() {
+        let severities: Vec<Severity> = PerceivedSeverity::all()
+            .iter()
+            .map(|s| s.severity())
+            .collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::Notice,
+                Severity::Notice,
+                Severity::Alert,
+                Severity::Critical,
+                Severity::Error,
+                Severity::Warning,
+            ]
+        );
+        for severity in PerceivedSeverity::all() {
+            assert_eq!(
+                PerceivedSeverity::from_keyword(severity.keyword()),
+                Some(*severity)
+            );
+        }
+    }
+
+    #[test]
+    fn alarm() {
+        let mut alarm = Alarm::new("eth0", 1, PerceivedSeverity::Major);
+        alarm.event_type = Some(EventType::CommunicationsAlarm);
+        alarm.resource_uri = Some("1.3.6.1.2.1.2.2.1.1.1".into());
+
+        let f = Rfc5424Builder::new("32473", Facility::Local0).build();
+        let mut out = Vec::new();
+        f.format(&mut out, &LinkDown(alarm.clone())).unwrap();
+        let parsed = parse(&out).unwrap();
+        assert_eq!(parsed.severity, Severity::Critical);
+        let element = &parsed.structured_data[0];
+        assert_eq!(element.id, "alarm");
+        assert_eq!(
+            element.params[..3],
+            [
+                ("resource".to_string(), "eth0".to_string()),
+                ("probableCause".to_string(), "1".to_string()),
+                ("perceivedSeverity".to_string(), "major".to_string()),
+            ]
+        );
+        assert_eq!(Alarm::from_params(&element.params), Some(alarm));
+
+        let unknown = vec![
+            ("resource".to_string(), "eth0".to_string()),
+            ("probableCause".to_string(), "1".to_string()),
+            ("perceivedSeverity".to_string(), "dire".to_string()),
+        ];
+        assert_eq!(Alarm::from_params(&unknown), None);
     }
 }
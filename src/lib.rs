@@ -12,8 +12,9 @@
 //! # Important details
 //! Some IANA reserved keywords are not implemented yet (`timeQuality`, and `meta`).
 //!
-//! The formatter is fairly strict in following the RFC. It may truncate fields
-//! or remove disallowed characters transparently to the caller. It will also
+//! The formatter is fairly strict in following the RFC. By default it truncates
+//! SD-IDs and PARAM-NAMEs, and removes disallowed characters from them; the
+//! [`sanitize`](sanitize/index.html) module has the other policies. It will also
 //! escape characters, as defined [in the RFC](https://tools.ietf.org/html/rfc5424#section-6)
 //!
 //! The formatter ([`Rfc5424`](struct.Rfc5424.html)) has a field for specifying if the message should be written as just
@@ -22,36 +23,140 @@
 //! syslog server (such as InfluxDB, or a remote RSYSLOG) this should be enabled, and the
 //! connection should be over TLS. However, if sending to the local RSYSLOG the normal
 //! 5424 format is likely correct(?).
+//!
+//! # Sending messages
+//! A [`Sender`](sender/struct.Sender.html) formats messages and sends them over a
+//! [`Transport`](sender/trait.Transport.html). Both can be built from a
+//! configuration file with the [`config`](config/index.html) module, and the
+//! [`relp`](relp/index.html) module has a transport with acknowledged delivery.
+//!
+//! # Receiving messages
+//! The [`parser`](parser/index.html) module turns RFC5424 messages back into the
+//! types used by this crate, and [`server`](server/index.html) has a small
+//! collector that receives messages over UDP, TCP, TLS (with the `tls` feature),
+//! DTLS (with the `dtls` feature) and Unix sockets. The [`dtls`](dtls/index.html)
+//! module also has a transport to send messages over DTLS.
+//!
+//! # Serde
+//! With the `serde` feature the types in [`types`](types/index.html) and
+//! [`iana`](iana/index.html), [`WriteFormat`](enum.WriteFormat.html) and
+//! [`SyslogMessage`](parser/struct.SyslogMessage.html) implement `Serialize` and
+//! `Deserialize`. Header values are checked the same way as their `new` functions
+//! when deserialized.
 
 #![deny(unsafe_code, missing_copy_implementations, unused_import_braces)]
 
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "encoding")]
+extern crate encoding_rs;
+#[cfg(feature = "compression")]
+extern crate flate2;
+#[cfg(any(feature = "tls", feature = "signing", feature = "dtls"))]
+extern crate openssl;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+pub mod config;
+pub mod discovery;
+#[cfg(feature = "dtls")]
+pub mod dtls;
+pub mod filter;
+pub mod framing;
+pub mod gelf;
 pub mod iana;
+pub mod parser;
+pub mod ratelimit;
+pub mod relay;
+pub mod relp;
+pub mod rfc3164;
+pub mod sanitize;
+pub mod sender;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod server;
+pub mod siem;
 pub mod types;
 use iana::*;
+use parser::SdElement;
+use rfc3164::SdFlattening;
+use sanitize::{ControlChars, InvalidName, NameField, Reporter, Sanitize, Sanitized};
 use types::*;
 
 /// Errors returned when verifying validity of metadata
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Error {
     FieldEmpty,
     FieldTooLong,
     InvalidCharacters,
+    /// Not an IP address, or not a `host:port` pair
+    InvalidAddress,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::FieldEmpty => write!(f, "field is empty"),
+            Error::FieldTooLong => write!(f, "field is too long"),
+            Error::InvalidCharacters => write!(f, "field contains invalid characters"),
+            Error::InvalidAddress => write!(f, "field is not a valid address"),
+        }
+    }
 }
 
+impl std::error::Error for Error {}
+
 /// Format of messages written out. RFC5425 just prepends the length
-/// of the 5424 message
-#[derive(Debug, PartialEq, Copy, Clone)]
+/// of the 5424 message, and RFC6587 appends a LF to it
+/// ([non-transparent framing](https://tools.ietf.org/html/rfc6587#section-3.4.2)).
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum WriteFormat {
+    #[default]
     RFC5424,
     RFC5425,
+    RFC6587,
+    /// Legacy BSD syslog, see the [`rfc3164`](rfc3164/index.html) module
+    RFC3164,
+}
+
+/// When a BOM is written before `Message::Text`.
+///
+/// [RFC5424 Section 6.4](https://tools.ietf.org/html/rfc5424#section-6.4) only
+/// requires it for a MSG that is declared UTF-8, and some receivers display it
+/// as junk. Messages without a BOM are parsed as `Message::Binary` by the
+/// [`parser`](parser/index.html).
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Bom {
+    #[default]
+    Always,
+    Never,
+    /// Only when the text contains a character that isn't ASCII
+    NonAscii,
 }
 
-impl Default for WriteFormat {
-    fn default() -> WriteFormat {
-        WriteFormat::RFC5424
+impl Bom {
+    fn prefix(self, text: &str) -> &'static [u8] {
+        match self {
+            Bom::Always => &BOM,
+            Bom::NonAscii if !text.is_ascii() => &BOM,
+            _ => &[],
+        }
     }
 }
 
@@ -67,13 +172,21 @@ pub struct Rfc5424 {
     version: u8,
     hostname: HostName,
     app_name: AppName,
-    pid: ProcessId,
+    pid: ProcessIdSource,
     msg_id: MessageId,
     facility: Facility,
     enterprise_id: String,
     iana_time_quality: Vec<TimeQuality>,
     iana_origin: Vec<Origin>,
     write_format: WriteFormat,
+    sd_flattening: SdFlattening,
+    max_len: Option<usize>,
+    sanitize: Sanitize,
+    on_sanitized: Option<Reporter>,
+    control_chars: ControlChars,
+    bom: Bom,
+    /// Correlation id of the next message split by `ControlChars::Split`
+    next_split_id: AtomicU64,
 }
 
 /// Builder for [`Rfc5424`](struct.Rfc5424.html)
@@ -82,6 +195,10 @@ pub struct Rfc5424Builder {
 }
 
 impl Rfc5424Builder {
+    /// `enterprise_id` is appended to every SD-ID of the structured data being
+    /// formatted (`id@enterprise_id`), unless the SD-ID already contains an `@`,
+    /// or is one of the [`RESERVED_SD_IDS`](iana/constant.RESERVED_SD_IDS.html).
+    /// An empty `enterprise_id` leaves all SD-IDs as they are.
     pub fn new(enterprise_id: &str, facility: Facility) -> Rfc5424Builder {
         Rfc5424Builder {
             data: Rfc5424 {
@@ -127,10 +244,42 @@ impl Rfc5424Builder {
     /// * `val` is an empty string
     /// * `val` doesn't contain printable ASCII characters (see `char::is_ascii_graphic`)
     pub fn pid(mut self, val: &str) -> Result<Self, Error> {
-        self.data.pid = ProcessId::new(val)?;
+        self.data.pid = ProcessIdSource::Static(ProcessId::new(val)?);
         Ok(self)
     }
 
+    /// Set the hostname to the one found by
+    /// [`discovery::hostname`](discovery/fn.hostname.html), or NILVALUE if there
+    /// is none. The name isn't resolved, use
+    /// [`discovery::resolved_hostname`](discovery/fn.resolved_hostname.html) with
+    /// [`hostname`](#method.hostname) for that.
+    pub fn discover_hostname(mut self) -> Self {
+        self.data.hostname = discovery::hostname().unwrap_or_default();
+        self
+    }
+
+    /// Set the app name to the name of the executable, see
+    /// [`discovery::app_name`](discovery/fn.app_name.html). NILVALUE is used if
+    /// the name can't be found.
+    pub fn discover_app_name(mut self) -> Self {
+        self.data.app_name = discovery::app_name().unwrap_or_default();
+        self
+    }
+
+    /// Set the process id to the id of this process. The id is read every time
+    /// a message is formatted, so it's still correct after a `fork`.
+    pub fn discover_pid(self) -> Self {
+        self.pid_source(ProcessIdSource::Current)
+    }
+
+    /// Set where the process id used in the header of the syslog message comes
+    /// from. Messages can also have their own, see
+    /// [`Rfc5424Data::pid`](trait.Rfc5424Data.html#method.pid).
+    pub fn pid_source(mut self, source: ProcessIdSource) -> Self {
+        self.data.pid = source;
+        self
+    }
+
     /// Set the msgId used in the header of the syslog message. (OPTIONAL)
     ///
     /// The MSGID SHOULD identify the type of message.  For example, a
@@ -149,9 +298,11 @@ impl Rfc5424Builder {
         Ok(self)
     }
 
-    /// Set the format of the output between RFC5424 and RFC5425.
+    /// Set the format of the output between RFC5424, RFC5425, and RFC6587.
     ///
     /// RFC5425 is the same as RFC5424 except it prepends the length of the message.
+    /// RFC6587 ends the message with a LF instead, for receivers that split
+    /// messages on newlines.
     ///
     /// # Example
     /// RFC5424: `<11>1 - server1.example.com my_app_name 5445 msg_id [hello@ent_id id="54" progress="complete"] sample message`
@@ -162,6 +313,71 @@ impl Rfc5424Builder {
         self
     }
 
+    /// Set how structured data is written into the MSG of `WriteFormat::RFC3164`
+    /// messages, which have no STRUCTURED-DATA part.
+    pub fn sd_flattening(mut self, f: SdFlattening) -> Self {
+        self.data.sd_flattening = f;
+        self
+    }
+
+    /// Limit the length of formatted messages to `max` bytes, not counting the
+    /// framing of `WriteFormat::RFC5425` and `WriteFormat::RFC6587`.
+    ///
+    /// [RFC5424 Section 6.1](https://tools.ietf.org/html/rfc5424#section-6.1)
+    /// only requires receivers to accept 480 bytes, and SHOULD accept 2048, so
+    /// longer messages may be truncated along the way. Messages that are too
+    /// long are shortened by:
+    ///
+    /// 1. truncating the MSG, without splitting a UTF-8 character of `Message::Text`
+    /// 2. dropping the MSG, then dropping SD-ELEMENTs: those of the message from
+    ///    last to first, then `alarm`, `timeQuality` and `origin`
+    ///
+    /// A `truncated` SD-ELEMENT with the `originalLength` of the message is added
+    /// to messages that were shortened, so the receiver knows data is missing.
+    /// If the header alone is longer than `max`, the message is written with
+    /// only the header and this element.
+    pub fn max_len(mut self, max: usize) -> Self {
+        self.data.max_len = Some(max);
+        self
+    }
+
+    /// Set how SD-IDs and PARAM-NAMEs that aren't valid are handled. See the
+    /// [`sanitize`](sanitize/index.html) module. Defaults to `Sanitize::Strip`.
+    pub fn sanitize(mut self, sanitize: Sanitize) -> Self {
+        self.data.sanitize = sanitize;
+        self
+    }
+
+    /// Call `callback` with each SD-ID and PARAM-NAME changed while formatting,
+    /// for example to count them, or to log the names that need fixing.
+    pub fn on_sanitized<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Sanitized) + Send + Sync + 'static,
+    {
+        self.data.on_sanitized = Some(Reporter(Arc::new(callback)));
+        self
+    }
+
+    /// Set how control characters in the text of a MSG are handled. See the
+    /// [`sanitize`](sanitize/index.html) module. Defaults to `ControlChars::Keep`.
+    ///
+    /// With `ControlChars::Split` one call to
+    /// [`Rfc5424::format`](struct.Rfc5424.html#method.format) may write several
+    /// messages, which needs a framed `WriteFormat` (RFC5425 or RFC6587); use
+    /// [`Rfc5424::format_each`](struct.Rfc5424.html#method.format_each) to get
+    /// them one at a time.
+    pub fn control_chars(mut self, control_chars: ControlChars) -> Self {
+        self.data.control_chars = control_chars;
+        self
+    }
+
+    /// Set when a BOM is written before the text of a MSG. Defaults to
+    /// `Bom::Always`. RFC3164 messages never have a BOM.
+    pub fn bom(mut self, bom: Bom) -> Self {
+        self.data.bom = bom;
+        self
+    }
+
     /// Add an IANA reserved origin key-value pair.
     ///
     /// # Examples
@@ -202,30 +418,162 @@ pub type StructuredData<'a> = HashMap<&'a str, Vec<(String, String)>>;
 /// Trait that defines what data is needed in order to create
 /// a RFC5424 message. Any type that implements this can be
 /// formatted with a [`Rfc5424`](struct.Rfc5424.html) struct.
+///
+/// The methods with a default implementation return header values for a single
+/// message. Their values are checked by the `new` function of their type, so
+/// they are written as is.
 pub trait Rfc5424Data {
     fn severity(&self) -> Severity;
     fn timestamp(&self) -> Option<String>;
-    fn structured_data(&self) -> Option<StructuredData>;
+    fn structured_data(&self) -> Option<StructuredData<'_>>;
     fn message(&self) -> Option<Message>;
-}
 
-fn generate_priority(facility: Facility, severity: Severity) -> String {
-    let priority = (facility as u8 * 8) + severity as u8;
-    format!("<{}>", priority)
+    /// PROCID of this message, used instead of the one of the formatter. Useful
+    /// for values that change between messages, such as thread or request ids.
+    fn pid(&self) -> Option<ProcessId> {
+        None
+    }
+
+    /// MSGID of this message, used instead of the one of the formatter. The
+    /// MSGID identifies the type of event, such as "TCPIN" or "TCPOUT".
+    fn msg_id(&self) -> Option<MessageId> {
+        None
+    }
+
+    /// APP-NAME of this message, used instead of the one of the formatter.
+    fn app_name(&self) -> Option<AppName> {
+        None
+    }
+
+    /// HOSTNAME of this message, used instead of the one of the formatter.
+    fn hostname(&self) -> Option<HostName> {
+        None
+    }
+
+    /// Facility of this message, used instead of the one of the formatter.
+    fn facility(&self) -> Option<Facility> {
+        None
+    }
+
+    /// Alarm state carried by this message, written as an IANA `alarm`
+    /// SD-ELEMENT. See [`Alarm`](iana/struct.Alarm.html).
+    fn alarm(&self) -> Option<Alarm> {
+        None
+    }
+
+    /// SD-ELEMENTs of this message in the order they are written, used instead
+    /// of `structured_data` when present. Keeps the order and repeated SD-IDs
+    /// of parsed messages, which signatures over relayed messages depend on.
+    #[doc(hidden)]
+    fn ordered_structured_data(&self) -> Option<Cow<'_, [SdElement]>> {
+        None
+    }
 }
 
 impl Rfc5424 {
     /// Format `Rfc5424Data` into a RFC5424 message according to the metadata in
-    /// `self`, and writes it using `writer`.
+    /// `self`, and writes it using `writer`. Messages split by
+    /// `ControlChars::Split` are written one after the other, each with its own
+    /// framing.
     ///
     /// # Errors
-    /// Errors when `writer` returns an error (`io::Error`)
+    /// Errors when `writer` returns an error (`io::Error`), and with an
+    /// `InvalidInput` error when `ControlChars::Split` splits the text while the
+    /// `WriteFormat` has no framing, since the messages would run together.
+    /// Use [`format_each`](#method.format_each) to write those one at a time.
     pub fn format<W: Write>(&self, writer: &mut W, message: &impl Rfc5424Data) -> io::Result<()> {
+        match self.parts(message) {
+            Some(ref parts) if parts.len() > 1 && !self.framed() => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "split messages need a framed WriteFormat, use format_each to write them",
+            )),
+            Some(parts) => parts
+                .iter()
+                .try_for_each(|part| self.format_one(writer, part)),
+            None => self.format_one(writer, message),
+        }
+    }
+
+    /// Check if the messages written are framed, so several can be written
+    /// one after the other
+    fn framed(&self) -> bool {
+        match self.write_format {
+            WriteFormat::RFC5425 | WriteFormat::RFC6587 => true,
+            WriteFormat::RFC5424 | WriteFormat::RFC3164 => false,
+        }
+    }
+
+    /// Format `Rfc5424Data` like [`format`](#method.format), passing each message
+    /// to `emit` on its own. Used by transports that send one message at a time,
+    /// such as datagram sockets.
+    ///
+    /// # Errors
+    /// Errors when `emit` returns an error
+    pub fn format_each<F>(&self, message: &impl Rfc5424Data, mut emit: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let mut buffer = Vec::new();
+        match self.parts(message) {
+            Some(parts) => {
+                for part in &parts {
+                    buffer.clear();
+                    self.format_one(&mut buffer, part)?;
+                    emit(&buffer)?;
+                }
+                Ok(())
+            }
+            None => {
+                self.format_one(&mut buffer, message)?;
+                emit(&buffer)
+            }
+        }
+    }
+
+    /// The messages written for `message` when the `ControlChars` policy changes
+    /// its text, or `None` to write it as is.
+    fn parts<'a, D: Rfc5424Data>(&self, message: &'a D) -> Option<Vec<Part<'a, D>>> {
+        if self.control_chars == ControlChars::Keep {
+            return None;
+        }
+        let text = match message.message() {
+            Some(Message::Text(text)) => text,
+            _ => return None,
+        };
+        let texts = self.control_chars.apply(&text);
+        let split = if texts.len() > 1 {
+            Some(self.next_split_id.fetch_add(1, Ordering::Relaxed))
+        } else {
+            None
+        };
+        let total = texts.len();
+        let parts = texts
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| Part {
+                data: message,
+                text,
+                split: split.map(|id| (id, i + 1, total)),
+            })
+            .collect();
+        Some(parts)
+    }
+
+    /// Format a single message
+    fn format_one<W: Write>(&self, writer: &mut W, message: &impl Rfc5424Data) -> io::Result<()> {
+        if self.write_format == WriteFormat::RFC3164 {
+            return rfc3164::format(self, writer, message);
+        }
+
         let mut log = String::new();
+        let header = self.header(message);
 
         // - HEADER -
         // PRI
-        log.push_str(&generate_priority(self.facility, message.severity()));
+        log.push_str(&format!(
+            "<{}>",
+            encode_priority(header.facility, message.severity())
+        ));
 
         // VERSION
         log.push_str(&self.version.to_string()); // TODO does this have to be zero padded
@@ -240,71 +588,40 @@ impl Rfc5424 {
         log.push(' ');
 
         // HOSTNAME
-        log.push_str(&self.hostname.0);
+        log.push_str(&header.hostname.0);
         log.push(' ');
 
         // APP-NAME
-        log.push_str(&self.app_name.0);
+        log.push_str(&header.app_name.0);
         log.push(' ');
 
         // PROCESS ID
-        log.push_str(&self.pid.0);
+        log.push_str(&header.pid.0);
         log.push(' ');
 
         // MESSAGE ID
-        log.push_str(&self.msg_id.0);
+        log.push_str(&header.msg_id.0);
         log.push(' ');
 
         // - STRUCTURED-DATA -
-        // TODO: precompute this and store in formatting struct?
-        if !self.iana_origin.is_empty() {
-            log.push_str("[origin");
-            for val in &self.iana_origin {
-                log.push_str(&val.to_string());
-            }
-            log.push(']');
-        }
-
-        if !self.iana_time_quality.is_empty() {
-            log.push_str("[timeQuality");
-            for val in &self.iana_time_quality {
-                log.push_str(&val.to_string());
-            }
-            log.push(']');
-        }
-
-        if let Some(sd) = message.structured_data() {
-            sd.iter().for_each(|(id, pairs)| {
-                log.push('[');
-                log.push_str(&remove_invalid(id));
-                log.push('@');
-                log.push_str(&self.enterprise_id);
-                for (name, val) in pairs {
-                    log.push(' ');
-                    log.push_str(&remove_invalid(name));
-                    log.push('=');
-                    log.push('"');
-                    log.push_str(&escape_val(val));
-                    log.push('"');
-                }
-                log.push(']');
-            })
+        let mut elements = self.checked_sd_elements(message)?;
+        let mut m = message.message();
+        if let Some(max) = self.max_len {
+            self.fit(max, log.len(), &mut elements, &mut m);
         }
-
-        // must use NILVALUE if we don't have any structured data
-        if message.structured_data().is_none()
-            && self.iana_origin.is_empty()
-            && self.iana_time_quality.is_empty()
-        {
+        if elements.is_empty() {
+            // must use NILVALUE if we don't have any structured data
             log.push(NILVALUE);
         }
+        for element in &elements {
+            write_sd_element(&mut log, element);
+        }
 
         // MESSAGE
-        let m = message.message();
         let msg_len: Option<usize> = m.as_ref().map(|msg: &Message| {
             log.push(' ');
             match msg {
-                Message::Text(s) => s.as_bytes().len() + 3, // add 3 for BOM
+                Message::Text(s) => self.bom.prefix(s).len() + s.len(),
                 Message::Binary(data) => data.as_slice().len(),
             }
         });
@@ -312,9 +629,9 @@ impl Rfc5424 {
         // write message length according to RFC5425
         if self.write_format == WriteFormat::RFC5425 {
             let length = if let Some(bytes) = msg_len {
-                log.as_bytes().len() + bytes
+                log.len() + bytes
             } else {
-                log.as_bytes().len()
+                log.len()
             };
             writer.write_all(format!("{} ", length).as_bytes())?;
         }
@@ -326,14 +643,349 @@ impl Rfc5424 {
         if let Some(msg) = m {
             match msg {
                 Message::Text(s) => {
-                    writer.write_all(&BOM)?;
+                    writer.write_all(self.bom.prefix(&s))?;
                     writer.write_all(s.as_bytes())?
                 }
                 Message::Binary(s) => writer.write_all(s.as_slice())?,
             }
         }
+
+        if self.write_format == WriteFormat::RFC6587 {
+            writer.write_all(b"\n")?;
+        }
         Ok(())
     }
+
+    /// The header fields of `message`, falling back to the ones of `self`
+    fn header(&self, message: &impl Rfc5424Data) -> Header {
+        Header {
+            facility: message.facility().unwrap_or(self.facility),
+            hostname: message.hostname().unwrap_or_else(|| self.hostname.clone()),
+            app_name: message.app_name().unwrap_or_else(|| self.app_name.clone()),
+            pid: message.pid().unwrap_or_else(|| self.pid.get()),
+            msg_id: message.msg_id().unwrap_or_else(|| self.msg_id.clone()),
+        }
+    }
+
+    /// Append `@enterprise_id` to `id`. IDs that are already qualified, or
+    /// registered with IANA, are kept as is.
+    fn qualify(&self, mut id: String) -> String {
+        if !self.is_qualified(&id) {
+            id.push('@');
+            id.push_str(&self.enterprise_id);
+        }
+        id
+    }
+
+    /// Check if `qualify` leaves `id` as is
+    fn is_qualified(&self, id: &str) -> bool {
+        id.contains('@') || is_reserved(id) || self.enterprise_id.is_empty()
+    }
+
+    /// Shorten the STRUCTURED-DATA and MSG of a message so it's no longer than
+    /// `max`, as described in [`Rfc5424Builder::max_len`]. `header_len` is the
+    /// length of everything before the STRUCTURED-DATA.
+    fn fit(
+        &self,
+        max: usize,
+        header_len: usize,
+        elements: &mut Vec<SdElement>,
+        message: &mut Option<Message>,
+    ) {
+        let sd_len = |elements: &[SdElement]| {
+            let mut sd = String::new();
+            for element in elements {
+                write_sd_element(&mut sd, element);
+            }
+            sd.len().max(1)
+        };
+        // the SP before the MSG, and the BOM of text
+        let msg_overhead = |message: &Message| match message {
+            Message::Text(s) => 1 + self.bom.prefix(s).len(),
+            Message::Binary(_) => 1,
+        };
+        let msg_len = |message: &Option<Message>| match message {
+            Some(m @ Message::Text(s)) => msg_overhead(m) + s.len(),
+            Some(m @ Message::Binary(b)) => msg_overhead(m) + b.len(),
+            None => 0,
+        };
+
+        let original = header_len + sd_len(elements) + msg_len(message);
+        if original <= max {
+            return;
+        }
+        // kept at the end, so it's never dropped
+        elements.push(SdElement {
+            id: self.qualify("truncated".into()),
+            params: vec![("originalLength".into(), original.to_string())],
+        });
+
+        let fixed = header_len + sd_len(elements);
+        if let Some(m) = message.take() {
+            let overhead = msg_overhead(&m);
+            if fixed + overhead < max {
+                let room = max - fixed - overhead;
+                *message = Some(match m {
+                    Message::Text(mut s) => {
+                        s.truncate(floor_char_boundary(s.as_bytes(), room));
+                        Message::Text(s)
+                    }
+                    Message::Binary(mut b) => {
+                        b.truncate(room);
+                        Message::Binary(b)
+                    }
+                });
+                return;
+            }
+        }
+
+        while elements.len() > 1 && header_len + sd_len(elements) > max {
+            let marker = elements.len() - 1;
+            elements.remove(marker - 1);
+        }
+    }
+
+    /// The SD-ELEMENTs of `message` as they are formatted, reporting the names
+    /// changed by the sanitize policy.
+    ///
+    /// # Errors
+    /// Errors with an `InvalidData` error wrapping an
+    /// [`InvalidName`](sanitize/struct.InvalidName.html) when the policy is
+    /// `Sanitize::Strict` and a name isn't valid, or a name is empty once
+    /// sanitized.
+    fn checked_sd_elements(&self, message: &impl Rfc5424Data) -> io::Result<Vec<SdElement>> {
+        let mut report = |sanitized: Sanitized| {
+            if let Some(Reporter(callback)) = &self.on_sanitized {
+                callback(&sanitized);
+            }
+        };
+        self.collect_sd_elements(message, self.sanitize, &mut report)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The SD-ELEMENTs of `message`, for inspecting it without formatting.
+    /// Names that `Sanitize::Strict` would reject are stripped instead.
+    fn sd_elements(&self, message: &impl Rfc5424Data) -> Vec<SdElement> {
+        let sanitize = match self.sanitize {
+            Sanitize::Strict => Sanitize::Strip,
+            lenient => lenient,
+        };
+        self.collect_sd_elements(message, sanitize, &mut |_| {})
+            .unwrap_or_default()
+    }
+
+    /// Collect the SD-ELEMENTs of a message: the IANA elements set on `self` and
+    /// the alarm of `message`, followed by the structured data of `message` with
+    /// sanitized names and qualified SD-IDs.
+    fn collect_sd_elements(
+        &self,
+        message: &impl Rfc5424Data,
+        sanitize: Sanitize,
+        report: &mut dyn FnMut(Sanitized),
+    ) -> Result<Vec<SdElement>, InvalidName> {
+        // TODO: precompute the IANA elements and store in formatting struct?
+        let mut elements = Vec::new();
+        if !self.iana_origin.is_empty() {
+            elements.push(SdElement {
+                id: "origin".into(),
+                params: self.iana_origin.iter().map(Origin::param).collect(),
+            });
+        }
+
+        if !self.iana_time_quality.is_empty() {
+            elements.push(SdElement {
+                id: "timeQuality".into(),
+                params: self.iana_time_quality.iter().map(TimeQuality::param).collect(),
+            });
+        }
+
+        if let Some(alarm) = message.alarm() {
+            elements.push(SdElement {
+                id: "alarm".into(),
+                params: alarm.params(),
+            });
+        }
+
+        let sd = message.structured_data();
+        let ordered = message.ordered_structured_data();
+        let pairs: Vec<(&str, &[(String, String)])> = match &ordered {
+            Some(ordered) => ordered
+                .iter()
+                .map(|element| (element.id.as_str(), element.params.as_slice()))
+                .collect(),
+            None => sd
+                .iter()
+                .flatten()
+                .map(|(id, pairs)| (*id, pairs.as_slice()))
+                .collect(),
+        };
+        for (id, pairs) in pairs {
+            // the `@enterprise_id` suffix counts towards the 32 characters
+            let max = if self.is_qualified(id) {
+                MAX_NAME_LEN
+            } else {
+                MAX_NAME_LEN.saturating_sub(self.enterprise_id.len() + 1)
+            };
+            let id = sanitized(sanitize, NameField::SdId, id, max, report)?;
+            let mut params = Vec::new();
+            for (name, val) in pairs {
+                let name = sanitized(sanitize, NameField::ParamName, name, MAX_NAME_LEN, report)?;
+                params.push((name, val.clone()));
+            }
+            elements.push(SdElement {
+                id: self.qualify(id),
+                params,
+            });
+        }
+        Ok(elements)
+    }
+}
+
+/// Longest SD-ID or PARAM-NAME allowed by the RFC
+const MAX_NAME_LEN: usize = 32;
+
+/// Apply `sanitize` to `name`, passing it to `report` if it was changed
+fn sanitized(
+    sanitize: Sanitize,
+    field: NameField,
+    name: &str,
+    max_len: usize,
+    report: &mut dyn FnMut(Sanitized),
+) -> Result<String, InvalidName> {
+    let written = sanitize.name(field, name, max_len)?;
+    if written != name {
+        report(Sanitized {
+            field,
+            original: name.to_string(),
+            written: written.clone(),
+        });
+    }
+    Ok(written)
+}
+
+/// Largest length of `bytes` that is no longer than `max`, and doesn't end in the
+/// middle of a UTF-8 character.
+pub(crate) fn floor_char_boundary(bytes: &[u8], max: usize) -> usize {
+    if bytes.len() <= max {
+        return bytes.len();
+    }
+    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+    // step back over at most 3 continuation bytes to the start of the character
+    let start = (max.saturating_sub(3)..=max)
+        .rev()
+        .find(|i| !is_continuation(bytes[*i]));
+    start.unwrap_or(max)
+}
+
+/// One of the messages written for `data`, with its text changed by the
+/// `ControlChars` policy
+struct Part<'a, D: 'a> {
+    data: &'a D,
+    text: String,
+    /// Correlation id, part number and number of parts of a split message
+    split: Option<(u64, usize, usize)>,
+}
+
+impl<'a, D: Rfc5424Data> Rfc5424Data for Part<'a, D> {
+    fn severity(&self) -> Severity {
+        self.data.severity()
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        self.data.timestamp()
+    }
+
+    fn structured_data(&self) -> Option<StructuredData<'_>> {
+        let mut sd = self.data.structured_data();
+        if let Some(split) = self.split_params() {
+            // an SD-ID of the message named `split` is kept as is
+            sd.get_or_insert_with(HashMap::new)
+                .entry("split")
+                .or_insert(split);
+        }
+        sd
+    }
+
+    fn ordered_structured_data(&self) -> Option<Cow<'_, [SdElement]>> {
+        let ordered = self.data.ordered_structured_data()?;
+        match self.split_params() {
+            Some(params) if !ordered.iter().any(|element| element.id == "split") => {
+                let mut elements = ordered.into_owned();
+                elements.push(SdElement {
+                    id: "split".into(),
+                    params,
+                });
+                Some(Cow::Owned(elements))
+            }
+            _ => Some(ordered),
+        }
+    }
+
+    fn message(&self) -> Option<Message> {
+        Some(Message::Text(self.text.clone()))
+    }
+
+    fn pid(&self) -> Option<ProcessId> {
+        self.data.pid()
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        self.data.msg_id()
+    }
+
+    fn app_name(&self) -> Option<AppName> {
+        self.data.app_name()
+    }
+
+    fn hostname(&self) -> Option<HostName> {
+        self.data.hostname()
+    }
+
+    fn facility(&self) -> Option<Facility> {
+        self.data.facility()
+    }
+
+    fn alarm(&self) -> Option<Alarm> {
+        self.data.alarm()
+    }
+}
+
+impl<'a, D> Part<'a, D> {
+    /// PARAMs of the `split` SD-ELEMENT of a split message
+    fn split_params(&self) -> Option<Vec<(String, String)>> {
+        self.split.map(|(id, part, total)| {
+            vec![
+                ("id".into(), id.to_string()),
+                ("part".into(), part.to_string()),
+                ("total".into(), total.to_string()),
+            ]
+        })
+    }
+}
+
+/// Header fields of a single message
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Header {
+    facility: Facility,
+    hostname: HostName,
+    app_name: AppName,
+    pid: ProcessId,
+    msg_id: MessageId,
+}
+
+/// Write `element` in the SD-ELEMENT format, escaping the PARAM-VALUEs.
+fn write_sd_element(log: &mut String, element: &SdElement) {
+    log.push('[');
+    log.push_str(&element.id);
+    for (name, val) in &element.params {
+        log.push(' ');
+        log.push_str(name);
+        log.push('=');
+        log.push('"');
+        log.push_str(&escape_val(val));
+        log.push('"');
+    }
+    log.push(']');
 }
 
 #[cfg(test)]
@@ -356,7 +1008,7 @@ mod tests {
             None
         }
 
-        fn structured_data(&self) -> Option<StructuredData> {
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
             self.structured_data.clone()
         }
 
@@ -373,7 +1025,7 @@ mod tests {
         out.extend(structured.trim().as_bytes());
 
         if let Some(s) = message {
-            out.push(' ' as u8);
+            out.push(b' ');
             out.extend(BOM.iter());
             out.extend(s.trim().as_bytes());
         }
@@ -508,4 +1160,381 @@ mod tests {
             test_vec(r#"<12>1 - - - - - [exampleSDID@32473 iut="3" eventSource="Application" eventID="1011"][examplePriority@32473 class="high"]"#, 
                 None)).unwrap().len(), s.len());
     }
+
+    #[test]
+    fn qualify() {
+        // every SD-ID used to get the suffix: `exampleSDID@32473@32473`,
+        // `meta@32473`, and `exampleSDID@` for an empty enterprise id
+        let f = Rfc5424Builder::new("32473", Facility::User).build();
+        assert_eq!(f.qualify("exampleSDID".into()), "exampleSDID@32473");
+        assert_eq!(f.qualify("exampleSDID@32473".into()), "exampleSDID@32473");
+        assert_eq!(f.qualify("meta".into()), "meta");
+
+        let f = Rfc5424Builder::new("", Facility::User).build();
+        assert_eq!(f.qualify("exampleSDID".into()), "exampleSDID");
+    }
+
+    #[test]
+    fn qualified_sd_ids() {
+        let mut hmap: StructuredData = HashMap::new();
+        hmap.insert("exampleSDID@32473", vec![("iut".into(), "3".into())]);
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: Some(hmap.clone()),
+            message: None,
+        };
+        let f = Rfc5424Builder::new("ent_id", Facility::User).build();
+
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"<11>1 - - - - - [exampleSDID@32473 iut="3"]"#
+        );
+
+        hmap.clear();
+        hmap.insert("meta", vec![("sequenceId".into(), "1".into())]);
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: Some(hmap),
+            message: None,
+        };
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"<11>1 - - - - - [meta sequenceId="1"]"#
+        );
+    }
+
+    #[test]
+    fn pid_sources() {
+        struct Worker(Option<&'static str>);
+
+        impl Rfc5424Data for Worker {
+            fn severity(&self) -> Severity {
+                Severity::Informational
+            }
+
+            fn timestamp(&self) -> Option<String> {
+                None
+            }
+
+            fn structured_data(&self) -> Option<StructuredData<'_>> {
+                None
+            }
+
+            fn message(&self) -> Option<Message> {
+                None
+            }
+
+            fn pid(&self) -> Option<ProcessId> {
+                self.0.map(|id| ProcessId::new(id).unwrap())
+            }
+        }
+
+        let format = |f: &Rfc5424, data: &Worker| {
+            let mut out = Vec::new();
+            f.format(&mut out, data).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let fixed = Rfc5424Builder::new("", Facility::User)
+            .pid("100")
+            .unwrap()
+            .build();
+        assert_eq!(format(&fixed, &Worker(None)), "<14>1 - - - 100 - -");
+        assert_eq!(
+            format(&fixed, &Worker(Some("worker-7"))),
+            "<14>1 - - - worker-7 - -"
+        );
+
+        let live = Rfc5424Builder::new("", Facility::User)
+            .discover_pid()
+            .build();
+        assert_eq!(
+            format(&live, &Worker(None)),
+            format!("<14>1 - - - {} - -", std::process::id())
+        );
+    }
+
+    #[test]
+    fn message_headers() {
+        struct Traffic(&'static str);
+
+        impl Rfc5424Data for Traffic {
+            fn severity(&self) -> Severity {
+                Severity::Notice
+            }
+
+            fn timestamp(&self) -> Option<String> {
+                None
+            }
+
+            fn structured_data(&self) -> Option<StructuredData<'_>> {
+                None
+            }
+
+            fn message(&self) -> Option<Message> {
+                None
+            }
+
+            fn msg_id(&self) -> Option<MessageId> {
+                Some(MessageId::new(self.0).unwrap())
+            }
+
+            fn facility(&self) -> Option<Facility> {
+                Some(Facility::Local7)
+            }
+        }
+
+        let f = Rfc5424Builder::new("", Facility::User)
+            .hostname("fw1")
+            .unwrap()
+            .app_name("filter")
+            .unwrap()
+            .msg_id("DEFAULT")
+            .unwrap()
+            .build();
+        for (id, expected) in &[
+            ("TCPIN", "<189>1 - fw1 filter - TCPIN -"),
+            ("TCPOUT", "<189>1 - fw1 filter - TCPOUT -"),
+        ] {
+            let mut out = Vec::new();
+            f.format(&mut out, &Traffic(id)).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn max_len() {
+        let f = Rfc5424Builder::new("32473", Facility::User)
+            .origin(Origin::Software("app".into()))
+            .max_len(121)
+            .write_format(WriteFormat::RFC5425)
+            .build();
+        let mut hmap: StructuredData = HashMap::new();
+        hmap.insert("req", vec![("id".into(), "42".into())]);
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: Some(hmap.clone()),
+            message: Some(Message::Text("\u{e9}".repeat(100))),
+        };
+
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        let (len, syslog_msg) = {
+            let space = out.iter().position(|b| *b == b' ').unwrap();
+            let len: usize = str::from_utf8(&out[..space]).unwrap().parse().unwrap();
+            (len, &out[space + 1..])
+        };
+        assert_eq!(len, syslog_msg.len());
+        // 'é' is 2 bytes, so one byte is left unused rather than splitting it
+        assert_eq!(len, 120);
+        let parsed = parser::parse(syslog_msg).unwrap();
+        let ids: Vec<_> = parsed.structured_data.iter().map(|e| &e.id[..]).collect();
+        assert_eq!(ids, vec!["origin", "req@32473", "truncated@32473"]);
+        assert_eq!(
+            parsed.structured_data[2].params,
+            vec![("originalLength".into(), "262".into())]
+        );
+        match parsed.message {
+            Some(Message::Text(s)) => assert!(s.chars().all(|c| c == '\u{e9}')),
+            other => panic!("{:?}", other),
+        }
+
+        // without room for the MSG, SD-ELEMENTs of the message go first, then IANA ones
+        let f = Rfc5424Builder::new("32473", Facility::User)
+            .origin(Origin::Software("app".into()))
+            .max_len(80)
+            .build();
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        assert_eq!(
+            str::from_utf8(&out).unwrap(),
+            r#"<11>1 - - - - - [origin software="app"][truncated@32473 originalLength="262"]"#
+        );
+
+        let mut out = Vec::new();
+        f.format(
+            &mut out,
+            &Rfc5424Message {
+                severity: Severity::Error,
+                structured_data: Some(hmap),
+                message: Some(Message::Binary(vec![0xff; 100])),
+            },
+        )
+        .unwrap();
+        assert!(out.len() <= 80);
+        assert!(!out.ends_with(&[0xff]));
+    }
+
+    #[test]
+    fn sanitize() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut hmap: StructuredData = HashMap::new();
+        // 30 characters, too long once `@32473` is appended
+        hmap.insert(
+            "a_very_long_structured_data_id",
+            vec![("user name".into(), "x".into())],
+        );
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: Some(hmap),
+            message: None,
+        };
+
+        let changed = Arc::new(AtomicUsize::new(0));
+        let counter = changed.clone();
+        let f = Rfc5424Builder::new("32473", Facility::User)
+            .sanitize(Sanitize::Replace)
+            .on_sanitized(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .build();
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        assert_eq!(
+            str::from_utf8(&out).unwrap(),
+            r#"<11>1 - - - - - [a_very_long_structured_dat@32473 user_name="x"]"#
+        );
+        assert_eq!(changed.load(Ordering::Relaxed), 2);
+
+        let f = Rfc5424Builder::new("32473", Facility::User)
+            .sanitize(Sanitize::Strict)
+            .build();
+        let err = f.format(&mut Vec::new(), &msg).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let invalid = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<InvalidName>())
+            .unwrap();
+        assert_eq!(invalid.field, NameField::SdId);
+        assert_eq!(invalid.value, "a_very_long_structured_data_id");
+        assert_eq!(invalid.error, Error::FieldTooLong);
+
+        // an empty name would write `[ x="1"]`
+        let mut sd = StructuredData::new();
+        sd.insert("\"", vec![("x".into(), "1".into())]);
+        let msg = Rfc5424Message {
+            structured_data: Some(sd),
+            ..msg
+        };
+        let f = Rfc5424Builder::new("32473", Facility::User).build();
+        let err = f.format(&mut Vec::new(), &msg).unwrap_err();
+        let invalid = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<InvalidName>())
+            .unwrap();
+        assert_eq!(invalid.error, Error::FieldEmpty);
+    }
+
+    #[test]
+    fn control_chars() {
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: None,
+            message: Some(Message::Text("a\nb".into())),
+        };
+        let format = |write_format, control_chars| {
+            let f = Rfc5424Builder::new("", Facility::User)
+                .write_format(write_format)
+                .control_chars(control_chars)
+                .build();
+            let mut out = Vec::new();
+            f.format(&mut out, &msg).unwrap();
+            out
+        };
+
+        let escaped = format(WriteFormat::RFC6587, ControlChars::Escape);
+        assert!(escaped.ends_with(b"a#012b\n"));
+        assert_eq!(escaped.iter().filter(|b| **b == b'\n').count(), 1);
+
+        let rfc3164 = format(WriteFormat::RFC3164, ControlChars::Space);
+        assert!(rfc3164.ends_with(b" a b"));
+
+        // split messages are written one after the other, each with its own framing
+        let split = format(WriteFormat::RFC6587, ControlChars::Split);
+        let lines: Vec<_> = split.split(|b| *b == b'\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(b"a"));
+        assert!(lines[1].ends_with(b"b"));
+
+        // without framing they can only be written one at a time
+        let f = Rfc5424Builder::new("", Facility::User)
+            .control_chars(ControlChars::Split)
+            .build();
+        let err = f.format(&mut Vec::new(), &msg).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut parts = 0;
+        f.format_each(&msg, |_| {
+            parts += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(parts, 2);
+    }
+
+    #[test]
+    fn split_keeps_sd_id() {
+        let mut hmap: StructuredData = HashMap::new();
+        hmap.insert("split", vec![("job".into(), "42".into())]);
+        let msg = Rfc5424Message {
+            severity: Severity::Error,
+            structured_data: Some(hmap),
+            message: Some(Message::Text("a\nb".into())),
+        };
+        let f = Rfc5424Builder::new("", Facility::User)
+            .control_chars(ControlChars::Split)
+            .build();
+        f.format_each(&msg, |message| {
+            assert!(message.starts_with(br#"<11>1 - - - - - [split job="42"] "#));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn bom() {
+        let format = |bom, text: &str| {
+            let f = Rfc5424Builder::new("", Facility::User)
+                .write_format(WriteFormat::RFC5425)
+                .bom(bom)
+                .build();
+            let msg = Rfc5424Message {
+                severity: Severity::Error,
+                structured_data: None,
+                message: Some(Message::Text(text.into())),
+            };
+            let mut out = Vec::new();
+            f.format(&mut out, &msg).unwrap();
+            out
+        };
+        let with_bom = |text: &str| {
+            let mut out = b"<11>1 - - - - - - ".to_vec();
+            out.extend(BOM.iter());
+            out.extend(text.as_bytes());
+            out
+        };
+        let framed = |msg: Vec<u8>| {
+            let mut out = format!("{} ", msg.len()).into_bytes();
+            out.extend(msg);
+            out
+        };
+
+        assert_eq!(format(Bom::Always, "plain"), framed(with_bom("plain")));
+        assert_eq!(
+            format(Bom::Never, "caf\u{e9}"),
+            framed("<11>1 - - - - - - caf\u{e9}".into())
+        );
+        assert_eq!(
+            format(Bom::NonAscii, "plain"),
+            framed(b"<11>1 - - - - - - plain".to_vec())
+        );
+        assert_eq!(
+            format(Bom::NonAscii, "caf\u{e9}"),
+            framed(with_bom("caf\u{e9}"))
+        );
+    }
 }
@@ -0,0 +1,837 @@
+//! Parsing of RFC5424 messages back into the types of this crate.
+//!
+//! [`parse`](fn.parse.html) is the inverse of [`Rfc5424::format`](../struct.Rfc5424.html#method.format).
+//! It takes the bytes of a single message, without any RFC5425 length prefix or
+//! trailing newline, and splits it into the header, structured data and message.
+//!
+//! [`parse_any`](fn.parse_any.html) is meant for raw bytes read from a socket. It
+//! detects the framing and format of the message, and parses it with either
+//! `parse` or [`rfc3164::parse`](../rfc3164/fn.parse.html).
+//!
+//! A MSG without a BOM is kept as `Message::Binary`, since its charset is
+//! unknown. Senders using a legacy charset can be decoded into text with a
+//! [`Charset`](trait.Charset.html) and
+//! [`SyslogMessage::decode_message`](struct.SyslogMessage.html#method.decode_message).
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::str;
+
+use rfc3164;
+use types::*;
+use {Rfc5424Data, StructuredData, BOM, NILVALUE};
+
+/// Errors returned when a message can't be parsed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ended before the message was complete
+    UnexpectedEnd,
+    /// The PRI is missing, or is larger than 191
+    InvalidPriority,
+    /// The VERSION is missing, or isn't a number
+    InvalidVersion,
+    /// The TIMESTAMP is neither NILVALUE or a RFC3339 timestamp
+    InvalidTimestamp,
+    /// HOSTNAME, APP-NAME, PROCID or MSGID is too long, or contains invalid characters
+    InvalidHeaderField,
+    /// The STRUCTURED-DATA is not well formed
+    InvalidStructuredData,
+    /// A PARAM-VALUE, or a MSG starting with a BOM, is not valid UTF-8
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "message ended early"),
+            ParseError::InvalidPriority => write!(f, "invalid PRI"),
+            ParseError::InvalidVersion => write!(f, "invalid VERSION"),
+            ParseError::InvalidTimestamp => write!(f, "invalid TIMESTAMP"),
+            ParseError::InvalidHeaderField => write!(f, "invalid header field"),
+            ParseError::InvalidStructuredData => write!(f, "invalid STRUCTURED-DATA"),
+            ParseError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// A single SD-ELEMENT, made of the SD-ID and its PARAM-NAME/PARAM-VALUE pairs
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SdElement {
+    pub id: String,
+    pub params: Vec<(String, String)>,
+}
+
+/// A RFC5424 message parsed back into its parts.
+///
+/// Header fields that were NILVALUE are left at their `Default` value. `message`
+/// is `Message::Text` when the MSG started with a BOM, and `Message::Binary` otherwise.
+///
+/// This implements [`Rfc5424Data`](../trait.Rfc5424Data.html), so it can be
+/// formatted again. All header fields of the message are used instead of the
+/// ones of the formatter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SyslogMessage {
+    pub facility: Facility,
+    pub severity: Severity,
+    pub version: u8,
+    pub timestamp: Option<String>,
+    pub hostname: HostName,
+    pub app_name: AppName,
+    pub pid: ProcessId,
+    pub msg_id: MessageId,
+    pub structured_data: Vec<SdElement>,
+    pub message: Option<Message>,
+}
+
+impl Rfc5424Data for SyslogMessage {
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        self.timestamp.clone()
+    }
+
+    fn structured_data(&self) -> Option<StructuredData<'_>> {
+        if self.structured_data.is_empty() {
+            return None;
+        }
+        let mut sd: StructuredData = HashMap::new();
+        for element in &self.structured_data {
+            sd.entry(element.id.as_str())
+                .or_default()
+                .extend(element.params.iter().cloned());
+        }
+        Some(sd)
+    }
+
+    fn message(&self) -> Option<Message> {
+        self.message.clone()
+    }
+
+    fn pid(&self) -> Option<ProcessId> {
+        Some(self.pid.clone())
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        Some(self.msg_id.clone())
+    }
+
+    fn app_name(&self) -> Option<AppName> {
+        Some(self.app_name.clone())
+    }
+
+    fn hostname(&self) -> Option<HostName> {
+        Some(self.hostname.clone())
+    }
+
+    fn facility(&self) -> Option<Facility> {
+        Some(self.facility)
+    }
+
+    fn ordered_structured_data(&self) -> Option<Cow<'_, [SdElement]>> {
+        if self.structured_data.is_empty() {
+            None
+        } else {
+            Some(Cow::Borrowed(&self.structured_data))
+        }
+    }
+}
+
+impl SyslogMessage {
+    /// Turn a `Message::Binary` into a `Message::Text` with `charset`. Returns
+    /// `false`, and leaves the message as is, when `charset` can't decode it.
+    pub fn decode_message(&mut self, charset: &dyn Charset) -> bool {
+        let text = match &self.message {
+            Some(Message::Binary(bytes)) => charset.decode(bytes),
+            _ => return false,
+        };
+        match text {
+            Some(text) => {
+                self.message = Some(Message::Text(text));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Decodes a MSG that isn't marked as UTF-8 by a BOM.
+///
+/// Implemented for closures, which can try several charsets in turn:
+/// ```ignore
+/// let charset = |bytes: &[u8]| {
+///     str::from_utf8(bytes).ok().map(String::from).or_else(|| Latin1.decode(bytes))
+/// };
+/// ```
+/// With the `encoding` feature, every `&'static encoding_rs::Encoding` is a
+/// charset, such as `encoding_rs::SHIFT_JIS`.
+pub trait Charset: Send + Sync {
+    /// The text of `bytes`, or `None` if they aren't valid in this charset
+    fn decode(&self, bytes: &[u8]) -> Option<String>;
+}
+
+impl<F> Charset for F
+where
+    F: Fn(&[u8]) -> Option<String> + Send + Sync,
+{
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        self(bytes)
+    }
+}
+
+/// ISO-8859-1, where every byte is the character with the same code point
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Latin1;
+
+impl Charset for Latin1 {
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        Some(bytes.iter().map(|b| char::from(*b)).collect())
+    }
+}
+
+/// Malformed input is rejected rather than replaced, and a BOM of the encoding
+/// is kept as part of the text
+#[cfg(feature = "encoding")]
+impl Charset for &'static encoding_rs::Encoding {
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        self.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|text| text.into_owned())
+    }
+}
+
+/// How a message was framed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Framing {
+    /// No framing, as in one message per datagram
+    None,
+    /// `MSG-LEN SP SYSLOG-MSG`, as written by `WriteFormat::RFC5425`
+    /// ([RFC6587 Section 3.4.1](https://tools.ietf.org/html/rfc6587#section-3.4.1))
+    OctetCounting,
+    /// `SYSLOG-MSG LF`, as written by `WriteFormat::RFC6587`
+    /// ([RFC6587 Section 3.4.2](https://tools.ietf.org/html/rfc6587#section-3.4.2))
+    NonTransparent,
+}
+
+/// Syslog message format
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Rfc5424,
+    Rfc3164,
+}
+
+/// What [`detect`](fn.detect.html) saw in the input
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub framing: Framing,
+    pub format: Format,
+    /// The octet count is larger than the rest of the input, so only the start
+    /// of the message was received
+    pub truncated: bool,
+}
+
+/// Detect the framing and format of `input`, and return them along with the
+/// message inside the framing.
+///
+/// * Input starting with a number, a space and a PRI is octet counted. Only the
+///   first message is returned, or the rest of the input when the number is
+///   larger than it, in which case the message is `truncated`.
+/// * Otherwise, input ending with a LF uses non-transparent framing. Any other
+///   LF is considered part of the message.
+/// * A message starting with a PRI and a VERSION followed by a space (`<PRI>1 `)
+///   is RFC5424, anything else is RFC3164.
+pub fn detect(input: &[u8]) -> (Detected, &[u8]) {
+    let digits = input.iter().take_while(|b| b.is_ascii_digit()).count();
+    let count = str::from_utf8(&input[..digits])
+        .ok()
+        .filter(|_| digits > 0 && input[0] != b'0' && input.get(digits) == Some(&b' '))
+        .and_then(|s| s.parse::<usize>().ok())
+        .map(|count| (count, &input[digits + 1..]))
+        // a number is only an octet count when a message follows it, and not
+        // just text such as "2 users logged in"
+        .filter(|&(_, rest)| parse_priority(&mut Cursor { buf: rest, pos: 0 }).is_ok());
+
+    let (framing, frame, truncated) = match count {
+        Some((count, rest)) if count <= rest.len() => {
+            (Framing::OctetCounting, &rest[..count], false)
+        }
+        Some((_, rest)) => (Framing::OctetCounting, rest, true),
+        None => match input.strip_suffix(b"\n") {
+            Some(frame) => (Framing::NonTransparent, frame, false),
+            None => (Framing::None, input, false),
+        },
+    };
+
+    let mut c = Cursor { buf: frame, pos: 0 };
+    let format = if parse_priority(&mut c).is_ok() && parse_version(&mut c).is_ok() {
+        Format::Rfc5424
+    } else {
+        Format::Rfc3164
+    };
+    let detected = Detected {
+        framing,
+        format,
+        truncated,
+    };
+    (detected, frame)
+}
+
+/// Parse raw bytes from a socket, whatever their framing and format.
+///
+/// See [`detect`](fn.detect.html) for how the framing and format are decided.
+///
+/// # Errors
+/// Returns `ParseError::UnexpectedEnd` when the message is `truncated`, or else the
+/// error of [`parse`](fn.parse.html) or [`rfc3164::parse`](../rfc3164/fn.parse.html),
+/// depending on the detected format.
+pub fn parse_any(input: &[u8]) -> Result<(SyslogMessage, Detected), ParseError> {
+    let (detected, frame) = detect(input);
+    if detected.truncated {
+        return Err(ParseError::UnexpectedEnd);
+    }
+    let message = match detected.format {
+        Format::Rfc5424 => parse(frame)?,
+        Format::Rfc3164 => rfc3164::parse(frame)?,
+    };
+    Ok((message, detected))
+}
+
+/// Parse a single RFC5424 message.
+///
+/// # Errors
+/// Returns a [`ParseError`](enum.ParseError.html) describing the first part of
+/// `input` that doesn't follow the
+/// [RFC5424 ABNF](https://tools.ietf.org/html/rfc5424#section-6).
+pub fn parse(input: &[u8]) -> Result<SyslogMessage, ParseError> {
+    let mut c = Cursor { buf: input, pos: 0 };
+
+    // - HEADER -
+    let (facility, severity) = parse_priority(&mut c)?;
+    let version = parse_version(&mut c)?;
+
+    let timestamp = c.field()?;
+    let timestamp = if timestamp == [NILVALUE as u8] {
+        None
+    } else if valid_timestamp(timestamp) {
+        Some(to_str(timestamp, ParseError::InvalidTimestamp)?.to_string())
+    } else {
+        return Err(ParseError::InvalidTimestamp);
+    };
+
+    let invalid = |_| ParseError::InvalidHeaderField;
+    let hostname = HostName::new(header_str(c.field()?)?).map_err(invalid)?;
+    let app_name = AppName::new(header_str(c.field()?)?).map_err(invalid)?;
+    let pid = ProcessId::new(header_str(c.field()?)?).map_err(invalid)?;
+    let msg_id = MessageId::new(header_str(c.field()?)?).map_err(invalid)?;
+
+    // - STRUCTURED-DATA -
+    let structured_data = parse_structured_data(&mut c)?;
+
+    // - MSG -
+    let message = match c.next() {
+        None => None,
+        Some(b' ') => Some(parse_msg(c.rest())?),
+        Some(_) => return Err(ParseError::InvalidStructuredData),
+    };
+
+    Ok(SyslogMessage {
+        facility,
+        severity,
+        version,
+        timestamp,
+        hostname,
+        app_name,
+        pid,
+        msg_id,
+        structured_data,
+        message,
+    })
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek();
+        if b.is_some() {
+            self.pos += 1;
+        }
+        b
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Advance while `pred` holds, returning the bytes that were skipped.
+    fn take_while<P: Fn(u8) -> bool>(&mut self, pred: P) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+        &self.buf[start..self.pos]
+    }
+
+    /// Read a space terminated header field, consuming the space.
+    fn field(&mut self) -> Result<&'a [u8], ParseError> {
+        let field = self.take_while(|b| b != b' ');
+        match self.next() {
+            Some(_) => Ok(field),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
+
+fn to_str(bytes: &[u8], err: ParseError) -> Result<&str, ParseError> {
+    str::from_utf8(bytes).map_err(|_| err)
+}
+
+fn header_str(bytes: &[u8]) -> Result<&str, ParseError> {
+    to_str(bytes, ParseError::InvalidHeaderField)
+}
+
+fn parse_priority(c: &mut Cursor) -> Result<(Facility, Severity), ParseError> {
+    if c.next() != Some(b'<') {
+        return Err(ParseError::InvalidPriority);
+    }
+    let digits = c.take_while(|b| b.is_ascii_digit());
+    if digits.is_empty() || digits.len() > 3 || c.next() != Some(b'>') {
+        return Err(ParseError::InvalidPriority);
+    }
+    let pri: u8 = to_str(digits, ParseError::InvalidPriority)?
+        .parse()
+        .map_err(|_| ParseError::InvalidPriority)?;
+    decode_priority(pri).ok_or(ParseError::InvalidPriority)
+}
+
+fn parse_version(c: &mut Cursor) -> Result<u8, ParseError> {
+    let digits = c.take_while(|b| b.is_ascii_digit());
+    if digits.is_empty() || digits[0] == b'0' {
+        return Err(ParseError::InvalidVersion);
+    }
+    let version = to_str(digits, ParseError::InvalidVersion)?
+        .parse()
+        .map_err(|_| ParseError::InvalidVersion)?;
+    match c.next() {
+        Some(b' ') => Ok(version),
+        Some(_) => Err(ParseError::InvalidVersion),
+        None => Err(ParseError::UnexpectedEnd),
+    }
+}
+
+/// Check `ts` against the TIMESTAMP rule: `YYYY-MM-DDThh:mm:ss[.frac](Z|+hh:mm|-hh:mm)`
+pub(crate) fn valid_timestamp(ts: &[u8]) -> bool {
+    const PATTERN: &[u8] = b"dddd-dd-ddTdd:dd:dd";
+    if ts.len() < PATTERN.len() {
+        return false;
+    }
+    let (date_time, mut rest) = ts.split_at(PATTERN.len());
+    let matches = date_time.iter().zip(PATTERN).all(|(b, p)| match p {
+        b'd' => b.is_ascii_digit(),
+        _ => b == p,
+    });
+    if !matches {
+        return false;
+    }
+
+    // TIME-SECFRAC
+    if rest.first() == Some(&b'.') {
+        let frac = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if frac == 0 || frac > 6 {
+            return false;
+        }
+        rest = &rest[1 + frac..];
+    }
+
+    // TIME-OFFSET
+    match rest {
+        b"Z" => true,
+        [sign, h1, h2, b':', m1, m2] => {
+            (*sign == b'+' || *sign == b'-') && [h1, h2, m1, m2].iter().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+fn parse_structured_data(c: &mut Cursor) -> Result<Vec<SdElement>, ParseError> {
+    match c.peek() {
+        Some(b) if b == NILVALUE as u8 => {
+            c.next();
+            Ok(Vec::new())
+        }
+        Some(b'[') => {
+            let mut elements = Vec::new();
+            while c.peek() == Some(b'[') {
+                elements.push(parse_sd_element(c)?);
+            }
+            Ok(elements)
+        }
+        Some(_) => Err(ParseError::InvalidStructuredData),
+        None => Err(ParseError::UnexpectedEnd),
+    }
+}
+
+fn parse_sd_element(c: &mut Cursor) -> Result<SdElement, ParseError> {
+    c.next(); // '['
+    let id = parse_sd_name(c)?;
+    let mut params = Vec::new();
+    loop {
+        match c.next() {
+            Some(b']') => break,
+            Some(b' ') => {
+                let name = parse_sd_name(c)?;
+                if c.next() != Some(b'=') || c.next() != Some(b'"') {
+                    return Err(ParseError::InvalidStructuredData);
+                }
+                params.push((name, parse_param_value(c)?));
+            }
+            Some(_) => return Err(ParseError::InvalidStructuredData),
+            None => return Err(ParseError::UnexpectedEnd),
+        }
+    }
+    Ok(SdElement { id, params })
+}
+
+/// Read a SD-NAME, used for both SD-IDs and PARAM-NAMEs.
+fn parse_sd_name(c: &mut Cursor) -> Result<String, ParseError> {
+    let name = c.take_while(|b| b.is_ascii_graphic() && b != b'=' && b != b']' && b != b'"');
+    match (name.is_empty(), c.peek()) {
+        (true, None) => Err(ParseError::UnexpectedEnd),
+        (true, Some(_)) => Err(ParseError::InvalidStructuredData),
+        (false, _) => Ok(to_str(name, ParseError::InvalidStructuredData)?.to_string()),
+    }
+}
+
+/// Read a PARAM-VALUE up to its closing quote, undoing the escaping done by `escape_val`.
+fn parse_param_value(c: &mut Cursor) -> Result<String, ParseError> {
+    let mut value = Vec::new();
+    loop {
+        match c.next() {
+            Some(b'"') => break,
+            Some(b'\\') => match c.peek() {
+                Some(b) if b == b'"' || b == b'\\' || b == b']' => {
+                    value.push(b);
+                    c.next();
+                }
+                _ => value.push(b'\\'),
+            },
+            Some(b) => value.push(b),
+            None => return Err(ParseError::UnexpectedEnd),
+        }
+    }
+    String::from_utf8(value).map_err(|_| ParseError::InvalidUtf8)
+}
+
+fn parse_msg(msg: &[u8]) -> Result<Message, ParseError> {
+    if msg.starts_with(&BOM) {
+        String::from_utf8(msg[BOM.len()..].to_vec())
+            .map(Message::Text)
+            .map_err(|_| ParseError::InvalidUtf8)
+    } else {
+        Ok(Message::Binary(msg.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Rfc5424Builder, WriteFormat};
+
+    fn with_bom(prefix: &str, msg: &str) -> Vec<u8> {
+        let mut out = prefix.as_bytes().to_vec();
+        out.extend(BOM.iter());
+        out.extend(msg.as_bytes());
+        out
+    }
+
+    #[test]
+    fn rfc_example_1() {
+        let input = with_bom(
+            "<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - ",
+            "'su root' failed for lonvick on /dev/pts/8",
+        );
+        let msg = parse(&input).unwrap();
+        assert_eq!(msg.facility, Facility::Auth);
+        assert_eq!(msg.severity, Severity::Critical);
+        assert_eq!(msg.version, 1);
+        assert_eq!(msg.timestamp, Some("2003-10-11T22:14:15.003Z".into()));
+        assert_eq!(
+            msg.hostname,
+            HostName::new("mymachine.example.com").unwrap()
+        );
+        assert_eq!(msg.app_name, AppName::new("su").unwrap());
+        assert_eq!(msg.pid, ProcessId::default());
+        assert_eq!(msg.msg_id, MessageId::new("ID47").unwrap());
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(
+            msg.message,
+            Some(Message::Text(
+                "'su root' failed for lonvick on /dev/pts/8".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn rfc_example_2() {
+        let input = b"<165>1 2003-08-24T05:14:15.000003-07:00 192.0.2.1 myproc 8710 - - %% It's time to make the do-nuts.";
+        let msg = parse(input).unwrap();
+        assert_eq!(msg.facility, Facility::Local4);
+        assert_eq!(msg.severity, Severity::Notice);
+        assert_eq!(msg.pid, ProcessId::new("8710").unwrap());
+        assert_eq!(
+            msg.message,
+            Some(Message::Binary(
+                b"%% It's time to make the do-nuts.".to_vec()
+            ))
+        );
+    }
+
+    #[test]
+    fn rfc_example_structured_data() {
+        let input = br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="Application" eventID="1011"][examplePriority@32473 class="high"]"#;
+        let msg = parse(input).unwrap();
+        assert_eq!(
+            msg.structured_data,
+            vec![
+                SdElement {
+                    id: "exampleSDID@32473".into(),
+                    params: vec![
+                        ("iut".into(), "3".into()),
+                        ("eventSource".into(), "Application".into()),
+                        ("eventID".into(), "1011".into()),
+                    ],
+                },
+                SdElement {
+                    id: "examplePriority@32473".into(),
+                    params: vec![("class".into(), "high".into())],
+                },
+            ]
+        );
+        assert_eq!(msg.message, None);
+    }
+
+    #[test]
+    fn escaped_param_values() {
+        let input = br#"<14>1 - - - - - [a@1 b="quote\" slash\\ bracket\] other\n"]"#;
+        let msg = parse(input).unwrap();
+        assert_eq!(
+            msg.structured_data[0].params[0].1,
+            r#"quote" slash\ bracket] other\n"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut hmap: StructuredData = HashMap::new();
+        hmap.insert("hello", vec![("id".into(), r#"a"b]c\d"#.into())]);
+        let original = SyslogMessage {
+            facility: Facility::Daemon,
+            severity: Severity::Warning,
+            version: 1,
+            timestamp: None,
+            hostname: HostName::new("server1.example.com").unwrap(),
+            app_name: AppName::new("my_app").unwrap(),
+            pid: ProcessId::new("5445").unwrap(),
+            msg_id: MessageId::new("TCPIN").unwrap(),
+            structured_data: vec![SdElement {
+                id: "hello@ent_id".into(),
+                params: vec![("id".into(), r#"a"b]c\d"#.into())],
+            }],
+            message: Some(Message::Text("sample message".into())),
+        };
+        let f = Rfc5424Builder::new("ent_id", Facility::Daemon)
+            .hostname("server1.example.com")
+            .unwrap()
+            .app_name("my_app")
+            .unwrap()
+            .pid("5445")
+            .unwrap()
+            .msg_id("TCPIN")
+            .unwrap()
+            .write_format(WriteFormat::RFC5424)
+            .build();
+
+        struct Data<'a>(StructuredData<'a>);
+        impl<'a> Rfc5424Data for Data<'a> {
+            fn severity(&self) -> Severity {
+                Severity::Warning
+            }
+            fn timestamp(&self) -> Option<String> {
+                None
+            }
+            fn structured_data(&self) -> Option<StructuredData<'_>> {
+                Some(self.0.clone())
+            }
+            fn message(&self) -> Option<Message> {
+                Some(Message::Text("sample message".into()))
+            }
+        }
+
+        let mut out = Vec::new();
+        f.format(&mut out, &Data(hmap)).unwrap();
+        assert_eq!(parse(&out).unwrap(), original);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse(b""), Err(ParseError::InvalidPriority));
+        assert_eq!(ParseError::InvalidPriority.to_string(), "invalid PRI");
+        assert_eq!(
+            parse(b"<192>1 - - - - - -"),
+            Err(ParseError::InvalidPriority)
+        );
+        assert_eq!(parse(b"<14>0 - - - - - -"), Err(ParseError::InvalidVersion));
+        assert_eq!(parse(b"<14>1 - - - - -"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            parse(b"<14>1 2003-10-11 - - - - -"),
+            Err(ParseError::InvalidTimestamp)
+        );
+        assert_eq!(
+            parse("<14>1 - h\u{e9}st - - - -".as_bytes()),
+            Err(ParseError::InvalidHeaderField)
+        );
+        assert_eq!(
+            parse(br#"<14>1 - - - - - [a b=c]"#),
+            Err(ParseError::InvalidStructuredData)
+        );
+        assert_eq!(
+            parse(br#"<14>1 - - - - - [a b="c"#),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse(b"<14>1 - - - - - - \xEF\xBB\xBF\xFF"),
+            Err(ParseError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn detection() {
+        let detected = |input: &[u8]| {
+            let (d, _) = detect(input);
+            (d.framing, d.format)
+        };
+        assert_eq!(
+            detected(b"<34>1 - - - - - -"),
+            (Framing::None, Format::Rfc5424)
+        );
+        assert_eq!(
+            detected(b"17 <34>1 - - - - - -"),
+            (Framing::OctetCounting, Format::Rfc5424)
+        );
+        assert_eq!(
+            detected(b"<34>1 - - - - - -\n"),
+            (Framing::NonTransparent, Format::Rfc5424)
+        );
+        assert_eq!(
+            detected(b"<34>Oct 11 22:14:15 mymachine su: hi"),
+            (Framing::None, Format::Rfc3164)
+        );
+        assert_eq!(
+            detected(b"<34>Oct 11 22:14:15 mymachine su: hi\n"),
+            (Framing::NonTransparent, Format::Rfc3164)
+        );
+        // a Cisco sequence number is not an octet count
+        assert_eq!(
+            detected(b"123: *Mar  1 18:46:11: %SYS-5-CONFIG_I: hi"),
+            (Framing::None, Format::Rfc3164)
+        );
+        // a number larger than the input counts the octets of a truncated message
+        let (d, frame) = detect(b"99 <34>1 - - - - - -");
+        assert_eq!(
+            (d.framing, d.format),
+            (Framing::OctetCounting, Format::Rfc5424)
+        );
+        assert!(d.truncated);
+        assert_eq!(frame, b"<34>1 - - - - - -");
+        // unless no message follows it
+        assert_eq!(detected(b"99 bottles"), (Framing::None, Format::Rfc3164));
+        // whatever its length
+        let (d, frame) = detect(b"2 users logged in\n");
+        assert_eq!(
+            (d.framing, d.format),
+            (Framing::NonTransparent, Format::Rfc3164)
+        );
+        assert_eq!(frame, b"2 users logged in");
+        assert_eq!(
+            detected(b"2 users logged in"),
+            (Framing::None, Format::Rfc3164)
+        );
+        assert!(!detect(b"17 <34>1 - - - - - -").0.truncated);
+    }
+
+    #[test]
+    fn parse_any_dispatches() {
+        let (msg, detected) = parse_any(b"20 <34>1 - host - - - -").unwrap();
+        assert_eq!(detected.framing, Framing::OctetCounting);
+        assert_eq!(detected.format, Format::Rfc5424);
+        assert_eq!(msg.hostname, HostName::new("host").unwrap());
+
+        let (msg, detected) = parse_any(b"<34>Oct 11 22:14:15 host su: hi\n").unwrap();
+        assert_eq!(detected.framing, Framing::NonTransparent);
+        assert_eq!(detected.format, Format::Rfc3164);
+        assert_eq!(msg.app_name, AppName::new("su").unwrap());
+        assert_eq!(msg.message, Some(Message::Text("hi".into())));
+
+        assert_eq!(
+            parse_any(b"<34>1 - - - - - [x"),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_any(b"99 <34>1 - host - - - -"),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        let msg = parse(br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3"] An application event"#).unwrap();
+        let json = ::serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["facility"], "local4");
+        assert_eq!(json["severity"], "notice");
+        assert_eq!(json["hostname"], "mymachine.example.com");
+        assert_eq!(json["pid"], "-");
+        assert_eq!(json["structured_data"][0]["id"], "exampleSDID@32473");
+        assert_eq!(json["structured_data"][0]["params"][0][1], "3");
+        assert_eq!(::serde_json::from_value::<SyslogMessage>(json).unwrap(), msg);
+    }
+
+    #[test]
+    fn charsets() {
+        let mut msg = parse(b"<13>1 - - - - - - caf\xe9").unwrap();
+        assert_eq!(msg.message, Some(Message::Binary(b"caf\xe9".to_vec())));
+        let utf8 = |bytes: &[u8]| str::from_utf8(bytes).ok().map(String::from);
+        assert!(!msg.decode_message(&utf8));
+        assert!(msg.decode_message(&Latin1));
+        assert_eq!(msg.message, Some(Message::Text("caf\u{e9}".into())));
+        // text is left alone
+        assert!(!msg.decode_message(&Latin1));
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn shift_jis() {
+        // "tesuto" in katakana
+        let mut msg = parse(b"<13>1 - - - - - - \x83\x65\x83\x58\x83\x67").unwrap();
+        assert!(msg.decode_message(&encoding_rs::SHIFT_JIS));
+        assert_eq!(
+            msg.message,
+            Some(Message::Text("\u{30c6}\u{30b9}\u{30c8}".into()))
+        );
+        let mut invalid = parse(b"<13>1 - - - - - - \x83").unwrap();
+        assert!(!invalid.decode_message(&encoding_rs::SHIFT_JIS));
+    }
+}
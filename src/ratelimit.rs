@@ -0,0 +1,613 @@
+//! Rate limiting and suppression of repeated messages.
+//!
+//! A [`RateLimiter`](struct.RateLimiter.html) wraps a
+//! [`Sender`](../sender/struct.Sender.html), and protects the receiver from
+//! code that logs in a loop:
+//!
+//! * Messages are limited with a token bucket per APP-NAME, MSGID and severity.
+//!   When limiting kicks in, the first dropped message is replaced by a message
+//!   with a `ratelimit` SD-ELEMENT holding the number of dropped messages. The
+//!   messages dropped after it are counted too, and reported by whichever comes
+//!   first: the next message of that key that gets through, the end of the
+//!   [`repeat_interval`](struct.RateLimiter.html#method.repeat_interval), or
+//!   [`flush`](struct.RateLimiter.html#method.flush).
+//! * Identical consecutive messages are sent once. When a different message
+//!   arrives, the repeats are summarized with a "last message repeated N times"
+//!   message, which has a `repeat` SD-ELEMENT with the count. Summaries are also
+//!   sent once the repeat interval has passed, and by `flush`.
+//!
+//! There is no timer: summaries that are due are sent by the next call to
+//! `send`, whatever its key, or by `flush`.
+//!
+//! The summaries have their own SD-IDs, qualified with the enterprise ID of the
+//! formatter, because parameters can't be added to the IANA `meta` SD-ID.
+//!
+//! # Examples
+//! ```ignore
+//! let sender = Sender::new(formatter, socket);
+//! // at most 100 messages every 10 seconds, per key
+//! let mut limited = RateLimiter::new(sender, RateLimit::new(100, Duration::from_secs(10)));
+//! limited.send(&message)?;
+//! // at shutdown, send the summaries of anything pending
+//! limited.flush()?;
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use parser::SdElement;
+use sender::{Sender, Transport};
+use types::*;
+use {Header, Rfc5424Data, StructuredData};
+
+/// Source of the current time. Implemented for functions returning an `Instant`,
+/// such as `Instant::now`, so tests can control time.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+impl<F: Fn() -> Instant> Clock for F {
+    fn now(&self) -> Instant {
+        self()
+    }
+}
+
+/// Number of messages allowed in an interval. Up to `burst` messages are sent
+/// at once, after which messages are let through at the rate of `burst` per
+/// `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, interval: Duration) -> RateLimit {
+        RateLimit { burst, interval }
+    }
+
+    /// Tokens added to a bucket in `elapsed`
+    fn refill(&self, elapsed: Duration) -> f64 {
+        if self.interval == Duration::from_secs(0) {
+            return f64::from(self.burst);
+        }
+        elapsed.as_secs_f64() * f64::from(self.burst) / self.interval.as_secs_f64()
+    }
+}
+
+/// Buckets of full keys are forgotten once there are more than this many
+const MAX_BUCKETS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    app_name: AppName,
+    msg_id: MessageId,
+    severity: Severity,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Messages dropped since the last drop counter message
+    dropped: u64,
+    /// When the last drop counter message was sent, while the key is limited
+    limited: Option<Instant>,
+    /// Header of the last dropped message, used for the drop counter message
+    header: Header,
+}
+
+/// What makes two messages identical. SD-ELEMENTs are sorted, since the order
+/// of `StructuredData` isn't stable.
+#[derive(Debug, PartialEq)]
+struct Identity {
+    header: Header,
+    severity: Severity,
+    message: Option<Message>,
+    structured_data: Vec<SdElement>,
+}
+
+#[derive(Debug)]
+struct Repeats {
+    last: Identity,
+    count: u64,
+    since: Instant,
+}
+
+/// Sends messages through a [`Sender`](../sender/struct.Sender.html), limiting
+/// their rate. See the [module documentation](index.html).
+pub struct RateLimiter<T, C = fn() -> Instant> {
+    sender: Sender<T>,
+    clock: C,
+    limit: RateLimit,
+    buckets: HashMap<Key, Bucket>,
+    suppress_repeats: bool,
+    repeat_interval: Duration,
+    repeats: Option<Repeats>,
+    /// When the earliest pending drop counter is due, if there is one
+    drops_due: Option<Instant>,
+}
+
+impl<T: Transport> RateLimiter<T> {
+    pub fn new(sender: Sender<T>, limit: RateLimit) -> RateLimiter<T> {
+        RateLimiter {
+            sender,
+            clock: Instant::now,
+            limit,
+            buckets: HashMap::new(),
+            suppress_repeats: true,
+            repeat_interval: Duration::from_secs(30),
+            repeats: None,
+            drops_due: None,
+        }
+    }
+}
+
+impl<T: Transport, C: Clock> RateLimiter<T, C> {
+    /// Use `clock` as the source of time, instead of `Instant::now`
+    pub fn clock<C2: Clock>(self, clock: C2) -> RateLimiter<T, C2> {
+        RateLimiter {
+            sender: self.sender,
+            clock,
+            limit: self.limit,
+            buckets: self.buckets,
+            suppress_repeats: self.suppress_repeats,
+            repeat_interval: self.repeat_interval,
+            repeats: self.repeats,
+            drops_due: self.drops_due,
+        }
+    }
+
+    /// Enable or disable the suppression of identical consecutive messages.
+    /// Enabled by default.
+    pub fn suppress_repeats(mut self, enabled: bool) -> Self {
+        self.suppress_repeats = enabled;
+        self
+    }
+
+    /// How often a summary is sent while a message keeps being repeated, or
+    /// messages of a key keep being dropped. Defaults to 30 seconds.
+    pub fn repeat_interval(mut self, interval: Duration) -> Self {
+        self.repeat_interval = interval;
+        self
+    }
+
+    pub fn sender(&self) -> &Sender<T> {
+        &self.sender
+    }
+
+    /// Give back the wrapped sender, without sending pending summaries
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+
+    /// Send `data`, unless it's a repeat of the last message or its key is over
+    /// the rate limit.
+    ///
+    /// # Errors
+    /// Errors when the transport returns an error (`io::Error`)
+    pub fn send(&mut self, data: &impl Rfc5424Data) -> io::Result<()> {
+        let now = self.clock.now();
+        self.send_due(now)?;
+        if !self.sender.allows(data) {
+            return Ok(());
+        }
+        let formatter = self.sender.formatter();
+        let header = formatter.header(data);
+
+        let identity = if self.suppress_repeats {
+            let mut structured_data = formatter.sd_elements(data);
+            structured_data.sort_by(|a, b| a.id.cmp(&b.id));
+            let identity = Identity {
+                header: header.clone(),
+                severity: data.severity(),
+                message: data.message(),
+                structured_data,
+            };
+            if let Some(repeats) = &mut self.repeats {
+                if repeats.last == identity {
+                    repeats.count += 1;
+                    return Ok(());
+                }
+            }
+            self.send_repeats(now)?;
+            Some(identity)
+        } else {
+            None
+        };
+
+        let key = Key {
+            app_name: header.app_name.clone(),
+            msg_id: header.msg_id.clone(),
+            severity: data.severity(),
+        };
+        if self.buckets.len() > MAX_BUCKETS {
+            let limit = self.limit;
+            self.buckets.retain(|_, bucket| {
+                bucket.dropped > 0
+                    || bucket.tokens + limit.refill(now.saturating_duration_since(bucket.updated))
+                        < f64::from(limit.burst)
+            });
+        }
+        let limit = self.limit;
+        let bucket = self.buckets.entry(key).or_insert_with(|| Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+            dropped: 0,
+            limited: None,
+            header: header.clone(),
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + limit.refill(elapsed)).min(f64::from(limit.burst));
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            match bucket.limited {
+                // limiting just kicked in
+                None => {
+                    bucket.limited = Some(now);
+                    return self
+                        .sender
+                        .send(&dropped_notice(&header, data.severity(), 1));
+                }
+                Some(since) => {
+                    bucket.dropped += 1;
+                    bucket.header = header;
+                    let due = since + self.repeat_interval;
+                    self.drops_due = Some(self.drops_due.map_or(due, |d| d.min(due)));
+                    return Ok(());
+                }
+            }
+        }
+        bucket.tokens -= 1.0;
+
+        let dropped = bucket.dropped;
+        bucket.dropped = 0;
+        bucket.limited = None;
+        if dropped > 0 {
+            self.sender
+                .send(&dropped_notice(&header, data.severity(), dropped))?;
+        }
+
+        self.sender.send(data)?;
+        if let Some(last) = identity {
+            self.repeats = Some(Repeats {
+                last,
+                count: 0,
+                since: now,
+            });
+        }
+        Ok(())
+    }
+
+    /// Send the summaries of repeated and dropped messages that are pending.
+    ///
+    /// # Errors
+    /// Errors when the transport returns an error (`io::Error`)
+    pub fn flush(&mut self) -> io::Result<()> {
+        let now = self.clock.now();
+        self.send_repeats(now)?;
+        self.send_dropped(now, true)
+    }
+
+    /// Send the summaries whose repeat interval has passed
+    fn send_due(&mut self, now: Instant) -> io::Result<()> {
+        let interval = self.repeat_interval;
+        if let Some(repeats) = &self.repeats {
+            if now.saturating_duration_since(repeats.since) >= interval {
+                self.send_repeats(now)?;
+            }
+        }
+        match self.drops_due {
+            Some(due) if due <= now => self.send_dropped(now, false),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send the drop counters of the keys that dropped messages since their
+    /// last one, or only of those that are due unless `all` is set
+    fn send_dropped(&mut self, now: Instant, all: bool) -> io::Result<()> {
+        let interval = self.repeat_interval;
+        let is_due = |bucket: &Bucket| {
+            all || bucket
+                .limited
+                .is_none_or(|since| now.saturating_duration_since(since) >= interval)
+        };
+        let mut pending = Vec::new();
+        let mut drops_due: Option<Instant> = None;
+        for (key, bucket) in self.buckets.iter_mut().filter(|(_, b)| b.dropped > 0) {
+            if is_due(bucket) {
+                pending.push(dropped_notice(&bucket.header, key.severity, bucket.dropped));
+                bucket.dropped = 0;
+                bucket.limited = Some(now);
+            } else if let Some(since) = bucket.limited {
+                let due = since + interval;
+                drops_due = Some(drops_due.map_or(due, |d| d.min(due)));
+            }
+        }
+        self.drops_due = drops_due;
+        pending.sort_by(|a, b| a.text.cmp(&b.text));
+        for notice in &pending {
+            self.sender.send(notice)?;
+        }
+        Ok(())
+    }
+
+    /// Send the summary of the repeats of the last message, if there were any
+    fn send_repeats(&mut self, now: Instant) -> io::Result<()> {
+        let repeats = match &mut self.repeats {
+            Some(repeats) if repeats.count > 0 => repeats,
+            _ => return Ok(()),
+        };
+        let notice = Notice {
+            header: repeats.last.header.clone(),
+            severity: repeats.last.severity,
+            text: format!("last message repeated {} times", repeats.count),
+            sd_id: "repeat",
+            param: ("count", repeats.count),
+        };
+        repeats.count = 0;
+        repeats.since = now;
+        self.sender.send(&notice)
+    }
+}
+
+fn dropped_notice(header: &Header, severity: Severity, dropped: u64) -> Notice {
+    Notice {
+        header: header.clone(),
+        severity,
+        text: format!("{} messages dropped by rate limit", dropped),
+        sd_id: "ratelimit",
+        param: ("dropped", dropped),
+    }
+}
+
+/// A message sent by the rate limiter itself, with the header of the messages
+/// it's about
+struct Notice {
+    header: Header,
+    severity: Severity,
+    text: String,
+    sd_id: &'static str,
+    param: (&'static str, u64),
+}
+
+impl Rfc5424Data for Notice {
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        None
+    }
+
+    fn structured_data(&self) -> Option<StructuredData<'_>> {
+        let (name, val) = self.param;
+        let mut sd = StructuredData::new();
+        sd.insert(self.sd_id, vec![(name.to_string(), val.to_string())]);
+        Some(sd)
+    }
+
+    fn message(&self) -> Option<Message> {
+        Some(Message::Text(self.text.clone()))
+    }
+
+    fn pid(&self) -> Option<ProcessId> {
+        Some(self.header.pid.clone())
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        Some(self.header.msg_id.clone())
+    }
+
+    fn app_name(&self) -> Option<AppName> {
+        Some(self.header.app_name.clone())
+    }
+
+    fn hostname(&self) -> Option<HostName> {
+        Some(self.header.hostname.clone())
+    }
+
+    fn facility(&self) -> Option<Facility> {
+        Some(self.header.facility)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse, SyslogMessage};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use {Rfc5424Builder, WriteFormat};
+
+    struct Event {
+        msg_id: &'static str,
+        text: &'static str,
+    }
+
+    impl Rfc5424Data for Event {
+        fn severity(&self) -> Severity {
+            Severity::Error
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.text.into()))
+        }
+
+        fn msg_id(&self) -> Option<MessageId> {
+            Some(MessageId::new(self.msg_id).unwrap())
+        }
+    }
+
+    fn event(msg_id: &'static str, text: &'static str) -> Event {
+        Event { msg_id, text }
+    }
+
+    type Limiter = RateLimiter<Vec<Vec<u8>>, Box<dyn Fn() -> Instant>>;
+
+    /// A rate limiter with a clock that only moves when the returned cell is changed
+    fn limiter(limit: RateLimit) -> (Limiter, Rc<Cell<Instant>>) {
+        let formatter = Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("app")
+            .unwrap()
+            .write_format(WriteFormat::RFC5424)
+            .build();
+        let now = Rc::new(Cell::new(Instant::now()));
+        let clock = now.clone();
+        let limiter = RateLimiter::new(Sender::new(formatter, Vec::new()), limit)
+            .clock(Box::new(move || clock.get()) as Box<dyn Fn() -> Instant>);
+        (limiter, now)
+    }
+
+    fn advance(now: &Cell<Instant>, secs: u64) {
+        now.set(now.get() + Duration::from_secs(secs));
+    }
+
+    fn sent(limiter: &Limiter) -> Vec<SyslogMessage> {
+        limiter
+            .sender()
+            .transport()
+            .iter()
+            .map(|m| parse(m).unwrap())
+            .collect()
+    }
+
+    fn text(message: &SyslogMessage) -> String {
+        match &message.message {
+            Some(Message::Text(t)) => t.clone(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn sent_texts(limiter: &Limiter) -> Vec<String> {
+        sent(limiter).iter().map(text).collect()
+    }
+
+    #[test]
+    fn token_bucket_per_key() {
+        let (mut limiter, now) = limiter(RateLimit::new(2, Duration::from_secs(10)));
+        for text in &["a", "b", "c", "d", "e"] {
+            limiter.send(&event("FLOOD", text)).unwrap();
+        }
+        // other keys have their own bucket
+        limiter.send(&event("OTHER", "x")).unwrap();
+        // the first dropped message is reported right away
+        let texts = sent_texts(&limiter);
+        assert_eq!(
+            texts,
+            vec!["a", "b", "1 messages dropped by rate limit", "x"]
+        );
+
+        // one token comes back every 5 seconds
+        advance(&now, 5);
+        limiter.send(&event("FLOOD", "f")).unwrap();
+        let sent = sent(&limiter);
+        assert_eq!(sent.len(), 6);
+        assert_eq!(text(&sent[4]), "2 messages dropped by rate limit");
+        assert_eq!(sent[4].msg_id, MessageId::new("FLOOD").unwrap());
+        assert_eq!(sent[4].structured_data[0].id, "ratelimit@32473");
+        assert_eq!(
+            sent[4].structured_data[0].params,
+            vec![("dropped".into(), "2".into())]
+        );
+        assert_eq!(text(&sent[5]), "f");
+    }
+
+    #[test]
+    fn flush_reports_drops() {
+        let (mut limiter, _) = limiter(RateLimit::new(1, Duration::from_secs(60)));
+        for text in &["1", "2", "3", "4"] {
+            limiter.send(&event("A", text)).unwrap();
+        }
+        limiter.flush().unwrap();
+        assert_eq!(
+            sent_texts(&limiter),
+            vec![
+                "1",
+                "1 messages dropped by rate limit",
+                "2 messages dropped by rate limit"
+            ]
+        );
+
+        // nothing left to report
+        limiter.flush().unwrap();
+        assert_eq!(limiter.sender().transport().len(), 3);
+    }
+
+    #[test]
+    fn reports_drops_when_due() {
+        let (mut limiter, now) = limiter(RateLimit::new(1, Duration::from_secs(3600)));
+        for text in &["1", "2", "3", "4"] {
+            limiter.send(&event("A", text)).unwrap();
+        }
+        advance(&now, 29);
+        limiter.send(&event("B", "b")).unwrap();
+        assert_eq!(sent_texts(&limiter).len(), 3);
+
+        // the flood of A is over, and a message of any key sends its counter
+        advance(&now, 1);
+        limiter.send(&event("C", "c")).unwrap();
+        let texts = sent_texts(&limiter);
+        assert_eq!(texts.len(), 5);
+        assert_eq!(texts[3], "2 messages dropped by rate limit");
+
+        // while A is still limited, its drops are reported every interval
+        limiter.send(&event("A", "5")).unwrap();
+        advance(&now, 30);
+        limiter.send(&event("D", "d")).unwrap();
+        let texts = sent_texts(&limiter);
+        assert_eq!(texts.len(), 7);
+        assert_eq!(texts[5], "1 messages dropped by rate limit");
+    }
+
+    #[test]
+    fn repeats() {
+        let (mut limiter, now) = limiter(RateLimit::new(100, Duration::from_secs(1)));
+        for _ in 0..5 {
+            limiter.send(&event("X", "same")).unwrap();
+        }
+        limiter.send(&event("X", "different")).unwrap();
+
+        assert_eq!(
+            sent_texts(&limiter),
+            vec!["same", "last message repeated 4 times", "different"]
+        );
+        let sent = sent(&limiter);
+        assert_eq!(
+            sent[1].structured_data[0],
+            SdElement {
+                id: "repeat@32473".into(),
+                params: vec![("count".into(), "4".into())],
+            }
+        );
+
+        // long runs of repeats are summarized once the interval has passed
+        limiter.send(&event("X", "different")).unwrap();
+        limiter.send(&event("X", "different")).unwrap();
+        advance(&now, 30);
+        limiter.send(&event("X", "different")).unwrap();
+        let texts = sent_texts(&limiter);
+        assert_eq!(texts.len(), 4);
+        assert_eq!(texts[3], "last message repeated 2 times");
+    }
+
+    #[test]
+    fn repeats_disabled() {
+        let (limiter, _) = limiter(RateLimit::new(100, Duration::from_secs(1)));
+        let mut limiter = limiter.suppress_repeats(false);
+        for _ in 0..3 {
+            limiter.send(&event("X", "same")).unwrap();
+        }
+        limiter.flush().unwrap();
+        assert_eq!(sent_texts(&limiter), vec!["same", "same", "same"]);
+    }
+}
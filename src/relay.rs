@@ -0,0 +1,210 @@
+//! Forwarding of parsed messages to another syslog receiver.
+//!
+//! A [`Relay`](struct.Relay.html) formats a
+//! [`SyslogMessage`](../parser/struct.SyslogMessage.html) again with
+//! [`Rfc5424::format`](../struct.Rfc5424.html#method.format), keeping its original
+//! header fields unless told otherwise. Its SD-ELEMENTs are written in the order
+//! they were received, so signatures over them still verify. The output can use a different
+//! [`WriteFormat`](../enum.WriteFormat.html) than the one the message arrived in.
+//!
+//! # Examples
+//! Forward everything received over UDP to a central server over TCP:
+//! ```ignore
+//! let relay = Relay::new(WriteFormat::RFC5425).origin_ip("192.0.2.10".parse()?);
+//! let upstream = Mutex::new(TcpStream::connect("logs.example.com:601")?);
+//! let server = Server::new(move |r: Received| {
+//!     if let Err(e) = relay.relay(&mut *upstream.lock().unwrap(), &r.message) {
+//!         eprintln!("forwarding failed: {}", e);
+//!     }
+//! });
+//! server.serve_udp(&UdpSocket::bind("0.0.0.0:514")?)?;
+//! ```
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+use parser::{SdElement, SyslogMessage};
+use types::HostName;
+use {Error, Rfc5424, WriteFormat};
+
+/// Re-emits parsed messages. See the [module documentation](index.html).
+#[derive(Debug, Clone)]
+pub struct Relay {
+    write_format: WriteFormat,
+    hostname: Option<HostName>,
+    origin_ip: Option<IpAddr>,
+}
+
+impl Relay {
+    /// Create a relay that writes messages in `write_format`, keeping every
+    /// header field of the original messages.
+    pub fn new(write_format: WriteFormat) -> Relay {
+        Relay {
+            write_format,
+            hostname: None,
+            origin_ip: None,
+        }
+    }
+
+    /// Replace the HOSTNAME of every relayed message with `val`.
+    ///
+    /// # Errors
+    /// * `val`'s length is larger than 255
+    /// * `val` is an empty string
+    /// * `val` doesn't contain printable ASCII characters (see `char::is_ascii_graphic`)
+    pub fn hostname(mut self, val: &str) -> Result<Self, Error> {
+        self.hostname = Some(HostName::new(val)?);
+        Ok(self)
+    }
+
+    /// Add `ip` as an `ip` parameter of the `origin` SD-ELEMENT of every relayed
+    /// message.
+    ///
+    /// An SD-ID can only appear once in a message, so if the message already has
+    /// an `origin` element the parameter is appended to it, as described for
+    /// multihomed hosts in [RFC5424 Section 7.2.1](https://tools.ietf.org/html/rfc5424#section-7.2.1).
+    pub fn origin_ip(mut self, ip: IpAddr) -> Self {
+        self.origin_ip = Some(ip);
+        self
+    }
+
+    /// Format `message` according to the relay's settings, and write it using `writer`.
+    ///
+    /// # Errors
+    /// Errors when `writer` returns an error (`io::Error`)
+    pub fn relay<W: Write>(&self, writer: &mut W, message: &SyslogMessage) -> io::Result<()> {
+        let formatter = Rfc5424 {
+            version: message.version,
+            write_format: self.write_format,
+            ..Default::default()
+        };
+
+        // the header fields of the message are used instead of the formatter's,
+        // so overrides are applied to a copy of it
+        let mut message = Cow::Borrowed(message);
+        if let Some(hostname) = &self.hostname {
+            message.to_mut().hostname = hostname.clone();
+        }
+        if let Some(ip) = self.origin_ip {
+            add_origin_ip(message.to_mut(), ip);
+        }
+        formatter.format(writer, &*message)
+    }
+}
+
+fn add_origin_ip(message: &mut SyslogMessage, ip: IpAddr) {
+    let param = ("ip".to_string(), ip.to_string());
+    match message
+        .structured_data
+        .iter_mut()
+        .find(|element| element.id == "origin")
+    {
+        Some(origin) => origin.params.push(param),
+        None => message.structured_data.push(SdElement {
+            id: "origin".into(),
+            params: vec![param],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use types::*;
+
+    const ORIGINAL: &[u8] = br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 1234 ID47 [exampleSDID@32473 iut="3"] An application event"#;
+
+    fn relay(relay: &Relay, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        relay.relay(&mut out, &parse(input).unwrap()).unwrap();
+        out
+    }
+
+    #[test]
+    fn keeps_original_message() {
+        assert_eq!(relay(&Relay::new(WriteFormat::RFC5424), ORIGINAL), ORIGINAL);
+    }
+
+    #[test]
+    fn keeps_element_order() {
+        // repeated SD-IDs aren't allowed by RFC5424, but are relayed as received
+        let original: &[u8] = br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 1234 ID47 [origin ip="192.0.2.1"][exampleSDID@32473 iut="3"][meta sequenceId="7"][exampleSDID@32473 eventID="1011"] An application event"#;
+        assert_eq!(relay(&Relay::new(WriteFormat::RFC5424), original), original);
+
+        let r = Relay::new(WriteFormat::RFC5424).origin_ip("192.0.2.10".parse().unwrap());
+        let ids: Vec<_> = parse(&relay(&r, original))
+            .unwrap()
+            .structured_data
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(
+            ids,
+            vec!["origin", "exampleSDID@32473", "meta", "exampleSDID@32473"]
+        );
+    }
+
+    #[test]
+    fn reframes() {
+        let mut octet_counted = format!("{} ", ORIGINAL.len()).into_bytes();
+        octet_counted.extend(ORIGINAL);
+        assert_eq!(
+            relay(&Relay::new(WriteFormat::RFC5425), ORIGINAL),
+            octet_counted
+        );
+
+        let mut lf = ORIGINAL.to_vec();
+        lf.push(b'\n');
+        assert_eq!(relay(&Relay::new(WriteFormat::RFC6587), ORIGINAL), lf);
+    }
+
+    #[test]
+    fn overrides_hostname() {
+        let r = Relay::new(WriteFormat::RFC5424)
+            .hostname("relay1.example.com")
+            .unwrap();
+        let relayed = parse(&relay(&r, ORIGINAL)).unwrap();
+        assert_eq!(
+            relayed.hostname,
+            HostName::new("relay1.example.com").unwrap()
+        );
+        assert_eq!(relayed.app_name, AppName::new("evntslog").unwrap());
+        assert_eq!(relayed.pid, ProcessId::new("1234").unwrap());
+        assert_eq!(relayed.msg_id, MessageId::new("ID47").unwrap());
+        assert_eq!(
+            relayed.timestamp,
+            Some("2003-10-11T22:14:15.003Z".to_string())
+        );
+    }
+
+    #[test]
+    fn adds_origin_ip() {
+        let r = Relay::new(WriteFormat::RFC5424).origin_ip("192.0.2.10".parse().unwrap());
+        let relayed = parse(&relay(&r, ORIGINAL)).unwrap();
+        let origin = relayed
+            .structured_data
+            .iter()
+            .find(|e| e.id == "origin")
+            .unwrap();
+        assert_eq!(origin.params, vec![("ip".into(), "192.0.2.10".into())]);
+
+        // a second hop adds to the existing origin element
+        let r2 = Relay::new(WriteFormat::RFC5424).origin_ip("192.0.2.20".parse().unwrap());
+        let relayed = parse(&relay(&r2, &relay(&r, ORIGINAL))).unwrap();
+        let origins: Vec<_> = relayed
+            .structured_data
+            .iter()
+            .filter(|e| e.id == "origin")
+            .collect();
+        assert_eq!(origins.len(), 1);
+        assert_eq!(
+            origins[0].params,
+            vec![
+                ("ip".into(), "192.0.2.10".into()),
+                ("ip".into(), "192.0.2.20".into())
+            ]
+        );
+    }
+}
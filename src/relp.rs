@@ -0,0 +1,649 @@
+//! The Reliable Event Logging Protocol of rsyslog
+//! ([RELP](https://github.com/rsyslog/librelp/blob/master/doc/relp.html)).
+//!
+//! RELP runs over TCP, and numbers every message it sends. The receiver
+//! answers each of them with a `rsp` frame once it has taken the message, so
+//! the sender knows which messages were lost when a connection breaks.
+//!
+//! [`RelpTransport`](struct.RelpTransport.html) sends the messages of a
+//! [`Sender`](../sender/struct.Sender.html) as `syslog` commands. It keeps up
+//! to a window of unacknowledged messages, and sends them all again after it
+//! reconnects, so every message is delivered at least once.
+//! [`serve_connection`](fn.serve_connection.html) serves a single session, and
+//! [`Server::serve_relp`](../server/struct.Server.html#method.serve_relp)
+//! accepts RELP sessions in a collector.
+//!
+//! # Examples
+//! ```ignore
+//! let transport = RelpTransport::connect("logs.example.com:2514")?;
+//! let mut sender = transport.into_sender(formatter);
+//! sender.send(&my_message)?;
+//! // wait for the receiver to acknowledge everything sent
+//! sender.transport_mut().flush()?;
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str;
+use std::time::Duration;
+
+use sender::{Sender, Transport};
+use {Rfc5424, WriteFormat};
+
+/// Largest transaction number, after which numbering starts over at 1
+const MAX_TXNR: u32 = 999_999_999;
+
+/// Longest DATALEN accepted, in digits
+const MAX_LEN_DIGITS: usize = 9;
+
+/// Largest `rsp` data a sender accepts
+const MAX_RSP_LEN: usize = 4096;
+
+/// `relp_software` offered by both sides
+const SOFTWARE: &str = concat!("syslog5424,", env!("CARGO_PKG_VERSION"));
+
+/// Offers sent with `open`
+fn open_offers() -> String {
+    format!(
+        "relp_version=0\nrelp_software={}\ncommands=syslog",
+        SOFTWARE
+    )
+}
+
+/// A RELP frame: `TXNR SP COMMAND SP DATALEN [SP DATA] LF`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub txnr: u32,
+    pub command: String,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(txnr: u32, command: &str, data: &[u8]) -> Frame {
+        Frame {
+            txnr,
+            command: command.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    /// Write the frame, trailer included.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut frame = format!("{} {} {}", self.txnr, self.command, self.data.len()).into_bytes();
+        if !self.data.is_empty() {
+            frame.push(b' ');
+            frame.extend_from_slice(&self.data);
+        }
+        frame.push(b'\n');
+        writer.write_all(&frame)
+    }
+
+    /// Read the next frame from `reader`, or `None` when it ends between
+    /// frames. Frames with more than `max_data_len` bytes of data are refused.
+    ///
+    /// # Errors
+    /// Errors when reading fails, or with `InvalidData` when the frame is
+    /// malformed, after which the stream can't be read any further.
+    pub fn read_from<R: BufRead>(reader: &mut R, max_data_len: usize) -> io::Result<Option<Frame>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let (txnr, _) = token(reader, MAX_LEN_DIGITS)?;
+        let txnr = number(&txnr)?;
+        let (command, _) = token(reader, 32)?;
+        let command = String::from_utf8(command).map_err(|_| invalid("command isn't ASCII"))?;
+        let (len, delimiter) = token(reader, MAX_LEN_DIGITS)?;
+        let len = number(&len)? as usize;
+        if len > max_data_len {
+            return Err(invalid("frame data is too long"));
+        }
+
+        let mut data = vec![0; len];
+        if len > 0 {
+            if delimiter != b' ' {
+                return Err(invalid("missing frame data"));
+            }
+            reader.read_exact(&mut data)?;
+        }
+        if len > 0 || delimiter == b' ' {
+            let mut trailer = [0];
+            reader.read_exact(&mut trailer)?;
+            if trailer[0] != b'\n' {
+                return Err(invalid("missing frame trailer"));
+            }
+        }
+        Ok(Some(Frame {
+            txnr,
+            command,
+            data,
+        }))
+    }
+
+    /// The status code and the offers of a `rsp` frame
+    fn response(&self) -> io::Result<(u16, Vec<(&str, &str)>)> {
+        let data = str::from_utf8(&self.data).map_err(|_| invalid("response isn't UTF-8"))?;
+        let (status, offers) = match data.find('\n') {
+            Some(i) => (&data[..i], &data[i + 1..]),
+            None => (data, ""),
+        };
+        let code = status
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| invalid("response has no status code"))?;
+        Ok((code, self::offers(offers)))
+    }
+}
+
+/// The `name=value` offers of `open` and its response, one per line
+fn offers(data: &str) -> Vec<(&str, &str)> {
+    data.split('\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.find('=') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        })
+        .collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read up to the next SP or LF, which is returned along with the token.
+fn token<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<(Vec<u8>, u8)> {
+    let mut token = Vec::new();
+    let mut byte = [0];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b' ' | b'\n' if !token.is_empty() => return Ok((token, byte[0])),
+            b' ' | b'\n' => return Err(invalid("empty frame field")),
+            _ if token.len() == max_len => return Err(invalid("frame field is too long")),
+            b => token.push(b),
+        }
+    }
+}
+
+fn number(digits: &[u8]) -> io::Result<u32> {
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return Err(invalid("expected a number"));
+    }
+    str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| invalid("expected a number"))
+}
+
+fn next_txnr(txnr: u32) -> u32 {
+    if txnr >= MAX_TXNR {
+        1
+    } else {
+        txnr + 1
+    }
+}
+
+/// A message sent but not acknowledged yet. `txnr` is 0 until it is sent on
+/// the current connection.
+#[derive(Debug)]
+struct Transaction {
+    txnr: u32,
+    message: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_txnr: u32,
+}
+
+impl Connection {
+    fn open(addresses: &[SocketAddr], timeout: Option<Duration>) -> io::Result<Connection> {
+        let stream = TcpStream::connect(addresses)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_txnr: 1,
+        };
+
+        let txnr = connection.write("open", open_offers().as_bytes())?;
+        let rsp = connection.read()?;
+        if rsp.txnr != txnr || rsp.command != "rsp" {
+            return Err(invalid("expected a response to open"));
+        }
+        let (code, offers) = rsp.response()?;
+        if code != 200 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "receiver refused the session: {}",
+                    String::from_utf8_lossy(&rsp.data)
+                ),
+            ));
+        }
+        let syslog = offers
+            .iter()
+            .any(|&(name, value)| name == "commands" && value.split(',').any(|c| c == "syslog"));
+        if !syslog {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "receiver doesn't offer the syslog command",
+            ));
+        }
+        Ok(connection)
+    }
+
+    fn write(&mut self, command: &str, data: &[u8]) -> io::Result<u32> {
+        let txnr = self.next_txnr;
+        self.next_txnr = next_txnr(txnr);
+        Frame::new(txnr, command, data).write_to(&mut self.writer)?;
+        Ok(txnr)
+    }
+
+    fn read(&mut self) -> io::Result<Frame> {
+        Frame::read_from(&mut self.reader, MAX_RSP_LEN)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "receiver closed the connection",
+            )
+        })
+    }
+}
+
+/// Sends messages as RELP `syslog` commands. See the
+/// [module documentation](index.html).
+///
+/// When sending fails, the connection is dropped and the error returned, but
+/// the message stays queued: the next `send` or `flush` reconnects and sends
+/// every unacknowledged message again, so it shouldn't be sent again by the
+/// caller. Only when the window is full and the receiver can't be reached is
+/// a message refused.
+#[derive(Debug)]
+pub struct RelpTransport {
+    addresses: Vec<SocketAddr>,
+    timeout: Option<Duration>,
+    window: usize,
+    connection: Option<Connection>,
+    unacked: VecDeque<Transaction>,
+}
+
+impl RelpTransport {
+    /// Open a RELP session with the receiver at `address`.
+    ///
+    /// # Errors
+    /// Errors when the connection fails, or when the receiver doesn't accept
+    /// `syslog` commands.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<RelpTransport> {
+        let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        let connection = Connection::open(&addresses, None)?;
+        Ok(RelpTransport {
+            addresses,
+            timeout: None,
+            window: 128,
+            connection: Some(connection),
+            unacked: VecDeque::new(),
+        })
+    }
+
+    /// Most messages sent but not acknowledged yet, 128 by default. `send`
+    /// waits for acknowledgements when there are this many.
+    pub fn window(mut self, window: usize) -> RelpTransport {
+        self.window = window.max(1);
+        self
+    }
+
+    /// How long to wait for acknowledgements before giving up on the
+    /// connection. Waits forever by default.
+    ///
+    /// # Errors
+    /// Errors when `timeout` is zero.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> io::Result<RelpTransport> {
+        if let Some(ref connection) = self.connection {
+            connection.writer.set_read_timeout(timeout)?;
+        }
+        self.timeout = timeout;
+        Ok(self)
+    }
+
+    /// Number of messages sent but not acknowledged yet
+    pub fn unacknowledged(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Pair the transport with `formatter`, switching it to
+    /// `WriteFormat::RFC5424`, since RELP frames carry their own length.
+    pub fn into_sender(self, mut formatter: Rfc5424) -> Sender<RelpTransport> {
+        formatter.write_format = WriteFormat::RFC5424;
+        Sender::new(formatter, self)
+    }
+
+    /// Wait until the receiver has acknowledged every message sent.
+    ///
+    /// # Errors
+    /// Errors when the connection fails, or when the receiver answers a message
+    /// with an error, in which case that message is dropped.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.acknowledged(0)
+    }
+
+    /// Wait for every acknowledgement, and close the session.
+    ///
+    /// # Errors
+    /// Errors when the connection fails.
+    pub fn close(mut self) -> io::Result<()> {
+        self.flush()?;
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+        let txnr = connection.write("close", b"")?;
+        loop {
+            let frame = connection.read()?;
+            if frame.command == "rsp" && frame.txnr == txnr {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run `f` on the connection, opening it first if needed. The connection is
+    /// dropped when `f` fails.
+    fn connected<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut Connection, &mut VecDeque<Transaction>) -> io::Result<T>,
+    {
+        if self.connection.is_none() {
+            let mut connection = Connection::open(&self.addresses, self.timeout)?;
+            for transaction in &mut self.unacked {
+                transaction.txnr = 0;
+            }
+            send_pending(&mut connection, &mut self.unacked)?;
+            self.connection = Some(connection);
+        }
+        let result = match self.connection {
+            Some(ref mut connection) => f(connection, &mut self.unacked),
+            None => unreachable!(),
+        };
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    /// Read responses until at most `pending` messages are unacknowledged.
+    fn acknowledged(&mut self, pending: usize) -> io::Result<()> {
+        let mut refused = None;
+        self.connected(|connection, unacked| {
+            while unacked.len() > pending {
+                let frame = connection.read()?;
+                match &frame.command[..] {
+                    "rsp" => {}
+                    "serverclose" => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "receiver closed the session",
+                        ))
+                    }
+                    _ => continue,
+                }
+                if let Some(i) = unacked.iter().position(|t| t.txnr == frame.txnr) {
+                    unacked.remove(i);
+                    if frame.response()?.0 != 200 {
+                        refused = Some(frame);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        match refused {
+            Some(frame) => Err(io::Error::other(format!(
+                "receiver refused a message: {}",
+                String::from_utf8_lossy(&frame.data)
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Send every transaction not sent on `connection` yet.
+fn send_pending(
+    connection: &mut Connection,
+    unacked: &mut VecDeque<Transaction>,
+) -> io::Result<()> {
+    for transaction in unacked.iter_mut().filter(|t| t.txnr == 0) {
+        transaction.txnr = connection.write("syslog", &transaction.message)?;
+    }
+    Ok(())
+}
+
+impl Transport for RelpTransport {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.unacked.len() >= self.window {
+            let pending = self.window - 1;
+            self.acknowledged(pending)?;
+        }
+        self.unacked.push_back(Transaction {
+            txnr: 0,
+            message: message.to_vec(),
+        });
+        self.connected(send_pending)
+    }
+}
+
+/// Answer to the offers of an `open` command: the RELP version and the
+/// commands both sides support. Errors when the sender offers no version.
+fn negotiate(offers: &[(&str, &str)]) -> Result<String, &'static str> {
+    if !offers.iter().any(|&(name, _)| name == "relp_version") {
+        return Err("500 relp_version offer missing");
+    }
+    let syslog = offers
+        .iter()
+        .any(|&(name, value)| name == "commands" && value.split(',').any(|c| c == "syslog"));
+    Ok(format!(
+        "200 OK\nrelp_version=0\nrelp_software={}\ncommands={}",
+        SOFTWARE,
+        if syslog { "syslog" } else { "" }
+    ))
+}
+
+/// Serve one RELP session on `stream`, handing the data of every `syslog`
+/// command to `accept`. Frames with more than `max_data_len` bytes of data end
+/// the session.
+///
+/// A message is answered with `200 OK` once `accept` returns `Ok`, and with
+/// `500` and the error otherwise. The session must start with an `open`
+/// command offering `relp_version`, and only the `syslog` command is offered
+/// back.
+///
+/// Returns when the sender closes the session or the connection.
+///
+/// # Errors
+/// Errors when the stream fails, or the sender breaks the protocol.
+pub fn serve_connection<S, F>(stream: S, max_data_len: usize, mut accept: F) -> io::Result<()>
+where
+    S: Read + Write,
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let mut stream = BufReader::new(stream);
+    let mut open = false;
+    while let Some(frame) = Frame::read_from(&mut stream, max_data_len)? {
+        let response = match &frame.command[..] {
+            "open" => {
+                let data = String::from_utf8_lossy(&frame.data);
+                match negotiate(&offers(&data)) {
+                    Ok(response) => {
+                        open = true;
+                        response
+                    }
+                    Err(response) => response.to_string(),
+                }
+            }
+            _ if !open => "500 session not open".to_string(),
+            "syslog" => match accept(&frame.data) {
+                Ok(()) => "200 OK".to_string(),
+                Err(e) => format!("500 {}", e),
+            },
+            "close" => {
+                Frame::new(frame.txnr, "rsp", b"").write_to(stream.get_mut())?;
+                return Frame::new(0, "serverclose", b"").write_to(stream.get_mut());
+            }
+            _ => "500 unknown command".to_string(),
+        };
+        Frame::new(frame.txnr, "rsp", response.as_bytes()).write_to(stream.get_mut())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn frames() {
+        let mut written = Vec::new();
+        let offers = open_offers();
+        Frame::new(1, "open", offers.as_bytes())
+            .write_to(&mut written)
+            .unwrap();
+        Frame::new(2, "close", b"").write_to(&mut written).unwrap();
+        written.extend_from_slice(b"3 rsp 0 \n");
+        let open = format!("1 open {} relp_version=0\n", offers.len());
+        assert!(written.starts_with(open.as_bytes()));
+
+        let mut reader = &written[..];
+        let open = Frame::read_from(&mut reader, 1024).unwrap().unwrap();
+        assert_eq!(open, Frame::new(1, "open", offers.as_bytes()));
+        let close = Frame::read_from(&mut reader, 1024).unwrap().unwrap();
+        assert_eq!(close, Frame::new(2, "close", b""));
+        let rsp = Frame::read_from(&mut reader, 1024).unwrap().unwrap();
+        assert_eq!(rsp, Frame::new(3, "rsp", b""));
+        assert!(Frame::read_from(&mut reader, 1024).unwrap().is_none());
+
+        for bad in &[
+            &b"x open 0\n"[..],
+            b"1 syslog 5 abc\n",
+            b"1 syslog 3 abcX",
+            b"1 syslog 9999 ",
+        ] {
+            assert!(Frame::read_from(&mut &bad[..], 1024).is_err());
+        }
+        assert_eq!(next_txnr(MAX_TXNR), 1);
+    }
+
+    #[test]
+    fn negotiates_offers() {
+        let response = negotiate(&offers(
+            "relp_version=0\nrelp_software=librelp,1.10.0,http://librelp.adiscon.com\ncommands=syslog",
+        ))
+        .unwrap();
+        assert!(response.starts_with("200 OK\nrelp_version=0\n"));
+        assert!(response.ends_with("\ncommands=syslog"));
+
+        let response = negotiate(&offers("relp_version=0\ncommands=other")).unwrap();
+        assert!(response.ends_with("\ncommands="));
+        assert!(negotiate(&offers("commands=syslog")).is_err());
+    }
+
+    /// Accept connections one at a time, serving those `serve` returns true
+    /// for and dropping the others after it returns.
+    fn receiver<F>(mut serve: F) -> (SocketAddr, Receiver<Vec<u8>>)
+    where
+        F: FnMut(usize, &mut TcpStream) -> bool + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                if serve(n, &mut stream) {
+                    let tx = tx.clone();
+                    let _ = serve_connection(stream, 1024, |data| {
+                        tx.send(data.to_vec()).map_err(io::Error::other)
+                    });
+                }
+            }
+        });
+        (addr, rx)
+    }
+
+    #[test]
+    fn acknowledged_delivery() {
+        let (addr, rx) = receiver(|_, _| true);
+        let mut transport = RelpTransport::connect(addr).unwrap().window(2);
+        for message in &[&b"one"[..], b"two", b"three"] {
+            transport.send(message).unwrap();
+            assert!(transport.unacknowledged() <= 2);
+        }
+        transport.flush().unwrap();
+        assert_eq!(transport.unacknowledged(), 0);
+        transport.close().unwrap();
+
+        let received: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+
+    #[test]
+    fn resends_after_reconnect() {
+        // the first connection takes two messages and breaks without answering
+        let (addr, rx) = receiver(|n, stream| {
+            if n > 0 {
+                return true;
+            }
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let open = Frame::read_from(&mut reader, 1024).unwrap().unwrap();
+            let response = negotiate(&offers(&open_offers())).unwrap();
+            Frame::new(open.txnr, "rsp", response.as_bytes())
+                .write_to(stream)
+                .unwrap();
+            for _ in 0..2 {
+                Frame::read_from(&mut reader, 1024).unwrap().unwrap();
+            }
+            false
+        });
+
+        let mut transport = RelpTransport::connect(addr)
+            .unwrap()
+            .timeout(Some(TIMEOUT))
+            .unwrap();
+        transport.send(b"lost").unwrap();
+        transport.send(b"also lost").unwrap();
+        assert!(transport.flush().is_err());
+        assert_eq!(transport.unacknowledged(), 2);
+
+        transport.flush().unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"lost");
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"also lost");
+    }
+
+    #[test]
+    fn refused_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, 1024, |data| match data {
+                b"bad" => Err(io::Error::other("bad message")),
+                _ => Ok(()),
+            })
+            .unwrap();
+        });
+
+        let mut transport = RelpTransport::connect(addr).unwrap();
+        transport.send(b"bad").unwrap();
+        transport.send(b"good").unwrap();
+        assert!(transport.flush().is_err());
+        assert_eq!(transport.unacknowledged(), 0);
+        transport.close().unwrap();
+    }
+}
@@ -0,0 +1,672 @@
+//! Legacy BSD syslog ([RFC3164](https://tools.ietf.org/html/rfc3164)) output.
+//!
+//! Selected with [`WriteFormat::RFC3164`](../enum.WriteFormat.html), this writes
+//! the same [`Rfc5424`](../struct.Rfc5424.html) metadata and
+//! [`Rfc5424Data`](../trait.Rfc5424Data.html) as
+//! `<PRI>Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`.
+//!
+//! * TIMESTAMP is taken from the month, day and time of the RFC3339 timestamp
+//!   of the message. Messages without a timestamp use the current UTC time.
+//! * TAG is the APP-NAME, truncated to 32 characters. It is left out, along with
+//!   PROCID, when the APP-NAME is NILVALUE.
+//! * MSGID has no equivalent, and isn't written.
+//! * Structured data is written into the MSG as chosen by
+//!   [`SdFlattening`](enum.SdFlattening.html).
+//! * The message is truncated to [`MAX_LEN`](constant.MAX_LEN.html) bytes, or the
+//!   maximum length of the formatter if that's smaller, without splitting a UTF-8
+//!   character. No BOM is written before `Message::Text`.
+//!
+//! [`parse`](fn.parse.html) goes the other way, turning the RFC3164 messages seen
+//! in the wild into a [`SyslogMessage`](../parser/struct.SyslogMessage.html).
+
+use std::io::{self, Write};
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parser::{valid_timestamp, ParseError, SdElement, SyslogMessage};
+use types::*;
+use {floor_char_boundary, write_sd_element, Rfc5424, Rfc5424Data};
+
+/// Maximum length of a RFC3164 message
+/// ([RFC3164 Section 4.1](https://tools.ietf.org/html/rfc3164#section-4.1))
+pub const MAX_LEN: usize = 1024;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How structured data is written into the MSG of a RFC3164 message
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum SdFlattening {
+    /// Structured data is not written
+    Discard,
+    /// SD-ELEMENTs are written before the message in RFC5424 syntax, the way
+    /// rsyslog does: `[exampleSDID@32473 iut="3"] message`
+    #[default]
+    Prefix,
+    /// Parameters are written after the message as `key="value"` pairs, where the
+    /// key is the SD-ID without the enterprise ID, and the PARAM-NAME:
+    /// `message exampleSDID.iut="3"`
+    KeyValue,
+}
+
+/// Write `message` in the RFC3164 format, using the metadata in `f`.
+pub(crate) fn format<W: Write>(
+    f: &Rfc5424,
+    writer: &mut W,
+    message: &impl Rfc5424Data,
+) -> io::Result<()> {
+    let header = f.header(message);
+    let mut out =
+        format!("<{}>", encode_priority(header.facility, message.severity())).into_bytes();
+
+    // TIMESTAMP
+    out.extend(timestamp(message.timestamp()).as_bytes());
+    out.push(b' ');
+
+    // HOSTNAME
+    out.extend(header.hostname.0.as_bytes());
+    out.push(b' ');
+
+    // TAG
+    if header.app_name != AppName::default() {
+        out.extend(header.app_name.0.bytes().take(32));
+        if header.pid != ProcessId::default() {
+            out.push(b'[');
+            out.extend(header.pid.0.as_bytes());
+            out.push(b']');
+        }
+        out.extend(b": ");
+    }
+
+    // MSG
+    let elements = match f.sd_flattening {
+        SdFlattening::Discard => Vec::new(),
+        _ => f.checked_sd_elements(message)?,
+    };
+    let mut msg: Vec<u8> = Vec::new();
+    if f.sd_flattening == SdFlattening::Prefix && !elements.is_empty() {
+        let mut sd = String::new();
+        for element in &elements {
+            write_sd_element(&mut sd, element);
+        }
+        msg.extend(sd.as_bytes());
+    }
+
+    if let Some(m) = message.message() {
+        if !msg.is_empty() {
+            msg.push(b' ');
+        }
+        match m {
+            Message::Text(s) => msg.extend(s.as_bytes()),
+            Message::Binary(data) => msg.extend(data),
+        }
+    }
+
+    if f.sd_flattening == SdFlattening::KeyValue {
+        for element in &elements {
+            let id = element.id.split('@').next().unwrap_or_default();
+            for (name, val) in &element.params {
+                if !msg.is_empty() {
+                    msg.push(b' ');
+                }
+                msg.extend(format!(r#"{}.{}="{}""#, id, name, escape_val(val)).as_bytes());
+            }
+        }
+    }
+
+    out.extend(msg);
+    let max = f.max_len.map_or(MAX_LEN, |max| max.min(MAX_LEN));
+    let len = floor_char_boundary(&out, max);
+    writer.write_all(&out[..len])
+}
+
+/// Convert a RFC3339 timestamp to the RFC3164 `Mmm dd hh:mm:ss` format, falling
+/// back to the current time if there is no valid timestamp.
+fn timestamp(ts: Option<String>) -> String {
+    if let Some(ts) = ts {
+        if valid_timestamp(ts.as_bytes()) {
+            let month: usize = ts[5..7].parse().unwrap_or(0);
+            if let Some(month) = month.checked_sub(1).and_then(|m| MONTHS.get(m)) {
+                return format!(
+                    "{} {:>2} {}",
+                    month,
+                    ts[8..10].trim_start_matches('0'),
+                    &ts[11..19]
+                );
+            }
+        }
+    }
+
+    let (_, month, day, secs) = utc_now();
+    format!(
+        "{} {:>2} {:02}:{:02}:{:02}",
+        MONTHS[month as usize - 1],
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// The current UTC date, and the number of seconds since midnight
+pub(crate) fn utc_now() -> (i64, u32, u32, u64) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    (year, month, day, secs % 86_400)
+}
+
+/// Convert days since 1970-01-01 into a (year, month, day) date.
+///
+/// From <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) date into days since 1970-01-01.
+///
+/// From <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse a RFC3164 message, as they are actually sent by devices.
+///
+/// The format is loosely `<PRI>TIMESTAMP HOSTNAME TAG[PID]: MSG`, but almost every
+/// part can be missing or different. This handles:
+///
+/// * A missing PRI, which is treated as `<13>` (user.notice) as described in
+///   [RFC3164 Section 4.3.3](https://tools.ietf.org/html/rfc3164#section-4.3.3)
+/// * `Mmm dd hh:mm:ss` timestamps, optionally with a year, fractional seconds,
+///   a trailing `:`, or a leading `*` or `.` like Cisco devices send
+/// * RFC3339 timestamps
+/// * A missing timestamp, in which case the whole rest is the MSG
+/// * A missing HOSTNAME
+/// * `TAG[PID]:`, `TAG:`, or no TAG at all
+/// * Cisco sequence numbers (`<189>123: ...`), which are stored as the `sequenceId`
+///   of a `meta` SD-ELEMENT
+///
+/// The result has version 1 and no MSGID. The TAG becomes the APP-NAME. BSD
+/// timestamps have no year or time zone, so the year is guessed from the
+/// current date and the time is assumed to be UTC. The MSG is `Message::Text`
+/// when it is valid UTF-8.
+///
+/// # Errors
+/// * `input` is empty
+/// * `input` starts with `<`, but doesn't have a valid PRI
+pub fn parse(input: &[u8]) -> Result<SyslogMessage, ParseError> {
+    if input.is_empty() {
+        return Err(ParseError::UnexpectedEnd);
+    }
+    let mut rest = input;
+
+    // PRI
+    let (facility, severity) = if rest[0] == b'<' {
+        let end = rest
+            .iter()
+            .position(|b| *b == b'>')
+            .ok_or(ParseError::InvalidPriority)?;
+        let pri = str::from_utf8(&rest[1..end])
+            .ok()
+            .filter(|s| !s.is_empty() && s.len() <= 3 && s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|s| s.parse().ok())
+            .and_then(decode_priority)
+            .ok_or(ParseError::InvalidPriority)?;
+        rest = &rest[end + 1..];
+        pri
+    } else {
+        (Facility::User, Severity::Notice)
+    };
+
+    // Cisco sequence number
+    let mut structured_data = Vec::new();
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits > 0 && rest[digits..].starts_with(b": ") {
+        structured_data.push(SdElement {
+            id: "meta".into(),
+            params: vec![(
+                "sequenceId".into(),
+                String::from_utf8_lossy(&rest[..digits]).into_owned(),
+            )],
+        });
+        rest = &rest[digits + 2..];
+    }
+    rest = trim_start(rest);
+
+    // TIMESTAMP
+    let timestamp = match parse_timestamp(rest) {
+        Some((ts, len)) => {
+            rest = trim_start(&rest[len..]);
+            Some(ts)
+        }
+        None => None,
+    };
+
+    // HOSTNAME, only looked for if there was a timestamp
+    let mut hostname = HostName::default();
+    if timestamp.is_some() {
+        let token = next_token(rest);
+        if parse_tag(token).is_none() && !token.starts_with(b"%") {
+            if let Ok(h) = str::from_utf8(token)
+                .map_err(|_| ())
+                .and_then(|h| HostName::new(h).map_err(|_| ()))
+            {
+                hostname = h;
+                rest = trim_start(&rest[token.len()..]);
+            }
+        }
+    }
+
+    // TAG
+    let mut app_name = AppName::default();
+    let mut pid = ProcessId::default();
+    if let Some((tag, tag_pid)) = parse_tag(next_token(rest)) {
+        app_name = tag;
+        pid = tag_pid.unwrap_or_default();
+        rest = &rest[next_token(rest).len()..];
+        if rest.first() == Some(&b' ') {
+            rest = &rest[1..];
+        }
+    }
+
+    // MSG
+    let message = if rest.is_empty() {
+        None
+    } else {
+        Some(match str::from_utf8(rest) {
+            Ok(s) => Message::Text(s.to_string()),
+            Err(_) => Message::Binary(rest.to_vec()),
+        })
+    };
+
+    Ok(SyslogMessage {
+        facility,
+        severity,
+        version: 1,
+        timestamp,
+        hostname,
+        app_name,
+        pid,
+        msg_id: MessageId::default(),
+        structured_data,
+        message,
+    })
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let spaces = bytes.iter().take_while(|b| **b == b' ').count();
+    &bytes[spaces..]
+}
+
+fn next_token(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().take_while(|b| **b != b' ').count();
+    &bytes[..len]
+}
+
+/// Parse `TAG[PID]:` or `TAG:` into an APP-NAME and PROCID.
+fn parse_tag(token: &[u8]) -> Option<(AppName, Option<ProcessId>)> {
+    let token = str::from_utf8(token.strip_suffix(b":")?).ok()?;
+    if token.starts_with('%') {
+        return None;
+    }
+    let (tag, pid) = match token.find('[') {
+        Some(open) if token.ends_with(']') => (
+            &token[..open],
+            Some(ProcessId::new(&token[open + 1..token.len() - 1]).ok()?),
+        ),
+        Some(_) => return None,
+        None => (token, None),
+    };
+    if tag.contains(']') {
+        return None;
+    }
+    Some((AppName::new(tag).ok()?, pid))
+}
+
+/// Parse a timestamp at the start of `bytes`, returning it in the RFC3339 format
+/// along with the number of bytes it took up.
+fn parse_timestamp(bytes: &[u8]) -> Option<(String, usize)> {
+    let token = next_token(bytes);
+    if valid_timestamp(token) {
+        return Some((str::from_utf8(token).ok()?.to_string(), token.len()));
+    }
+
+    // [*.]Mmm dd [yyyy ]hh:mm:ss[.frac][:]
+    let text = str::from_utf8(&bytes[..bytes.len().min(40)])
+        .or_else(|e| str::from_utf8(&bytes[..e.valid_up_to()]))
+        .ok()?;
+    let marker = if text.starts_with('*') || text.starts_with('.') {
+        1
+    } else {
+        0
+    };
+    let mut pos = marker;
+    let mut words = text[marker..].split(' ');
+    let mut word = |pos: &mut usize| -> Option<&str> {
+        let w = words.next()?;
+        *pos += w.len() + 1;
+        Some(w)
+    };
+
+    let month = MONTHS
+        .iter()
+        .position(|m| Some(*m) == text.get(marker..marker + 3))? as u32
+        + 1;
+    word(&mut pos)?; // month
+    let mut day = word(&mut pos)?;
+    if day.is_empty() {
+        // space padded day
+        day = word(&mut pos)?;
+    }
+    let day: u32 = day.parse().ok().filter(|d| (1..=31).contains(d))?;
+    let mut time = word(&mut pos)?;
+    let mut year = None;
+    if time.len() == 4 && time.bytes().all(|b| b.is_ascii_digit()) {
+        year = time.parse().ok();
+        time = word(&mut pos)?;
+    }
+    let len = pos - 1;
+    let time = time.strip_suffix(':').unwrap_or(time);
+    let (time, frac) = match time.find('.') {
+        Some(dot) => (&time[..dot], &time[dot..]),
+        None => (time, ""),
+    };
+    let ts = format!(
+        "{:04}-{:02}-{:02}T{}{}Z",
+        year.unwrap_or_else(|| infer_year(month, utc_now())),
+        month,
+        day,
+        time,
+        frac
+    );
+    if valid_timestamp(ts.as_bytes()) {
+        Some((ts, len))
+    } else {
+        None
+    }
+}
+
+/// Guess the year of a timestamp without one. Messages from a month that is
+/// still more than a month away are assumed to be from last year, which
+/// happens around new year.
+fn infer_year(month: u32, (year, current_month, _, _): (i64, u32, u32, u64)) -> i64 {
+    if month > current_month + 1 {
+        year - 1
+    } else {
+        year
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {Rfc5424Builder, StructuredData, WriteFormat};
+
+    struct Data {
+        timestamp: Option<String>,
+        message: Option<Message>,
+    }
+
+    impl Rfc5424Data for Data {
+        fn severity(&self) -> Severity {
+            Severity::Critical
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            self.timestamp.clone()
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            let mut sd: StructuredData = HashMap::new();
+            sd.insert("exampleSDID", vec![("iut".into(), "3".into())]);
+            Some(sd)
+        }
+
+        fn message(&self) -> Option<Message> {
+            self.message.clone()
+        }
+    }
+
+    fn data(msg: &str) -> Data {
+        Data {
+            timestamp: Some("2003-10-01T22:14:15.003-07:00".into()),
+            message: Some(Message::Text(msg.into())),
+        }
+    }
+
+    fn format(builder: Rfc5424Builder, data: &Data) -> String {
+        let mut out = Vec::new();
+        builder
+            .write_format(WriteFormat::RFC3164)
+            .build()
+            .format(&mut out, data)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn builder() -> Rfc5424Builder {
+        Rfc5424Builder::new("32473", Facility::Auth)
+            .hostname("mymachine")
+            .unwrap()
+            .app_name("su")
+            .unwrap()
+            .pid("123")
+            .unwrap()
+    }
+
+    #[test]
+    fn flattening() {
+        let d = data("'su root' failed");
+        assert_eq!(
+            format(builder(), &d),
+            r#"<34>Oct  1 22:14:15 mymachine su[123]: [exampleSDID@32473 iut="3"] 'su root' failed"#
+        );
+        assert_eq!(
+            format(builder().sd_flattening(SdFlattening::KeyValue), &d),
+            r#"<34>Oct  1 22:14:15 mymachine su[123]: 'su root' failed exampleSDID.iut="3""#
+        );
+        assert_eq!(
+            format(builder().sd_flattening(SdFlattening::Discard), &d),
+            r#"<34>Oct  1 22:14:15 mymachine su[123]: 'su root' failed"#
+        );
+    }
+
+    #[test]
+    fn tag() {
+        let d = data("hello");
+        let f = Rfc5424Builder::new("32473", Facility::Auth)
+            .hostname("mymachine")
+            .unwrap()
+            .sd_flattening(SdFlattening::Discard);
+        assert_eq!(format(f, &d), "<34>Oct  1 22:14:15 mymachine hello");
+
+        let f = Rfc5424Builder::new("32473", Facility::Auth)
+            .app_name("a_very_long_application_name_that_is_over_32")
+            .unwrap()
+            .sd_flattening(SdFlattening::Discard);
+        assert_eq!(
+            format(f, &d),
+            "<34>Oct  1 22:14:15 - a_very_long_application_name_tha: hello"
+        );
+    }
+
+    #[test]
+    fn current_time() {
+        let d = Data {
+            timestamp: None,
+            message: None,
+        };
+        let out = format(builder().sd_flattening(SdFlattening::Discard), &d);
+        // <34>Mmm dd hh:mm:ss mymachine su[123]:
+        assert_eq!(out.len(), 39);
+        assert!(MONTHS.contains(&&out[4..7]));
+        assert!(out.ends_with(" mymachine su[123]: "));
+    }
+
+    #[test]
+    fn truncation() {
+        let header = "<34>Oct  1 22:14:15 mymachine su[123]: ";
+        let fill = "a".repeat(MAX_LEN - header.len() - 1);
+        let d = data(&format!("{}\u{e9}", fill));
+        let out = format(builder().sd_flattening(SdFlattening::Discard), &d);
+        // the two byte character doesn't fit, so it's removed entirely
+        assert_eq!(out.len(), MAX_LEN - 1);
+        assert!(out.ends_with('a'));
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        for &days in &[0, 11_016, 19_723, -1, 60] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    fn assert_ts(msg: &SyslogMessage, suffix: &str) {
+        let ts = msg.timestamp.as_ref().unwrap();
+        assert!(ts.ends_with(suffix), "{} doesn't end with {}", ts, suffix);
+        assert!(valid_timestamp(ts.as_bytes()));
+    }
+
+    #[test]
+    fn parse_rfc_example() {
+        let msg =
+            parse(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8")
+                .unwrap();
+        assert_eq!(msg.facility, Facility::Auth);
+        assert_eq!(msg.severity, Severity::Critical);
+        assert_ts(&msg, "-10-11T22:14:15Z");
+        assert_eq!(msg.hostname, HostName::new("mymachine").unwrap());
+        assert_eq!(msg.app_name, AppName::new("su").unwrap());
+        assert_eq!(msg.pid, ProcessId::default());
+        assert_eq!(
+            msg.message,
+            Some(Message::Text(
+                "'su root' failed for lonvick on /dev/pts/8".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_variants() {
+        // TAG[PID]:, and a space padded day
+        let msg = parse(b"<13>Feb  5 17:32:18 10.0.0.99 sshd[1234]: Accepted").unwrap();
+        assert_ts(&msg, "-02-05T17:32:18Z");
+        assert_eq!(msg.hostname, HostName::new("10.0.0.99").unwrap());
+        assert_eq!(msg.app_name, AppName::new("sshd").unwrap());
+        assert_eq!(msg.pid, ProcessId::new("1234").unwrap());
+        assert_eq!(msg.message, Some(Message::Text("Accepted".into())));
+
+        // missing hostname
+        let msg = parse(b"<13>Feb  5 17:32:18 sshd[1234]: Accepted").unwrap();
+        assert_eq!(msg.hostname, HostName::default());
+        assert_eq!(msg.app_name, AppName::new("sshd").unwrap());
+
+        // RFC3339 timestamp
+        let msg = parse(b"<13>2003-10-11T22:14:15.003-07:00 host app: hi").unwrap();
+        assert_eq!(msg.timestamp, Some("2003-10-11T22:14:15.003-07:00".into()));
+        assert_eq!(msg.hostname, HostName::new("host").unwrap());
+
+        // timestamp with a year, and no tag
+        let msg = parse(b"<13>Mar  1 2024 18:46:11 host just a message").unwrap();
+        assert_eq!(msg.timestamp, Some("2024-03-01T18:46:11Z".into()));
+        assert_eq!(msg.app_name, AppName::default());
+        assert_eq!(msg.message, Some(Message::Text("just a message".into())));
+
+        // missing PRI
+        let msg = parse(b"Oct 11 22:14:15 host app: hi").unwrap();
+        assert_eq!(msg.facility, Facility::User);
+        assert_eq!(msg.severity, Severity::Notice);
+        assert_eq!(msg.app_name, AppName::new("app").unwrap());
+
+        // no timestamp, so no hostname either
+        let msg = parse(b"<13>hello world").unwrap();
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.hostname, HostName::default());
+        assert_eq!(msg.message, Some(Message::Text("hello world".into())));
+    }
+
+    #[test]
+    fn parse_cisco() {
+        let msg =
+            parse(b"<189>123: *Mar  1 18:46:11.123: %SYS-5-CONFIG_I: Configured from console")
+                .unwrap();
+        assert_eq!(msg.facility, Facility::Local7);
+        assert_eq!(msg.severity, Severity::Notice);
+        assert_ts(&msg, "-03-01T18:46:11.123Z");
+        assert_eq!(msg.hostname, HostName::default());
+        assert_eq!(msg.app_name, AppName::default());
+        assert_eq!(
+            msg.structured_data,
+            vec![SdElement {
+                id: "meta".into(),
+                params: vec![("sequenceId".into(), "123".into())],
+            }]
+        );
+        assert_eq!(
+            msg.message,
+            Some(Message::Text(
+                "%SYS-5-CONFIG_I: Configured from console".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(b""), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse(b"<13 hello"), Err(ParseError::InvalidPriority));
+        assert_eq!(parse(b"<200>hello"), Err(ParseError::InvalidPriority));
+    }
+
+    #[test]
+    fn reformat_as_5424() {
+        let msg = parse(b"<34>Oct 11 22:14:15 mymachine su[42]: failed").unwrap();
+        let f = Rfc5424Builder::new("", msg.facility)
+            .hostname(&msg.hostname.0)
+            .unwrap()
+            .app_name(&msg.app_name.0)
+            .unwrap()
+            .pid(&msg.pid.0)
+            .unwrap()
+            .build();
+        let mut out = Vec::new();
+        f.format(&mut out, &msg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("<34>1 "));
+        assert!(out.ends_with("-10-11T22:14:15Z mymachine su 42 - - \u{feff}failed"));
+    }
+
+    #[test]
+    fn year_inference() {
+        assert_eq!(infer_year(12, (2024, 1, 1, 0)), 2023);
+        assert_eq!(infer_year(2, (2024, 1, 1, 0)), 2024);
+        assert_eq!(infer_year(6, (2024, 6, 1, 0)), 2024);
+    }
+}
@@ -0,0 +1,299 @@
+//! How invalid SD-IDs and PARAM-NAMEs of structured data, and control characters
+//! in the MSG, are handled.
+//!
+//! # SD-IDs and PARAM-NAMEs
+//!
+//! SD-IDs and PARAM-NAMEs are limited to 32 printable ASCII characters, other
+//! than `'='`, `' '`, `']'` and `'"'`
+//! ([RFC5424 Section 6.3](https://tools.ietf.org/html/rfc5424#section-6.3)).
+//! The limit includes the `@enterprise_id` suffix the formatter adds to SD-IDs.
+//!
+//! The [`Sanitize`](enum.Sanitize.html) policy of a formatter is set with
+//! [`Rfc5424Builder::sanitize`](../struct.Rfc5424Builder.html#method.sanitize).
+//! Names changed by the policy can be reported to a callback set with
+//! [`Rfc5424Builder::on_sanitized`](../struct.Rfc5424Builder.html#method.on_sanitized).
+//!
+//! # Control characters
+//! A LF in the MSG ends the message early for receivers using LF framing
+//! (RFC6587 and most local syslog daemons), and other control characters can
+//! garble terminals. The [`ControlChars`](enum.ControlChars.html) policy of a
+//! formatter is set with
+//! [`Rfc5424Builder::control_chars`](../struct.Rfc5424Builder.html#method.control_chars),
+//! and applies to every [`WriteFormat`](../enum.WriteFormat.html) and transport.
+
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use Error;
+
+/// What to do with SD-IDs and PARAM-NAMEs that aren't valid
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Sanitize {
+    /// Remove the invalid characters, and truncate the name. Fails like
+    /// `Strict` when nothing is left of the name.
+    #[default]
+    Strip,
+    /// Replace the invalid characters with `'_'`, and truncate the name. Fails
+    /// like `Strict` when the name is empty.
+    Replace,
+    /// Fail formatting with an [`InvalidName`](struct.InvalidName.html) error
+    Strict,
+}
+
+/// What to do with ASCII control characters, including TAB and DEL, in the text
+/// of a MSG. `Message::Binary` is always written as is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ControlChars {
+    /// Write the text as is
+    #[default]
+    Keep,
+    /// Write each control character as `'#'` followed by its 3 digit octal
+    /// code, the way rsyslog does: `"a\nb"` is written as `"a#012b"`
+    Escape,
+    /// Replace each control character with a space
+    Space,
+    /// Write each line of the text as its own message, and escape the other
+    /// control characters. Empty lines are skipped.
+    ///
+    /// When there is more than one line, every message gets a `split`
+    /// SD-ELEMENT with the same `id`, its `part` number starting at 1, and the
+    /// `total` number of parts. The `id` is a counter of the formatter, so the
+    /// parts are told apart from other messages by HOSTNAME, APP-NAME and PROCID.
+    Split,
+}
+
+impl ControlChars {
+    /// The texts written for `text`, more than one when `text` is split.
+    pub(crate) fn apply(self, text: &str) -> Vec<String> {
+        match self {
+            ControlChars::Keep => vec![text.to_string()],
+            ControlChars::Escape => vec![escape(text)],
+            ControlChars::Space => vec![text
+                .chars()
+                .map(|c| if c.is_ascii_control() { ' ' } else { c })
+                .collect()],
+            ControlChars::Split => {
+                let lines: Vec<String> = text
+                    .split('\n')
+                    .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                    .filter(|line| !line.is_empty())
+                    .map(escape)
+                    .collect();
+                if lines.is_empty() {
+                    vec![escape(text)]
+                } else {
+                    lines
+                }
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_control() {
+            escaped.push_str(&format!("#{:03o}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// The kind of name being sanitized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameField {
+    SdId,
+    ParamName,
+}
+
+impl fmt::Display for NameField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameField::SdId => f.write_str("SD-ID"),
+            NameField::ParamName => f.write_str("PARAM-NAME"),
+        }
+    }
+}
+
+/// A name rejected by `Sanitize::Strict`.
+///
+/// [`Rfc5424::format`](../struct.Rfc5424.html#method.format) returns it inside an
+/// `io::Error` of kind `InvalidData`, which can be recovered with
+/// `err.get_ref().and_then(|e| e.downcast_ref::<InvalidName>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidName {
+    pub field: NameField,
+    pub value: String,
+    pub error: Error,
+}
+
+impl fmt::Display for InvalidName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?}: {}", self.field, self.value, self.error)
+    }
+}
+
+impl error::Error for InvalidName {}
+
+/// A name that was changed by `Sanitize::Strip` or `Sanitize::Replace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitized {
+    pub field: NameField,
+    pub original: String,
+    /// The name as written, without the `@enterprise_id` suffix
+    pub written: String,
+}
+
+/// Callback receiving the names changed while formatting
+#[derive(Clone)]
+pub(crate) struct Reporter(pub(crate) Arc<dyn Fn(&Sanitized) + Send + Sync>);
+
+impl fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Reporter")
+    }
+}
+
+fn is_valid(c: char) -> bool {
+    c.is_ascii_graphic() && c != '=' && c != ']' && c != '"'
+}
+
+impl Sanitize {
+    /// Apply the policy to `name`, which may be at most `max_len` characters.
+    pub(crate) fn name(
+        self,
+        field: NameField,
+        name: &str,
+        max_len: usize,
+    ) -> Result<String, InvalidName> {
+        let invalid = |error| InvalidName {
+            field,
+            value: name.to_string(),
+            error,
+        };
+        let written: String = match self {
+            Sanitize::Strip => name
+                .chars()
+                .filter(|c| is_valid(*c))
+                .take(max_len)
+                .collect(),
+            Sanitize::Replace => name
+                .chars()
+                .map(|c| if is_valid(c) { c } else { '_' })
+                .take(max_len)
+                .collect(),
+            Sanitize::Strict => name.to_string(),
+        };
+        // an empty name can't be written in any mode
+        if written.is_empty() {
+            return Err(invalid(Error::FieldEmpty));
+        }
+        match self {
+            Sanitize::Strip | Sanitize::Replace => Ok(written),
+            Sanitize::Strict => {
+                if !name.chars().all(is_valid) {
+                    Err(invalid(Error::InvalidCharacters))
+                } else if name.len() > max_len {
+                    Err(invalid(Error::FieldTooLong))
+                } else {
+                    Ok(name.to_string())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes() {
+        let name = "my key=\u{e9}";
+        assert_eq!(
+            Sanitize::Strip.name(NameField::ParamName, name, 32),
+            Ok("mykey".to_string())
+        );
+        assert_eq!(
+            Sanitize::Replace.name(NameField::ParamName, name, 32),
+            Ok("my_key__".to_string())
+        );
+        assert_eq!(
+            Sanitize::Strict.name(NameField::SdId, name, 32),
+            Err(InvalidName {
+                field: NameField::SdId,
+                value: name.to_string(),
+                error: Error::InvalidCharacters,
+            })
+        );
+
+        let long = "a".repeat(40);
+        assert_eq!(
+            Sanitize::Strip.name(NameField::SdId, &long, 26),
+            Ok("a".repeat(26))
+        );
+        assert_eq!(
+            Sanitize::Strict
+                .name(NameField::SdId, &long, 26)
+                .unwrap_err()
+                .error,
+            Error::FieldTooLong
+        );
+        assert_eq!(
+            Sanitize::Strict
+                .name(NameField::SdId, "", 32)
+                .unwrap_err()
+                .error,
+            Error::FieldEmpty
+        );
+        for mode in &[Sanitize::Strip, Sanitize::Replace, Sanitize::Strict] {
+            assert_eq!(
+                mode.name(NameField::ParamName, "", 32),
+                Err(InvalidName {
+                    field: NameField::ParamName,
+                    value: String::new(),
+                    error: Error::FieldEmpty,
+                })
+            );
+        }
+        assert_eq!(
+            Sanitize::Strip
+                .name(NameField::SdId, "= \"]", 32)
+                .unwrap_err()
+                .error,
+            Error::FieldEmpty
+        );
+    }
+
+    #[test]
+    fn control_chars() {
+        let text = "first\tline\r\n\nsecond\x7f";
+        assert_eq!(ControlChars::Keep.apply(text), vec![text]);
+        assert_eq!(
+            ControlChars::Escape.apply(text),
+            vec!["first#011line#015#012#012second#177"]
+        );
+        assert_eq!(
+            ControlChars::Space.apply(text),
+            vec!["first line   second "]
+        );
+        assert_eq!(
+            ControlChars::Split.apply(text),
+            vec!["first#011line", "second#177"]
+        );
+        assert_eq!(ControlChars::Split.apply("\n"), vec!["#012"]);
+    }
+}
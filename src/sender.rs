@@ -0,0 +1,271 @@
+//! Sending formatted messages to a syslog receiver.
+//!
+//! A [`Transport`](trait.Transport.html) delivers one formatted message at a
+//! time, and a [`Sender`](struct.Sender.html) pairs it with the
+//! [`Rfc5424`](../struct.Rfc5424.html) formatter that produces those messages.
+//!
+//! The transport doesn't add any framing of its own: pick a
+//! [`WriteFormat`](../enum.WriteFormat.html) that suits it, such as `RFC5425`
+//! for TCP and TLS, and `RFC5424` for UDP and Unix datagram sockets.
+//!
+//! # Examples
+//! ```ignore
+//! let socket = UdpSocket::bind("0.0.0.0:0")?;
+//! socket.connect("logs.example.com:514")?;
+//!
+//! let formatter = Rfc5424Builder::new("32473", Facility::Local0)
+//!     .app_name("my_app")?
+//!     .build();
+//! let mut sender = Sender::new(formatter, socket);
+//! sender.send(&my_message)?;
+//! ```
+
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixStream};
+
+use filter::Filter;
+use {Rfc5424, Rfc5424Data};
+
+/// Delivers formatted messages to a receiver.
+///
+/// `message` is a single message, including the framing of the formatter's
+/// [`WriteFormat`](../enum.WriteFormat.html).
+pub trait Transport {
+    fn send(&mut self, message: &[u8]) -> io::Result<()>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        (**self).send(message)
+    }
+}
+
+/// Messages are sent as a datagram to the address the socket is connected to
+impl Transport for UdpSocket {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        UdpSocket::send(self, message).map(|_| ())
+    }
+}
+
+impl Transport for TcpStream {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.write_all(message)
+    }
+}
+
+/// Messages are sent as a datagram to the path the socket is connected to,
+/// for example `/dev/log`
+#[cfg(unix)]
+impl Transport for UnixDatagram {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        UnixDatagram::send(self, message).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.write_all(message)
+    }
+}
+
+/// Collects messages in memory, mostly useful for tests
+impl Transport for Vec<Vec<u8>> {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.push(message.to_vec());
+        Ok(())
+    }
+}
+
+/// Formats messages with a [`Rfc5424`](../struct.Rfc5424.html) and sends them
+/// over a [`Transport`](trait.Transport.html).
+#[derive(Debug)]
+pub struct Sender<T> {
+    formatter: Rfc5424,
+    transport: T,
+    filter: Option<Filter>,
+}
+
+impl<T: Transport> Sender<T> {
+    pub fn new(formatter: Rfc5424, transport: T) -> Sender<T> {
+        Sender {
+            formatter,
+            transport,
+            filter: None,
+        }
+    }
+
+    /// Only send messages allowed by `filter`. See the
+    /// [`filter`](../filter/index.html) module.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Format `data` and send it, unless the filter of the sender drops it.
+    ///
+    /// # Errors
+    /// Errors when the transport returns an error (`io::Error`)
+    pub fn send(&mut self, data: &impl Rfc5424Data) -> io::Result<()> {
+        if !self.allows(data) {
+            return Ok(());
+        }
+        let transport = &mut self.transport;
+        self.formatter
+            .format_each(data, |message| transport.send(message))
+    }
+
+    /// Check if the filter of the sender, if any, lets `data` through
+    pub fn allows(&self, data: &impl Rfc5424Data) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.allows(&self.formatter, data))
+    }
+
+    pub fn formatter(&self) -> &Rfc5424 {
+        &self.formatter
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Give back the formatter and the transport
+    pub fn into_parts(self) -> (Rfc5424, T) {
+        (self.formatter, self.transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use sanitize::ControlChars;
+    use std::net::TcpListener;
+    use types::*;
+    use {Rfc5424Builder, StructuredData, WriteFormat};
+
+    struct Line(&'static str);
+
+    impl Rfc5424Data for Line {
+        fn severity(&self) -> Severity {
+            Severity::Warning
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.0.to_string()))
+        }
+    }
+
+    fn formatter(write_format: WriteFormat) -> Rfc5424 {
+        Rfc5424Builder::new("", Facility::Local0)
+            .app_name("sender")
+            .unwrap()
+            .write_format(write_format)
+            .build()
+    }
+
+    #[test]
+    fn sends_each_message() {
+        let mut sender = Sender::new(formatter(WriteFormat::RFC5424), Vec::new());
+        sender.send(&Line("first")).unwrap();
+        sender.send(&Line("second")).unwrap();
+
+        let (_, sent) = sender.into_parts();
+        assert_eq!(sent.len(), 2);
+        let second = parse(&sent[1]).unwrap();
+        assert_eq!(second.severity, Severity::Warning);
+        assert_eq!(second.message, Some(Message::Text("second".into())));
+    }
+
+    #[test]
+    fn split_lines() {
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .control_chars(ControlChars::Split)
+            .build();
+        let mut sender = Sender::new(f, Vec::new());
+        sender.send(&Line("panicked at:\n  main.rs:3\n")).unwrap();
+        sender.send(&Line("one line")).unwrap();
+
+        let sent = sender.transport();
+        assert_eq!(sent.len(), 3);
+        let first = parse(&sent[0]).unwrap();
+        let second = parse(&sent[1]).unwrap();
+        assert_eq!(first.message, Some(Message::Text("panicked at:".into())));
+        assert_eq!(second.message, Some(Message::Text("  main.rs:3".into())));
+        assert_eq!(second.structured_data[0].id, "split@32473");
+        assert_eq!(
+            second.structured_data[0].params,
+            vec![
+                ("id".into(), "0".into()),
+                ("part".into(), "2".into()),
+                ("total".into(), "2".into()),
+            ]
+        );
+        assert_eq!(
+            first.structured_data[0].params[0],
+            ("id".into(), "0".into())
+        );
+        assert!(parse(&sent[2]).unwrap().structured_data.is_empty());
+    }
+
+    #[test]
+    fn filtered() {
+        let filter = Filter::new();
+        filter.set_min_severity(Severity::Error);
+        let mut sender = Sender::new(formatter(WriteFormat::RFC5424), Vec::new()).filter(filter);
+        sender.send(&Line("dropped")).unwrap();
+        assert!(sender.transport().is_empty());
+    }
+
+    #[test]
+    fn udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(receiver.local_addr().unwrap()).unwrap();
+
+        let mut sender = Sender::new(formatter(WriteFormat::RFC5424), socket);
+        sender.send(&Line("over udp")).unwrap();
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).unwrap();
+        let message = parse(&buf[..len]).unwrap();
+        assert_eq!(message.app_name, AppName::new("sender").unwrap());
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+
+        let mut sender = Sender::new(formatter(WriteFormat::RFC5425), stream);
+        sender.send(&Line("over tcp")).unwrap();
+        drop(sender);
+
+        let mut received = Vec::new();
+        io::Read::read_to_end(&mut accepted, &mut received).unwrap();
+        let expected = {
+            let mut v = Vec::new();
+            formatter(WriteFormat::RFC5425)
+                .format(&mut v, &Line("over tcp"))
+                .unwrap();
+            v
+        };
+        assert_eq!(received, expected);
+    }
+}
@@ -0,0 +1,933 @@
+//! A small syslog collector.
+//!
+//! [`Server`](struct.Server.html) receives messages over UDP, TCP, TLS, DTLS,
+//! RELP or Unix sockets, parses every frame with [`parser::parse_any`](../parser/fn.parse_any.html),
+//! and hands the result to a [`Handler`](trait.Handler.html). Both RFC5424 and
+//! RFC3164 messages are accepted.
+//!
+//! Each `serve_*` function blocks the calling thread. Stream transports spawn a
+//! thread per connection, and split the stream into frames with a
+//! [`FrameReader`](../framing/struct.FrameReader.html), so both octet counted
+//! and LF terminated frames are accepted on the same connection.
+//!
+//! # Examples
+//! ```ignore
+//! let (tx, rx) = std::sync::mpsc::channel();
+//! let server = Server::new(tx);
+//! let socket = UdpSocket::bind("0.0.0.0:514")?;
+//! std::thread::spawn(move || server.serve_udp(&socket));
+//!
+//! for received in rx {
+//!     println!("{:?}: {:?}", received.peer, received.message);
+//! }
+//! ```
+
+#[cfg(feature = "dtls")]
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+#[cfg(feature = "dtls")]
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "dtls")]
+use dtls::{self, Hello};
+use framing::{FrameError, FrameReader};
+#[cfg(feature = "dtls")]
+use openssl::ssl::SslAcceptor;
+use parser::{self, Charset, Detected, ParseError, SyslogMessage};
+use relp;
+
+/// How long to wait after failing to accept a connection
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(10);
+
+/// Where a received message came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Peer {
+    Ip(SocketAddr),
+    /// Unix socket peers are often unnamed, in which case there is no path
+    Unix(Option<PathBuf>),
+}
+
+/// A parsed message, and the peer that sent it
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub peer: Peer,
+    pub message: SyslogMessage,
+    /// Format of the message, and the framing found inside the payload: that of
+    /// datagrams, or of RELP `syslog` data. The framing of TCP, TLS and unix
+    /// streams is removed before parsing, so for them it's `Framing::None`.
+    pub detected: Detected,
+}
+
+/// Receives the messages parsed by a [`Server`](struct.Server.html).
+///
+/// Implemented for closures taking a [`Received`](struct.Received.html), and for
+/// `mpsc::Sender<Received>`. Handlers are called from the thread that read the
+/// message, so they should not block for long. Only `mpsc::Sender<Received>`
+/// implements [`store`](#method.store), which RELP needs.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, received: Received);
+
+    /// Called for frames that could not be parsed. Does nothing by default.
+    fn parse_error(&self, _peer: &Peer, _frame: &[u8], _error: ParseError) {}
+
+    /// Called instead of `handle` by transports that acknowledge every message,
+    /// such as [RELP](struct.Server.html#method.serve_relp). The sender is told
+    /// the message was taken only when this returns `Ok`, so it should return
+    /// once the message is durably stored.
+    ///
+    /// Returns an `Unsupported` error by default, so every message is refused:
+    /// a handler that doesn't implement this can't tell when a message is stored.
+    fn store(&self, _received: Received) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "handler doesn't store messages",
+        ))
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Received) + Send + Sync + 'static,
+{
+    fn handle(&self, received: Received) {
+        self(received)
+    }
+}
+
+impl Handler for Sender<Received> {
+    fn handle(&self, received: Received) {
+        // the receiver hanging up is not the server's problem
+        let _ = self.send(received);
+    }
+
+    /// Errors when the receiver hung up
+    fn store(&self, received: Received) -> io::Result<()> {
+        self.send(received)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "handler hung up"))
+    }
+}
+
+/// Limits applied by a [`Server`](struct.Server.html)
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Largest frame accepted, in bytes. Larger frames are dropped.
+    pub max_frame_len: usize,
+    /// Maximum number of stream connections open at once. Connections over the
+    /// limit are closed as soon as they are accepted.
+    pub max_connections: usize,
+    /// Stream connections idle for longer than this are closed.
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_frame_len: 64 * 1024,
+            max_connections: 256,
+            read_timeout: None,
+        }
+    }
+}
+
+/// Counters updated by a [`Server`](struct.Server.html) as it runs
+#[derive(Debug, Default)]
+pub struct Metrics {
+    messages: AtomicU64,
+    parse_errors: AtomicU64,
+    framing_errors: AtomicU64,
+    bytes: AtomicU64,
+    connections_accepted: AtomicU64,
+    connections_rejected: AtomicU64,
+    active_connections: AtomicUsize,
+    accept_errors: AtomicU64,
+}
+
+impl Metrics {
+    /// Messages parsed and passed to the handler
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    /// Frames that failed to parse
+    pub fn parse_errors(&self) -> u64 {
+        self.parse_errors.load(Ordering::Relaxed)
+    }
+
+    /// Oversized frames, and data skipped because it wasn't framed correctly
+    pub fn framing_errors(&self) -> u64 {
+        self.framing_errors.load(Ordering::Relaxed)
+    }
+
+    /// Total size of all frames received
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Stream connections accepted
+    pub fn connections_accepted(&self) -> u64 {
+        self.connections_accepted.load(Ordering::Relaxed)
+    }
+
+    /// Stream connections closed for going over `Limits::max_connections`
+    pub fn connections_rejected(&self) -> u64 {
+        self.connections_rejected.load(Ordering::Relaxed)
+    }
+
+    /// Stream connections currently open
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Failures to accept a stream connection, such as running out of file
+    /// descriptors. The server goes on accepting connections after them.
+    pub fn accept_errors(&self) -> u64 {
+        self.accept_errors.load(Ordering::Relaxed)
+    }
+}
+
+/// Wraps accepted TCP connections in TLS for [`Server::serve_tls`](struct.Server.html#method.serve_tls).
+///
+/// With the `tls` feature enabled this is implemented for `openssl::ssl::SslAcceptor`.
+pub trait TlsAcceptor: Send + Sync + 'static {
+    type Stream: Read + Send + 'static;
+
+    /// Perform the handshake on `stream`. Called on the connection's own thread.
+    fn accept(&self, stream: TcpStream) -> io::Result<Self::Stream>;
+}
+
+#[cfg(feature = "tls")]
+impl TlsAcceptor for ::openssl::ssl::SslAcceptor {
+    type Stream = ::openssl::ssl::SslStream<TcpStream>;
+
+    fn accept(&self, stream: TcpStream) -> io::Result<Self::Stream> {
+        ::openssl::ssl::SslAcceptor::accept(self, stream)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+/// Syslog collector. See the [module documentation](index.html).
+///
+/// Cloning a `Server` is cheap, and the clones share their handler and metrics.
+#[derive(Clone)]
+pub struct Server {
+    handler: Arc<dyn Handler>,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    charset: Option<Arc<dyn Charset>>,
+}
+
+impl Server {
+    pub fn new<H: Handler>(handler: H) -> Server {
+        Server {
+            handler: Arc::new(handler),
+            limits: Limits::default(),
+            metrics: Arc::new(Metrics::default()),
+            charset: None,
+        }
+    }
+
+    /// Set the limits used for every transport.
+    pub fn limits(mut self, limits: Limits) -> Server {
+        self.limits = limits;
+        self
+    }
+
+    /// Decode every `Message::Binary` with `charset`, for senders that don't
+    /// use UTF-8. Messages it can't decode are passed on as they are.
+    pub fn charset<C: Charset + 'static>(mut self, charset: C) -> Server {
+        self.charset = Some(Arc::new(charset));
+        self
+    }
+
+    /// Counters shared by every transport this server is serving.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Receive messages on `socket`, one message per datagram
+    /// ([RFC5426](https://tools.ietf.org/html/rfc5426)).
+    ///
+    /// # Errors
+    /// Returns when receiving from `socket` fails.
+    pub fn serve_udp(&self, socket: &UdpSocket) -> io::Result<()> {
+        let mut buf = vec![0; self.limits.max_frame_len + 1];
+        loop {
+            let (n, addr) = socket.recv_from(&mut buf)?;
+            self.frame(&buf[..n], &Peer::Ip(addr));
+        }
+    }
+
+    /// Accept plain TCP connections on `listener`. Failures to accept a
+    /// connection are counted in
+    /// [`Metrics::accept_errors`](struct.Metrics.html#method.accept_errors), and
+    /// don't stop the server.
+    pub fn serve_tcp(&self, listener: &TcpListener) -> io::Result<()> {
+        self.serve_tls(listener, Plain)
+    }
+
+    /// Accept TCP connections on `listener`, and wrap each of them with `acceptor`
+    /// ([RFC5425](https://tools.ietf.org/html/rfc5425)). Connections failing the
+    /// handshake are closed.
+    ///
+    /// Failures to accept a connection are counted in
+    /// [`Metrics::accept_errors`](struct.Metrics.html#method.accept_errors), and
+    /// don't stop the server.
+    pub fn serve_tls<A: TlsAcceptor>(&self, listener: &TcpListener, acceptor: A) -> io::Result<()> {
+        let acceptor = Arc::new(acceptor);
+        loop {
+            let (stream, addr) = match self.accepted(listener.accept(), TcpStream::set_read_timeout)
+            {
+                Some(accepted) => accepted,
+                None => continue,
+            };
+            let acceptor = acceptor.clone();
+            self.spawn_connection(Peer::Ip(addr), move || acceptor.accept(stream));
+        }
+    }
+
+    /// Receive messages over DTLS ([RFC6012](https://tools.ietf.org/html/rfc6012))
+    /// on `socket`. Every peer address gets its own association, accepted with
+    /// `acceptor` and read on its own thread like a stream connection, so the
+    /// connection limit applies. See [`dtls`](../dtls/index.html).
+    ///
+    /// When `acceptor` does a [`cookie_exchange`](../dtls/fn.cookie_exchange.html),
+    /// ClientHellos without a valid cookie are answered from this thread, and
+    /// only peers that echo their cookie get an association. Datagrams from
+    /// spoofed addresses then never use up a connection.
+    ///
+    /// # Errors
+    /// Returns when receiving from `socket` fails.
+    #[cfg(feature = "dtls")]
+    pub fn serve_dtls(&self, socket: &UdpSocket, acceptor: SslAcceptor) -> io::Result<()> {
+        let socket = Arc::new(socket.try_clone()?);
+        let acceptor = Arc::new(acceptor);
+        let (closed, closed_rx) = mpsc::channel();
+        let mut associations: HashMap<SocketAddr, (u64, SyncSender<Vec<u8>>)> = HashMap::new();
+        let mut next_id = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let (n, addr) = socket.recv_from(&mut buf)?;
+            for (peer, id) in closed_rx.try_iter() {
+                if associations.get(&peer).is_some_and(|a| a.0 == id) {
+                    associations.remove(&peer);
+                }
+            }
+            let datagram = &buf[..n];
+            if let Some((_, queue)) = associations.get(&addr) {
+                // a full queue drops the datagram, as the network would
+                match queue.try_send(datagram.to_vec()) {
+                    Err(TrySendError::Disconnected(_)) => {}
+                    _ => continue,
+                }
+            }
+
+            let first = match dtls::hello(&acceptor, &addr, datagram) {
+                Hello::Accept(first) => first,
+                Hello::Verify(reply) => {
+                    let _ = socket.send_to(&reply, addr);
+                    continue;
+                }
+                Hello::Ignore => continue,
+            };
+            let (queue, datagrams) = mpsc::sync_channel(64);
+            for datagram in first {
+                let _ = queue.send(datagram);
+            }
+            next_id += 1;
+            associations.insert(addr, (next_id, queue));
+            let association = dtls::Association {
+                socket: socket.clone(),
+                peer: addr,
+                id: next_id,
+                datagrams,
+                timeout: None,
+                closed: closed.clone(),
+            };
+            let acceptor = acceptor.clone();
+            let idle = self.limits.read_timeout;
+            self.spawn_connection(Peer::Ip(addr), move || {
+                dtls::accept(&acceptor, association, idle)
+            });
+        }
+    }
+
+    /// Accept RELP sessions on `listener`, as sent by rsyslog's `omrelp`. See
+    /// [`relp`](../relp/index.html).
+    ///
+    /// Every message is handed to [`Handler::store`](trait.Handler.html#method.store),
+    /// and acknowledged with `rsp 200` only once it returns `Ok`. Messages that
+    /// can't be parsed or stored are answered with `500`, so the handler must
+    /// implement `store`: closures get every message refused. The data of a
+    /// `syslog` command may also carry the framing of `WriteFormat::RFC5425` or
+    /// `RFC6587`.
+    ///
+    /// Failures to accept a connection are counted in
+    /// [`Metrics::accept_errors`](struct.Metrics.html#method.accept_errors), and
+    /// don't stop the server.
+    pub fn serve_relp(&self, listener: &TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, addr) = match self.accepted(listener.accept(), TcpStream::set_read_timeout)
+            {
+                Some(accepted) => accepted,
+                None => continue,
+            };
+            let peer = Peer::Ip(addr);
+            self.spawn(move |server| {
+                let max_data_len = server.limits.max_frame_len;
+                let _ = relp::serve_connection(stream, max_data_len, |data| {
+                    match server.parse_frame(data, &peer) {
+                        Some(received) => server.handler.store(received),
+                        None => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "message can't be parsed",
+                        )),
+                    }
+                });
+            });
+        }
+    }
+
+    /// Receive messages on a Unix datagram socket, such as `/dev/log`.
+    ///
+    /// # Errors
+    /// Returns when receiving from `socket` fails.
+    #[cfg(unix)]
+    pub fn serve_unix_datagram(&self, socket: &UnixDatagram) -> io::Result<()> {
+        let mut buf = vec![0; self.limits.max_frame_len + 1];
+        loop {
+            let (n, addr) = socket.recv_from(&mut buf)?;
+            let peer = Peer::Unix(addr.as_pathname().map(|p| p.to_path_buf()));
+            self.frame(&buf[..n], &peer);
+        }
+    }
+
+    /// Accept Unix stream connections on `listener`. Failures to accept a
+    /// connection are counted in
+    /// [`Metrics::accept_errors`](struct.Metrics.html#method.accept_errors), and
+    /// don't stop the server.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: &UnixListener) -> io::Result<()> {
+        loop {
+            let (stream, addr) =
+                match self.accepted(listener.accept(), UnixStream::set_read_timeout) {
+                    Some(accepted) => accepted,
+                    None => continue,
+                };
+            let peer = Peer::Unix(addr.as_pathname().map(|p| p.to_path_buf()));
+            self.spawn_connection(peer, move || Ok(stream));
+        }
+    }
+
+    /// The connection `accept` returned, with the read timeout of the limits set.
+    /// Errors are counted, and followed by a short pause, since errors such as
+    /// running out of file descriptors last for a while.
+    fn accepted<S, A>(
+        &self,
+        accept: io::Result<(S, A)>,
+        set_read_timeout: fn(&S, Option<Duration>) -> io::Result<()>,
+    ) -> Option<(S, A)> {
+        let accepted = accept.and_then(|(stream, addr)| {
+            set_read_timeout(&stream, self.limits.read_timeout)?;
+            Ok((stream, addr))
+        });
+        if accepted.is_err() {
+            self.metrics.accept_errors.fetch_add(1, Ordering::Relaxed);
+            thread::sleep(ACCEPT_ERROR_PAUSE);
+        }
+        accepted.ok()
+    }
+
+    /// Read frames from a stream connection on a new thread, if the connection
+    /// limit allows it. `open` runs on that thread before the first read.
+    fn spawn_connection<S, F>(&self, peer: Peer, open: F)
+    where
+        S: Read,
+        F: FnOnce() -> io::Result<S> + Send + 'static,
+    {
+        self.spawn(move |server| {
+            if let Ok(stream) = open() {
+                server.read_stream(stream, &peer);
+            }
+        });
+    }
+
+    /// Run `connection` on a new thread, if the connection limit allows it.
+    fn spawn<F>(&self, connection: F)
+    where
+        F: FnOnce(&Server) + Send + 'static,
+    {
+        let active = self
+            .metrics
+            .active_connections
+            .fetch_add(1, Ordering::Relaxed);
+        if active >= self.limits.max_connections {
+            self.metrics
+                .active_connections
+                .fetch_sub(1, Ordering::Relaxed);
+            self.metrics
+                .connections_rejected
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.metrics
+            .connections_accepted
+            .fetch_add(1, Ordering::Relaxed);
+
+        let server = self.clone();
+        thread::spawn(move || {
+            let _guard = ConnectionGuard(&server.metrics);
+            connection(&server);
+        });
+    }
+
+    fn read_stream<S: Read>(&self, stream: S, peer: &Peer) {
+        for frame in FrameReader::new(stream, self.limits.max_frame_len) {
+            match frame {
+                Ok(frame) => self.frame(&frame, peer),
+                Err(FrameError::Io(_)) => return,
+                Err(_) => {
+                    self.metrics.framing_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn frame(&self, frame: &[u8], peer: &Peer) {
+        if let Some(received) = self.parse_frame(frame, peer) {
+            self.handler.handle(received);
+        }
+    }
+
+    /// Parse `frame`, updating the metrics. Frames that can't be parsed are
+    /// passed to `Handler::parse_error`.
+    fn parse_frame(&self, frame: &[u8], peer: &Peer) -> Option<Received> {
+        self.metrics
+            .bytes
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+        if frame.len() > self.limits.max_frame_len {
+            self.metrics.framing_errors.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        match parser::parse_any(frame) {
+            Ok((mut message, detected)) => {
+                if let Some(charset) = &self.charset {
+                    message.decode_message(&**charset);
+                }
+                self.metrics.messages.fetch_add(1, Ordering::Relaxed);
+                Some(Received {
+                    peer: peer.clone(),
+                    message,
+                    detected,
+                })
+            }
+            Err(e) => {
+                self.metrics.parse_errors.fetch_add(1, Ordering::Relaxed);
+                self.handler.parse_error(peer, frame, e);
+                None
+            }
+        }
+    }
+}
+
+/// `TlsAcceptor` used for plain TCP connections
+struct Plain;
+
+impl TlsAcceptor for Plain {
+    type Stream = TcpStream;
+
+    fn accept(&self, stream: TcpStream) -> io::Result<TcpStream> {
+        Ok(stream)
+    }
+}
+
+/// Decrements the active connection count when a connection thread ends,
+/// even if the handler panicked.
+struct ConnectionGuard<'a>(&'a Metrics);
+
+impl<'a> Drop for ConnectionGuard<'a> {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Format;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::mpsc::{channel, Receiver};
+    use types::*;
+    use {Rfc5424Builder, Rfc5424Data, StructuredData, WriteFormat};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct TestMessage(&'static str);
+
+    impl Rfc5424Data for TestMessage {
+        fn severity(&self) -> Severity {
+            Severity::Warning
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            let mut sd: StructuredData = HashMap::new();
+            sd.insert("test", vec![("text".into(), self.0.into())]);
+            Some(sd)
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.0.into()))
+        }
+    }
+
+    fn format(text: &'static str, write_format: WriteFormat) -> Vec<u8> {
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("server_test")
+            .unwrap()
+            .write_format(write_format)
+            .build();
+        let mut out = Vec::new();
+        f.format(&mut out, &TestMessage(text)).unwrap();
+        out
+    }
+
+    fn assert_received(rx: &Receiver<Received>, text: &str) {
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(received.message.facility, Facility::Local0);
+        assert_eq!(received.message.severity, Severity::Warning);
+        assert_eq!(
+            received.message.app_name,
+            AppName::new("server_test").unwrap()
+        );
+        assert_eq!(received.message.structured_data[0].id, "test@32473");
+        assert_eq!(received.message.message, Some(Message::Text(text.into())));
+    }
+
+    fn tcp_server(server: Server) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_tcp(&listener));
+        addr
+    }
+
+    #[test]
+    fn udp() {
+        let (tx, rx) = channel();
+        let server = Server::new(tx);
+        let metrics = server.metrics();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || server.serve_udp(&socket));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(&format("first", WriteFormat::RFC5424), addr)
+            .unwrap();
+        client
+            .send_to(b"<13>Oct 11 22:14:15 host app: legacy", addr)
+            .unwrap();
+        client.send_to(b"<999>not syslog", addr).unwrap();
+        client
+            .send_to(&format("second", WriteFormat::RFC5424), addr)
+            .unwrap();
+
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(received.peer, Peer::Ip(client.local_addr().unwrap()));
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text("first".into()))
+        );
+        assert_eq!(received.detected.format, Format::Rfc5424);
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text("legacy".into()))
+        );
+        assert_eq!(received.detected.format, Format::Rfc3164);
+        assert_received(&rx, "second");
+        assert_eq!(metrics.messages(), 3);
+        assert_eq!(metrics.parse_errors(), 1);
+    }
+
+    #[test]
+    fn charset() {
+        let (tx, rx) = channel();
+        let server = Server::new(tx).charset(parser::Latin1);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || server.serve_udp(&socket));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"<13>1 - - - - - - caf\xe9", addr).unwrap();
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text("caf\u{e9}".into()))
+        );
+    }
+
+    #[test]
+    fn tcp_mixed_framing() {
+        let (tx, rx) = channel();
+        let addr = tcp_server(Server::new(tx));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(&format("octet counted", WriteFormat::RFC5425))
+            .unwrap();
+        let mut lf = format("lf framed", WriteFormat::RFC5424);
+        lf.push(b'\n');
+        client.write_all(&lf).unwrap();
+        client
+            .write_all(&format("octet counted again", WriteFormat::RFC5425))
+            .unwrap();
+
+        assert_received(&rx, "octet counted");
+        assert_received(&rx, "lf framed");
+        assert_received(&rx, "octet counted again");
+    }
+
+    #[test]
+    fn tcp_limits() {
+        let (tx, rx) = channel();
+        let server = Server::new(tx).limits(Limits {
+            max_frame_len: 100,
+            max_connections: 1,
+            read_timeout: None,
+        });
+        let metrics = server.metrics();
+        let addr = tcp_server(server);
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        first
+            .write_all(&format("hello", WriteFormat::RFC5425))
+            .unwrap();
+        assert_received(&rx, "hello");
+
+        // the second connection is over the limit, so it's closed straight away
+        let mut second = TcpStream::connect(addr).unwrap();
+        second.set_read_timeout(Some(TIMEOUT)).unwrap();
+        assert_eq!(second.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(metrics.connections_rejected(), 1);
+
+        // oversized frames are skipped
+        first.write_all(b"101 ").unwrap();
+        first.write_all(&[b'x'; 101]).unwrap();
+        first
+            .write_all(&format("after", WriteFormat::RFC5425))
+            .unwrap();
+        assert_received(&rx, "after");
+        assert_eq!(metrics.framing_errors(), 1);
+    }
+
+    #[test]
+    fn accept_errors() {
+        let server = Server::new(|_: Received| {});
+        let failed: io::Result<(TcpStream, SocketAddr)> = Err(io::Error::other("EMFILE"));
+        assert!(server
+            .accepted(failed, TcpStream::set_read_timeout)
+            .is_none());
+        assert_eq!(server.metrics().accept_errors(), 1);
+    }
+
+    #[test]
+    fn tls_acceptor() {
+        struct Reverse;
+        impl TlsAcceptor for Reverse {
+            type Stream = io::Cursor<Vec<u8>>;
+            fn accept(&self, mut stream: TcpStream) -> io::Result<Self::Stream> {
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                data.reverse();
+                Ok(io::Cursor::new(data))
+            }
+        }
+
+        let (tx, rx) = channel();
+        let server = Server::new(tx);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_tls(&listener, Reverse));
+
+        let mut data = format("wrapped", WriteFormat::RFC5425);
+        data.reverse();
+        TcpStream::connect(addr).unwrap().write_all(&data).unwrap();
+        assert_received(&rx, "wrapped");
+    }
+
+    #[test]
+    fn relp() {
+        use relp::RelpTransport;
+        use sender::Transport;
+
+        /// Stores every message but "full"
+        struct Store(Sender<Received>);
+        impl Handler for Store {
+            fn handle(&self, _: Received) {
+                panic!("RELP messages are stored");
+            }
+            fn store(&self, received: Received) -> io::Result<()> {
+                if received.message.message == Some(Message::Text("full".into())) {
+                    return Err(io::Error::other("disk full"));
+                }
+                self.0.store(received)
+            }
+        }
+
+        let (tx, rx) = channel();
+        let server = Server::new(Store(tx));
+        let metrics = server.metrics();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_relp(&listener));
+
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("server_test")
+            .unwrap()
+            .build();
+        let transport = RelpTransport::connect(addr).unwrap();
+        let mut sender = transport.into_sender(f);
+        sender.send(&TestMessage("stored")).unwrap();
+        sender.transport_mut().flush().unwrap();
+        assert_received(&rx, "stored");
+
+        sender.send(&TestMessage("full")).unwrap();
+        let refused = sender.transport_mut().flush().unwrap_err();
+        assert!(refused.to_string().contains("500 disk full"));
+
+        let transport = sender.transport_mut();
+        transport
+            .send(&format("framed", WriteFormat::RFC5425))
+            .unwrap();
+        transport.send(b"").unwrap();
+        assert!(transport.flush().is_err());
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(received.detected.framing, parser::Framing::OctetCounting);
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text("framed".into()))
+        );
+        assert_eq!(metrics.parse_errors(), 1);
+    }
+
+    #[test]
+    fn relp_needs_store() {
+        use relp::RelpTransport;
+
+        let server = Server::new(|_: Received| panic!("closures don't store messages"));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_relp(&listener));
+
+        let f = Rfc5424Builder::new("32473", Facility::Local0).build();
+        let mut sender = RelpTransport::connect(addr).unwrap().into_sender(f);
+        sender.send(&TestMessage("not stored")).unwrap();
+        let refused = sender.transport_mut().flush().unwrap_err();
+        assert!(refused
+            .to_string()
+            .contains("500 handler doesn't store messages"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_sockets() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::net::UnixStream;
+
+        let dir = env::temp_dir().join(format!("syslog5424-server-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dgram_path = dir.join("dgram.sock");
+        let stream_path = dir.join("stream.sock");
+        let _ = fs::remove_file(&dgram_path);
+        let _ = fs::remove_file(&stream_path);
+
+        let (tx, rx) = channel();
+        let server = Server::new(tx);
+        let socket = UnixDatagram::bind(&dgram_path).unwrap();
+        let listener = UnixListener::bind(&stream_path).unwrap();
+        let s = server.clone();
+        thread::spawn(move || s.serve_unix_datagram(&socket));
+        thread::spawn(move || server.serve_unix(&listener));
+
+        let client = UnixDatagram::unbound().unwrap();
+        client
+            .send_to(&format("datagram", WriteFormat::RFC5424), &dgram_path)
+            .unwrap();
+        let received = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(received.peer, Peer::Unix(None));
+        assert_eq!(
+            received.message.message,
+            Some(Message::Text("datagram".into()))
+        );
+
+        let mut stream = UnixStream::connect(&stream_path).unwrap();
+        stream
+            .write_all(&format("stream", WriteFormat::RFC5425))
+            .unwrap();
+        assert_received(&rx, "stream");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls() {
+        use openssl::asn1::Asn1Time;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslVerifyMode};
+        use openssl::x509::{X509NameBuilder, X509};
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        let acceptor = acceptor.build();
+
+        let (tx, rx) = channel();
+        let server = Server::new(tx);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_tls(&listener, acceptor));
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let tcp = TcpStream::connect(addr).unwrap();
+        let mut client = connector.build().connect("localhost", tcp).unwrap();
+        client
+            .write_all(&format("encrypted", WriteFormat::RFC5425))
+            .unwrap();
+        client.flush().unwrap();
+        assert_received(&rx, "encrypted");
+    }
+}
@@ -0,0 +1,413 @@
+//! ArcSight CEF and QRadar LEEF messages, for SIEMs that expect them in the MSG.
+//!
+//! A [`Renderer`](struct.Renderer.html) turns `Rfc5424Data` into the text of a
+//! MSG. The header of both formats names the device that sent the event:
+//! its product and version are the `software` and `swVersion`
+//! [`Origin`](../iana/enum.Origin.html) of the formatter when it has them. The
+//! structured data of the message becomes the extension, with keys made of the
+//! SD-ID and the PARAM-NAME: `[req path="/"]` is written as `req_path=/`.
+//!
+//! [`Renderer::message`](struct.Renderer.html#method.message) wraps the data so
+//! it can be formatted as usual, with the rendered text as its MSG.
+//!
+//! # Examples
+//! ```ignore
+//! let cef = Renderer::cef("Example Corp");
+//! sender.send(&cef.message(sender.formatter(), &my_message))?;
+//! // <132>1 ... CEF:0|Example Corp|my_app|1.2.0|LOGIN|user logged in|5|req_user=alice
+//! ```
+
+use iana::{Alarm, Origin};
+use types::*;
+use {Rfc5424, Rfc5424Data, StructuredData};
+
+/// Event severity on the 0 to 10 scale of CEF and LEEF, where 10 is the most
+/// severe
+pub fn severity(severity: Severity) -> u8 {
+    match severity {
+        Severity::Emergency => 10,
+        Severity::Alert => 9,
+        Severity::Critical => 8,
+        Severity::Error => 7,
+        Severity::Warning => 5,
+        Severity::Notice => 3,
+        Severity::Informational => 1,
+        Severity::Debug => 0,
+    }
+}
+
+/// Output format of a [`Renderer`](struct.Renderer.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiemFormat {
+    /// `CEF:0|vendor|product|version|signatureId|name|severity|extension`, with
+    /// the extension separated by spaces
+    Cef,
+    /// `LEEF:1.0|vendor|product|version|eventId|attributes`, with the
+    /// attributes separated by tabs
+    Leef,
+}
+
+/// Renders `Rfc5424Data` as CEF or LEEF. See the [module documentation](index.html).
+///
+/// The event id (CEF `signatureId`, LEEF `eventId`) is the MSGID of the
+/// message. CEF uses the text of the MSG as the event `name`, and LEEF writes
+/// it as the `msg` attribute.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    format: SiemFormat,
+    vendor: String,
+    product: Option<String>,
+    version: String,
+}
+
+impl Renderer {
+    pub fn cef(vendor: &str) -> Renderer {
+        Renderer::new(SiemFormat::Cef, vendor)
+    }
+
+    pub fn leef(vendor: &str) -> Renderer {
+        Renderer::new(SiemFormat::Leef, vendor)
+    }
+
+    fn new(format: SiemFormat, vendor: &str) -> Renderer {
+        Renderer {
+            format,
+            vendor: vendor.to_string(),
+            product: None,
+            version: String::new(),
+        }
+    }
+
+    /// Product written when the formatter has no `software` origin. The
+    /// APP-NAME of the message is used otherwise.
+    pub fn product(mut self, product: &str) -> Renderer {
+        self.product = Some(product.to_string());
+        self
+    }
+
+    /// Version written when the formatter has no `swVersion` origin. Empty by
+    /// default.
+    pub fn version(mut self, version: &str) -> Renderer {
+        self.version = version.to_string();
+        self
+    }
+
+    /// The text of the MSG for `data`, formatted with `formatter`.
+    pub fn render(&self, formatter: &Rfc5424, data: &impl Rfc5424Data) -> String {
+        let header = formatter.header(data);
+        let mut product = None;
+        let mut version = None;
+        for origin in &formatter.iana_origin {
+            match origin {
+                Origin::Software(s) => product = product.or(Some(&s[..])),
+                Origin::Version(s) => version = version.or(Some(&s[..])),
+                _ => {}
+            }
+        }
+        let product = product
+            .or(self.product.as_deref())
+            .unwrap_or(&header.app_name.0);
+        let version = version.unwrap_or(&self.version);
+        let text = match data.message() {
+            Some(Message::Text(text)) => text,
+            Some(Message::Binary(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+            None => String::new(),
+        };
+        let extension = extension(data.structured_data());
+
+        match self.format {
+            SiemFormat::Cef => {
+                let name = if text.is_empty() {
+                    &header.msg_id.0
+                } else {
+                    &text
+                };
+                let mut cef = format!(
+                    "CEF:0|{}|{}|{}|{}|{}|{}|",
+                    cef_header(&self.vendor),
+                    cef_header(product),
+                    cef_header(version),
+                    cef_header(&header.msg_id.0),
+                    cef_header(name),
+                    severity(data.severity())
+                );
+                let extension: Vec<String> = extension
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, cef_value(value)))
+                    .collect();
+                cef.push_str(&extension.join(" "));
+                cef
+            }
+            SiemFormat::Leef => {
+                let mut leef = format!(
+                    "LEEF:1.0|{}|{}|{}|{}|sev={}",
+                    leef_header(&self.vendor),
+                    leef_header(product),
+                    leef_header(version),
+                    leef_header(&header.msg_id.0),
+                    severity(data.severity()).max(1)
+                );
+                if !text.is_empty() {
+                    leef.push_str("\tmsg=");
+                    leef.push_str(&leef_value(&text));
+                }
+                for (key, value) in &extension {
+                    leef.push('\t');
+                    leef.push_str(key);
+                    leef.push('=');
+                    leef.push_str(&leef_value(value));
+                }
+                leef
+            }
+        }
+    }
+
+    /// Wrap `data` so that its MSG is rendered by `self`. The wrapper has no
+    /// structured data of its own, since it is part of the MSG.
+    pub fn message<'a, D: Rfc5424Data>(&self, formatter: &Rfc5424, data: &'a D) -> Rendered<'a, D> {
+        Rendered {
+            data,
+            text: self.render(formatter, data),
+        }
+    }
+}
+
+/// `Rfc5424Data` with a CEF or LEEF MSG, made by
+/// [`Renderer::message`](struct.Renderer.html#method.message)
+#[derive(Debug)]
+pub struct Rendered<'a, D: 'a> {
+    data: &'a D,
+    text: String,
+}
+
+impl<'a, D: Rfc5424Data> Rendered<'a, D> {
+    /// The rendered MSG
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<'a, D: Rfc5424Data> Rfc5424Data for Rendered<'a, D> {
+    fn severity(&self) -> Severity {
+        self.data.severity()
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        self.data.timestamp()
+    }
+
+    fn structured_data(&self) -> Option<StructuredData<'_>> {
+        None
+    }
+
+    fn message(&self) -> Option<Message> {
+        Some(Message::Text(self.text.clone()))
+    }
+
+    fn pid(&self) -> Option<ProcessId> {
+        self.data.pid()
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        self.data.msg_id()
+    }
+
+    fn app_name(&self) -> Option<AppName> {
+        self.data.app_name()
+    }
+
+    fn hostname(&self) -> Option<HostName> {
+        self.data.hostname()
+    }
+
+    fn facility(&self) -> Option<Facility> {
+        self.data.facility()
+    }
+
+    fn alarm(&self) -> Option<Alarm> {
+        self.data.alarm()
+    }
+}
+
+/// The `sdid_param` keys and values of the structured data, sorted by SD-ID.
+/// Characters other than ASCII letters, digits and `'_'` are left out of keys.
+fn extension(sd: Option<StructuredData>) -> Vec<(String, String)> {
+    let mut elements: Vec<_> = sd.into_iter().flatten().collect();
+    elements.sort_by_key(|&(id, _)| id);
+    let mut extension = Vec::new();
+    for (id, params) in elements {
+        for (name, value) in params {
+            let key: String = format!("{}_{}", id, name)
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect();
+            extension.push((key, value));
+        }
+    }
+    extension
+}
+
+/// CEF header fields escape `'|'` and `'\'`, and can't hold line breaks.
+fn cef_header(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '|' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// CEF extension values escape `'='` and `'\'`, and write line breaks as `\n`
+/// and `\r`.
+fn cef_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '=' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// LEEF header fields escape `'|'` and `'\'`, and can't hold control
+/// characters.
+fn leef_header(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '|' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// LEEF attribute values can't hold the tab delimiter, or any other control
+/// character, which are replaced with spaces. Nothing else is escaped.
+fn leef_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use Rfc5424Builder;
+
+    struct Event {
+        text: &'static str,
+        sd: Vec<(&'static str, &'static str, &'static str)>,
+    }
+
+    impl Rfc5424Data for Event {
+        fn severity(&self) -> Severity {
+            Severity::Warning
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            let mut sd: StructuredData = HashMap::new();
+            for &(id, name, value) in &self.sd {
+                sd.entry(id)
+                    .or_default()
+                    .push((name.to_string(), value.to_string()));
+            }
+            Some(sd)
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.text.to_string()))
+        }
+
+        fn msg_id(&self) -> Option<MessageId> {
+            Some(MessageId::new("LOGIN").unwrap())
+        }
+    }
+
+    fn event() -> Event {
+        Event {
+            text: "user logged in",
+            sd: vec![
+                ("req", "user", "alice"),
+                ("req", "path", "/a=b\\c\nd"),
+                ("auth@32473", "method", "pass\tword|x"),
+            ],
+        }
+    }
+
+    #[test]
+    fn cef() {
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("my_app")
+            .unwrap()
+            .origin(Origin::Version("1.2.0".into()))
+            .build();
+        assert_eq!(
+            Renderer::cef("Example|Corp").render(&f, &event()),
+            "CEF:0|Example\\|Corp|my_app|1.2.0|LOGIN|user logged in|5|\
+             auth32473_method=pass\tword|x req_user=alice req_path=/a\\=b\\\\c\\nd"
+        );
+
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .origin(Origin::Software("sshd".into()))
+            .build();
+        let renderer = Renderer::cef("Example").product("unused").version("9");
+        let quiet = Event {
+            text: "",
+            sd: Vec::new(),
+        };
+        assert_eq!(
+            renderer.render(&f, &quiet),
+            "CEF:0|Example|sshd|9|LOGIN|LOGIN|5|"
+        );
+    }
+
+    #[test]
+    fn leef() {
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .origin(Origin::Software("my|app".into()))
+            .build();
+        assert_eq!(
+            Renderer::leef("Example").version("2").render(&f, &event()),
+            "LEEF:1.0|Example|my\\|app|2|LOGIN|sev=5\tmsg=user logged in\t\
+             auth32473_method=pass word|x\treq_user=alice\treq_path=/a=b\\c d"
+        );
+    }
+
+    #[test]
+    fn message() {
+        let f = Rfc5424Builder::new("32473", Facility::Local0).build();
+        let event = event();
+        let rendered = Renderer::cef("Example").message(&f, &event);
+        assert!(rendered.text().starts_with("CEF:0|Example|-||LOGIN|"));
+
+        let mut out = Vec::new();
+        f.format(&mut out, &rendered).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("<132>1 - - - - LOGIN - \u{feff}CEF:0|"));
+        assert_eq!(severity(Severity::Emergency), 10);
+        assert_eq!(severity(Severity::Debug), 0);
+    }
+}
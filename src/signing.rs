@@ -0,0 +1,925 @@
+//! Signed syslog messages ([RFC5848](https://tools.ietf.org/html/rfc5848)).
+//!
+//! A [`SignedSender`](struct.SignedSender.html) wraps a
+//! [`Sender`](../sender/struct.Sender.html) and hashes every message it sends.
+//! Every `max_hashes` messages, and on [`flush`](struct.SignedSender.html#method.flush),
+//! it sends a signature block: a message with an `ssign` SD-ELEMENT holding the
+//! hashes and a signature over the whole block. Certificate blocks (`ssign-cert`)
+//! carrying the public key, or a certificate, are sent before the first
+//! signature block and then periodically, so collectors that start late can
+//! find the key.
+//!
+//! A [`Verifier`](struct.Verifier.html) takes the received messages, checks the
+//! blocks with a trusted public key, and reports the messages that are missing,
+//! altered or unsigned.
+//!
+//! Details of this implementation:
+//!
+//! * A single signature group is used (`SG="0"`).
+//! * Hashes are taken over the message without the transport framing, that is
+//!   without the RFC5425 length or the RFC6587 LF.
+//! * The signature schemes are DSA, RSA (PKCS#1 v1.5) and Ed25519. RFC5848
+//!   only registers scheme `1` (OpenPGP DSA): DSA signatures are written DER
+//!   encoded, and RSA and Ed25519 use the unregistered schemes `2` and `3`,
+//!   which only this module understands.
+//! * The reboot session ID (RSID) must increase every time the signer starts.
+//!   [`next_rsid`](fn.next_rsid.html) keeps it in a file.
+//!
+//! Blocks are formatted by the formatter of the sender, which must not use
+//! `WriteFormat::RFC3164`, and whose `max_len` must leave room for them.
+//!
+//! # Examples
+//! ```ignore
+//! let key = SigningKey::new(PKey::private_key_from_pem(&fs::read("signer.pem")?)?)?;
+//! let rsid = signing::next_rsid(Path::new("/var/lib/myapp/rsid"))?;
+//! let mut signed = SignedSender::new(Sender::new(formatter, socket), key, rsid);
+//! signed.send(&message)?;
+//! // at shutdown, sign the messages sent since the last signature block
+//! signed.flush()?;
+//!
+//! // on the collector
+//! let mut verifier = Verifier::new(VerifyingKey::new(public_key)?);
+//! for frame in frames {
+//!     verifier.push(&frame);
+//! }
+//! for finding in verifier.finish().findings {
+//!     eprintln!("{:?}", finding);
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use openssl::base64;
+use openssl::hash::{self, MessageDigest};
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::sign;
+
+use parser::{self, SdElement};
+use rfc3164::utc_now;
+use sender::{Sender, Transport};
+use types::*;
+use {Rfc5424Data, StructuredData, WriteFormat};
+
+/// Largest RSID, GBC and message number allowed by the RFC
+const MAX_COUNTER: u64 = 9_999_999_999;
+/// Length of the part of the certificate payload sent in each `ssign-cert` block
+const FRAGMENT_LEN: usize = 1024;
+
+/// Hash algorithm of the message hashes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    fn code(self) -> char {
+        match self {
+            HashAlgorithm::Sha1 => '1',
+            HashAlgorithm::Sha256 => '2',
+        }
+    }
+
+    fn from_code(code: char) -> Option<HashAlgorithm> {
+        match code {
+            '1' => Some(HashAlgorithm::Sha1),
+            '2' => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    fn digest(self) -> MessageDigest {
+        match self {
+            HashAlgorithm::Sha1 => MessageDigest::sha1(),
+            HashAlgorithm::Sha256 => MessageDigest::sha256(),
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(hash::hash(self.digest(), data)
+            .map_err(io::Error::other)?
+            .to_vec())
+    }
+}
+
+/// Signature scheme, decided by the type of the key
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignatureScheme {
+    Dsa,
+    Rsa,
+    Ed25519,
+}
+
+impl SignatureScheme {
+    fn code(self) -> char {
+        match self {
+            SignatureScheme::Dsa => '1',
+            SignatureScheme::Rsa => '2',
+            SignatureScheme::Ed25519 => '3',
+        }
+    }
+
+    fn of(id: Id) -> io::Result<SignatureScheme> {
+        match id {
+            Id::DSA => Ok(SignatureScheme::Dsa),
+            Id::RSA => Ok(SignatureScheme::Rsa),
+            Id::ED25519 => Ok(SignatureScheme::Ed25519),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported key type, expected DSA, RSA or Ed25519",
+            )),
+        }
+    }
+}
+
+/// Private key signing the blocks
+pub struct SigningKey {
+    key: PKey<Private>,
+    scheme: SignatureScheme,
+}
+
+impl SigningKey {
+    /// # Errors
+    /// Errors with `InvalidInput` when `key` isn't a DSA, RSA or Ed25519 key
+    pub fn new(key: PKey<Private>) -> io::Result<SigningKey> {
+        let scheme = SignatureScheme::of(key.id())?;
+        Ok(SigningKey { key, scheme })
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
+    /// The public key matching this key, for a verifier
+    pub fn verifying_key(&self) -> io::Result<VerifyingKey> {
+        let der = self.key.public_key_to_der().map_err(io::Error::other)?;
+        VerifyingKey::new(PKey::public_key_from_der(&der).map_err(io::Error::other)?)
+    }
+
+    fn sign(&self, hash: HashAlgorithm, data: &[u8]) -> io::Result<Vec<u8>> {
+        let signature = match self.scheme {
+            SignatureScheme::Ed25519 => sign::Signer::new_without_digest(&self.key)
+                .and_then(|mut signer| signer.sign_oneshot_to_vec(data)),
+            _ => sign::Signer::new(hash.digest(), &self.key).and_then(|mut signer| {
+                signer.update(data)?;
+                signer.sign_to_vec()
+            }),
+        };
+        signature.map_err(io::Error::other)
+    }
+}
+
+/// Public key checking the blocks
+pub struct VerifyingKey {
+    key: PKey<Public>,
+    scheme: SignatureScheme,
+}
+
+impl VerifyingKey {
+    /// # Errors
+    /// Errors with `InvalidInput` when `key` isn't a DSA, RSA or Ed25519 key
+    pub fn new(key: PKey<Public>) -> io::Result<VerifyingKey> {
+        let scheme = SignatureScheme::of(key.id())?;
+        Ok(VerifyingKey { key, scheme })
+    }
+
+    fn verify(&self, hash: HashAlgorithm, data: &[u8], signature: &[u8]) -> bool {
+        let valid = match self.scheme {
+            SignatureScheme::Ed25519 => sign::Verifier::new_without_digest(&self.key)
+                .and_then(|mut verifier| verifier.verify_oneshot(signature, data)),
+            _ => sign::Verifier::new(hash.digest(), &self.key).and_then(|mut verifier| {
+                verifier.update(data)?;
+                verifier.verify(signature)
+            }),
+        };
+        valid.unwrap_or(false)
+    }
+}
+
+/// Read the last reboot session ID from `path`, and replace it with the next
+/// one, which is returned. A missing file starts the count at 1.
+///
+/// # Errors
+/// Errors when the file can't be read or written, or doesn't hold a number
+pub fn next_rsid(path: &Path) -> io::Result<u64> {
+    let last = match fs::read_to_string(path) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    let next = if last >= MAX_COUNTER { 1 } else { last + 1 };
+    // written next to the file and renamed, so a crash never leaves it empty
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, next.to_string())?;
+    fs::rename(&tmp, path)?;
+    Ok(next)
+}
+
+/// The current time as a RFC3339 timestamp
+fn now() -> String {
+    let (year, month, day, secs) = utc_now();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// `message` without the framing of `write_format`
+fn unframed(write_format: WriteFormat, message: &[u8]) -> &[u8] {
+    match write_format {
+        WriteFormat::RFC5425 => message
+            .iter()
+            .position(|b| *b == b' ')
+            .map_or(message, |space| &message[space + 1..]),
+        WriteFormat::RFC6587 => message.strip_suffix(b"\n").unwrap_or(message),
+        _ => message,
+    }
+}
+
+/// Sends messages through a [`Sender`](../sender/struct.Sender.html), followed by
+/// signature and certificate blocks. See the [module documentation](index.html).
+pub struct SignedSender<T> {
+    sender: Sender<T>,
+    key: SigningKey,
+    rsid: u64,
+    hash: HashAlgorithm,
+    priority: u8,
+    max_hashes: usize,
+    cert_interval: u64,
+    certificate: Option<Vec<u8>>,
+    /// Global block counter of the next signature block
+    gbc: u64,
+    /// Message number of the first hash in `hashes`
+    first_number: u64,
+    hashes: Vec<Vec<u8>>,
+    /// Signature blocks sent since the last certificate block, `None` before
+    /// the first one
+    blocks_since_cert: Option<u64>,
+}
+
+impl<T: Transport> SignedSender<T> {
+    /// Sign the messages sent through `sender` with `key`. `rsid` is the reboot
+    /// session ID, see [`next_rsid`](fn.next_rsid.html).
+    pub fn new(sender: Sender<T>, key: SigningKey, rsid: u64) -> SignedSender<T> {
+        let priority = encode_priority(sender.formatter().facility, Severity::Notice);
+        SignedSender {
+            sender,
+            key,
+            rsid,
+            hash: HashAlgorithm::Sha256,
+            priority,
+            max_hashes: 25,
+            cert_interval: 100,
+            certificate: None,
+            gbc: 1,
+            first_number: 1,
+            hashes: Vec::new(),
+            blocks_since_cert: None,
+        }
+    }
+
+    /// Defaults to `HashAlgorithm::Sha256`
+    pub fn hash_algorithm(mut self, hash: HashAlgorithm) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// PRI of the signature and certificate blocks (SPRI). Defaults to the
+    /// facility of the formatter, with `Severity::Notice`.
+    pub fn signature_priority(mut self, facility: Facility, severity: Severity) -> Self {
+        self.priority = encode_priority(facility, severity);
+        self
+    }
+
+    /// Number of hashes in a signature block. Defaults to 25, which keeps
+    /// blocks with SHA256 hashes under 2048 bytes.
+    pub fn max_hashes(mut self, max: usize) -> Self {
+        self.max_hashes = max.max(1);
+        self
+    }
+
+    /// Send a certificate block again after this many signature blocks.
+    /// Defaults to 100. With 0 it's only sent once.
+    pub fn certificate_interval(mut self, blocks: u64) -> Self {
+        self.cert_interval = blocks;
+        self
+    }
+
+    /// Send `der`, a X.509 certificate of the key, in the certificate blocks
+    /// instead of the bare public key
+    pub fn certificate(mut self, der: Vec<u8>) -> Self {
+        self.certificate = Some(der);
+        self
+    }
+
+    pub fn rsid(&self) -> u64 {
+        self.rsid
+    }
+
+    /// Global block counter of the next signature block
+    pub fn global_block_counter(&self) -> u64 {
+        self.gbc
+    }
+
+    pub fn sender(&self) -> &Sender<T> {
+        &self.sender
+    }
+
+    /// Give back the wrapped sender, without signing the pending hashes
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+
+    /// Send `data`, unless the filter of the sender drops it, and a signature
+    /// block once enough messages were sent.
+    ///
+    /// # Errors
+    /// Errors when formatting, signing or the transport fails
+    pub fn send(&mut self, data: &impl Rfc5424Data) -> io::Result<()> {
+        if !self.sender.allows(data) {
+            return Ok(());
+        }
+        if self.blocks_since_cert.is_none() {
+            self.send_certificate()?;
+        }
+        let mut messages = Vec::new();
+        self.sender.formatter().format_each(data, |message| {
+            messages.push(message.to_vec());
+            Ok(())
+        })?;
+        let write_format = self.sender.formatter().write_format;
+        for message in messages {
+            self.hashes
+                .push(self.hash.hash(unframed(write_format, &message))?);
+            self.sender.transport_mut().send(&message)?;
+            if self.hashes.len() >= self.max_hashes {
+                self.send_signature()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a signature block for the messages sent since the last one.
+    ///
+    /// # Errors
+    /// Errors when formatting, signing or the transport fails
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.send_signature()
+    }
+
+    fn ver(&self) -> String {
+        format!("01{}{}", self.hash.code(), self.key.scheme.code())
+    }
+
+    fn send_signature(&mut self) -> io::Result<()> {
+        if self.hashes.is_empty() {
+            return Ok(());
+        }
+        let hashes: Vec<String> = self
+            .hashes
+            .iter()
+            .map(|h| base64::encode_block(h))
+            .collect();
+        let params = vec![
+            ("VER", self.ver()),
+            ("RSID", self.rsid.to_string()),
+            ("SG", "0".to_string()),
+            ("SPRI", self.priority.to_string()),
+            ("GBC", self.gbc.to_string()),
+            ("FMN", self.first_number.to_string()),
+            ("CNT", hashes.len().to_string()),
+            ("HB", hashes.join(" ")),
+        ];
+        self.send_block("ssign", params)?;
+
+        self.first_number += self.hashes.len() as u64;
+        if self.first_number > MAX_COUNTER {
+            self.first_number = 1;
+        }
+        self.gbc = if self.gbc >= MAX_COUNTER {
+            1
+        } else {
+            self.gbc + 1
+        };
+        self.hashes.clear();
+
+        let sent = self.blocks_since_cert.map_or(1, |blocks| blocks + 1);
+        self.blocks_since_cert = Some(sent);
+        if self.cert_interval > 0 && sent >= self.cert_interval {
+            self.send_certificate()?;
+        }
+        Ok(())
+    }
+
+    fn send_certificate(&mut self) -> io::Result<()> {
+        let (blob_type, blob) = match &self.certificate {
+            Some(der) => ('C', der.clone()),
+            None => (
+                'K',
+                self.key.key.public_key_to_der().map_err(io::Error::other)?,
+            ),
+        };
+        let payload = format!("{} {} {}", now(), blob_type, base64::encode_block(&blob));
+        // the payload is ASCII, so it can be split at any byte
+        for (i, fragment) in payload.as_bytes().chunks(FRAGMENT_LEN).enumerate() {
+            let params = vec![
+                ("VER", self.ver()),
+                ("RSID", self.rsid.to_string()),
+                ("SG", "0".to_string()),
+                ("SPRI", self.priority.to_string()),
+                ("TPBL", payload.len().to_string()),
+                ("INDEX", (i * FRAGMENT_LEN + 1).to_string()),
+                ("FLEN", fragment.len().to_string()),
+                ("FRAG", String::from_utf8_lossy(fragment).into_owned()),
+            ];
+            self.send_block("ssign-cert", params)?;
+        }
+        self.blocks_since_cert = Some(0);
+        Ok(())
+    }
+
+    /// Sign and send a block. The signature covers the block formatted with an
+    /// empty SIGN, which is added as the last parameter.
+    fn send_block(&mut self, sd_id: &'static str, params: Vec<(&str, String)>) -> io::Result<()> {
+        let (facility, severity) =
+            decode_priority(self.priority).unwrap_or((Facility::User, Severity::Notice));
+        let mut block = Block {
+            facility,
+            severity,
+            timestamp: now(),
+            sd_id,
+            params: params
+                .into_iter()
+                .map(|(name, val)| (name.to_string(), val))
+                .collect(),
+        };
+        block.params.push(("SIGN".into(), String::new()));
+
+        let formatter = self.sender.formatter();
+        let mut unsigned = Vec::new();
+        formatter.format(&mut unsigned, &block)?;
+        let signature = self
+            .key
+            .sign(self.hash, unframed(formatter.write_format, &unsigned))?;
+        if let Some(sign) = block.params.last_mut() {
+            sign.1 = base64::encode_block(&signature);
+        }
+
+        let mut signed = Vec::new();
+        formatter.format(&mut signed, &block)?;
+        self.sender.transport_mut().send(&signed)
+    }
+}
+
+/// A signature or certificate block, with the header of the formatter
+struct Block {
+    facility: Facility,
+    severity: Severity,
+    timestamp: String,
+    sd_id: &'static str,
+    params: Vec<(String, String)>,
+}
+
+impl Rfc5424Data for Block {
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn timestamp(&self) -> Option<String> {
+        Some(self.timestamp.clone())
+    }
+
+    fn structured_data(&self) -> Option<StructuredData<'_>> {
+        let mut sd = StructuredData::new();
+        sd.insert(self.sd_id, self.params.clone());
+        Some(sd)
+    }
+
+    fn message(&self) -> Option<Message> {
+        None
+    }
+
+    fn facility(&self) -> Option<Facility> {
+        Some(self.facility)
+    }
+}
+
+/// A problem found by a [`Verifier`](struct.Verifier.html). Messages are the
+/// bytes that were pushed to the verifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// A message hashed by a signature block was not received
+    Missing { rsid: u64, number: u64 },
+    /// A message was received in place of one hashed by a signature block,
+    /// but its hash is different
+    Altered {
+        rsid: u64,
+        number: u64,
+        message: Vec<u8>,
+    },
+    /// A message isn't hashed by any signature block
+    Unsigned { message: Vec<u8> },
+    /// Signature blocks with these global block counters were not received,
+    /// so the messages they hashed can't be checked
+    MissingBlocks { rsid: u64, first: u64, last: u64 },
+    /// A signature or certificate block isn't well formed, or its signature is
+    /// invalid. Its hashes are ignored.
+    InvalidBlock { message: Vec<u8> },
+}
+
+/// Result of [`Verifier::finish`](struct.Verifier.html#method.finish)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Number of messages whose hash is in a valid signature block
+    pub verified: usize,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug)]
+struct SignatureBlock {
+    rsid: u64,
+    gbc: u64,
+    first_number: u64,
+    hash: HashAlgorithm,
+    hashes: Vec<Vec<u8>>,
+}
+
+/// Checks messages signed by a [`SignedSender`](struct.SignedSender.html). See
+/// the [module documentation](index.html).
+///
+/// Messages are matched to the hashes of the signature blocks of every RSID.
+/// A message received between two messages of a block, in place of a missing
+/// one, is reported as altered.
+pub struct Verifier {
+    key: VerifyingKey,
+    messages: Vec<Vec<u8>>,
+    blocks: Vec<SignatureBlock>,
+    findings: Vec<Finding>,
+}
+
+impl Verifier {
+    /// Verify blocks signed by the private key of `key`
+    pub fn new(key: VerifyingKey) -> Verifier {
+        Verifier {
+            key,
+            messages: Vec::new(),
+            blocks: Vec::new(),
+            findings: Vec::new(),
+        }
+    }
+
+    /// Add a received message, without its transport framing, in the order it
+    /// was received.
+    pub fn push(&mut self, message: &[u8]) {
+        let parsed = match parser::parse(message) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                self.messages.push(message.to_vec());
+                return;
+            }
+        };
+        let element = parsed
+            .structured_data
+            .iter()
+            .find(|e| e.id == "ssign" || e.id == "ssign-cert");
+        let element = match element {
+            Some(element) => element,
+            None => {
+                self.messages.push(message.to_vec());
+                return;
+            }
+        };
+        match self.check_block(message, element) {
+            // certificate blocks only need a valid signature
+            Some(block) => self.blocks.extend(block),
+            None => self.findings.push(Finding::InvalidBlock {
+                message: message.to_vec(),
+            }),
+        }
+    }
+
+    /// Check the signature of a block, and read the hashes of signature blocks
+    fn check_block(&self, message: &[u8], element: &SdElement) -> Option<Option<SignatureBlock>> {
+        let param = |name: &str| {
+            element
+                .params
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let number = |name: &str| param(name).and_then(|v| v.parse::<u64>().ok());
+
+        let ver = param("VER")?;
+        if ver.len() != 4 || !ver.starts_with("01") {
+            return None;
+        }
+        let hash = HashAlgorithm::from_code(ver.chars().nth(2)?)?;
+        if ver.chars().nth(3)? != self.key.scheme.code() {
+            return None;
+        }
+        let signature = base64::decode_block(param("SIGN")?).ok()?;
+        if !self
+            .key
+            .verify(hash, &without_signature(message)?, &signature)
+        {
+            return None;
+        }
+        if element.id == "ssign-cert" {
+            return Some(None);
+        }
+
+        let hashes = param("HB")?
+            .split(' ')
+            .filter(|h| !h.is_empty())
+            .map(|h| base64::decode_block(h).ok())
+            .collect::<Option<Vec<_>>>()?;
+        if number("CNT")? != hashes.len() as u64 {
+            return None;
+        }
+        Some(Some(SignatureBlock {
+            rsid: number("RSID")?,
+            gbc: number("GBC")?,
+            first_number: number("FMN")?,
+            hash,
+            hashes,
+        }))
+    }
+
+    /// Match the messages to the signature blocks, and report what doesn't match
+    pub fn finish(mut self) -> Report {
+        self.blocks.sort_by_key(|b| (b.rsid, b.gbc));
+        self.blocks.dedup_by_key(|b| (b.rsid, b.gbc));
+        for pair in self.blocks.windows(2) {
+            if pair[0].rsid == pair[1].rsid && pair[1].gbc > pair[0].gbc + 1 {
+                self.findings.push(Finding::MissingBlocks {
+                    rsid: pair[0].rsid,
+                    first: pair[0].gbc + 1,
+                    last: pair[1].gbc - 1,
+                });
+            }
+        }
+
+        let mut hashes: HashMap<HashAlgorithm, HashMap<Vec<u8>, Vec<usize>>> = HashMap::new();
+        let mut covered = vec![false; self.messages.len()];
+        let mut verified = 0;
+        for block in &self.blocks {
+            let index = hashes.entry(block.hash).or_insert_with(|| {
+                let mut index: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+                for (i, message) in self.messages.iter().enumerate() {
+                    if let Ok(hash) = block.hash.hash(message) {
+                        index.entry(hash).or_default().push(i);
+                    }
+                }
+                index
+            });
+
+            // the received message of each hash of the block
+            let matched: Vec<Option<usize>> = block
+                .hashes
+                .iter()
+                .map(|hash| {
+                    let found = index
+                        .get(hash)
+                        .and_then(|found| found.iter().find(|i| !covered[**i]).cloned());
+                    if let Some(i) = found {
+                        covered[i] = true;
+                    }
+                    found
+                })
+                .collect();
+            verified += matched.iter().filter(|m| m.is_some()).count();
+
+            let mut slot = 0;
+            while slot < matched.len() {
+                if matched[slot].is_some() {
+                    slot += 1;
+                    continue;
+                }
+                // a run of hashes without a message
+                let end = (slot..matched.len())
+                    .find(|i| matched[*i].is_some())
+                    .unwrap_or(matched.len());
+                let before = slot.checked_sub(1).and_then(|i| matched[i]);
+                let after = matched.get(end).copied().flatten();
+                // unsigned messages received in their place were altered
+                let replacements: Vec<usize> = match (before, after) {
+                    (Some(before), Some(after)) => {
+                        (before + 1..after).filter(|i| !covered[*i]).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let mut replacements = replacements.into_iter();
+                for missing in slot..end {
+                    let number = block.first_number + missing as u64;
+                    match replacements.next() {
+                        Some(i) => {
+                            covered[i] = true;
+                            self.findings.push(Finding::Altered {
+                                rsid: block.rsid,
+                                number,
+                                message: self.messages[i].clone(),
+                            });
+                        }
+                        None => self.findings.push(Finding::Missing {
+                            rsid: block.rsid,
+                            number,
+                        }),
+                    }
+                }
+                slot = end;
+            }
+        }
+
+        for (message, covered) in self.messages.into_iter().zip(covered) {
+            if !covered {
+                self.findings.push(Finding::Unsigned { message });
+            }
+        }
+        Report {
+            verified,
+            findings: self.findings,
+        }
+    }
+}
+
+/// `message` with the value of its SIGN parameter removed, as it was signed
+fn without_signature(message: &[u8]) -> Option<Vec<u8>> {
+    let pattern = b" SIGN=\"";
+    let start = message.windows(pattern.len()).rposition(|w| w == pattern)? + pattern.len();
+    let len = message[start..].iter().position(|b| *b == b'"')?;
+    let mut unsigned = message[..start].to_vec();
+    unsigned.extend_from_slice(&message[start + len..]);
+    Some(unsigned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use std::env;
+    use {Rfc5424Builder, WriteFormat};
+
+    struct Line(String);
+
+    impl Rfc5424Data for Line {
+        fn severity(&self) -> Severity {
+            Severity::Notice
+        }
+
+        fn timestamp(&self) -> Option<String> {
+            None
+        }
+
+        fn structured_data(&self) -> Option<StructuredData<'_>> {
+            None
+        }
+
+        fn message(&self) -> Option<Message> {
+            Some(Message::Text(self.0.clone()))
+        }
+    }
+
+    fn signed(key: SigningKey, write_format: WriteFormat) -> Vec<Vec<u8>> {
+        let f = Rfc5424Builder::new("32473", Facility::Local0)
+            .app_name("audit")
+            .unwrap()
+            .write_format(write_format)
+            .build();
+        let mut sender = SignedSender::new(Sender::new(f, Vec::new()), key, 7).max_hashes(3);
+        for i in 1..=5 {
+            sender.send(&Line(format!("event {}", i))).unwrap();
+        }
+        sender.flush().unwrap();
+        assert_eq!(sender.global_block_counter(), 3);
+        let (f, sent) = sender.into_inner().into_parts();
+        sent.iter()
+            .map(|m| unframed(f.write_format, m).to_vec())
+            .collect()
+    }
+
+    fn ed25519() -> SigningKey {
+        SigningKey::new(PKey::generate_ed25519().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn blocks() {
+        let key = ed25519();
+        let public = key.verifying_key().unwrap();
+        let sent = signed(key, WriteFormat::RFC5425);
+        // certificate block, 3 messages, signature block, 2 messages, signature block
+        assert_eq!(sent.len(), 8);
+        let cert = parser::parse(&sent[0]).unwrap();
+        assert_eq!(cert.structured_data[0].id, "ssign-cert");
+        assert_eq!(cert.facility, Facility::Local0);
+        assert_eq!(cert.severity, Severity::Notice);
+
+        let block = parser::parse(&sent[4]).unwrap();
+        let element = &block.structured_data[0];
+        assert_eq!(element.id, "ssign");
+        let names: Vec<_> = element.params.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["VER", "RSID", "SG", "SPRI", "GBC", "FMN", "CNT", "HB", "SIGN"]
+        );
+        assert_eq!(element.params[0].1, "0123");
+        assert_eq!(element.params[1].1, "7");
+        assert_eq!(element.params[6].1, "3");
+
+        let mut verifier = Verifier::new(public);
+        for message in &sent {
+            verifier.push(message);
+        }
+        assert_eq!(
+            verifier.finish(),
+            Report {
+                verified: 5,
+                findings: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn tampering() {
+        let key = ed25519();
+        let public = key.verifying_key().unwrap();
+        let mut sent = signed(key, WriteFormat::RFC5424);
+        let altered = String::from_utf8(sent[2].clone())
+            .unwrap()
+            .replace("event 2", "event X")
+            .into_bytes();
+        sent[2] = altered.clone();
+        // drop "event 5", and add a message that was never signed
+        sent.remove(6);
+        sent.push(b"<13>1 - - - - - - forged".to_vec());
+        // a signature block with a forged hash
+        let forged = String::from_utf8(sent[4].clone())
+            .unwrap()
+            .replace("GBC=\"1\"", "GBC=\"9\"")
+            .into_bytes();
+        sent.push(forged.clone());
+
+        let mut verifier = Verifier::new(public);
+        for message in &sent {
+            verifier.push(message);
+        }
+        let report = verifier.finish();
+        assert_eq!(report.verified, 3);
+        assert_eq!(
+            report.findings,
+            vec![
+                Finding::InvalidBlock { message: forged },
+                Finding::Altered {
+                    rsid: 7,
+                    number: 2,
+                    message: altered,
+                },
+                Finding::Missing { rsid: 7, number: 5 },
+                Finding::Unsigned {
+                    message: b"<13>1 - - - - - - forged".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rsa() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let key = SigningKey::new(key).unwrap();
+        assert_eq!(key.scheme(), SignatureScheme::Rsa);
+        let public = key.verifying_key().unwrap();
+        let sent = signed(key, WriteFormat::RFC6587);
+
+        // a different key doesn't verify the blocks
+        let mut verifier = Verifier::new(ed25519().verifying_key().unwrap());
+        verifier.push(&sent[4]);
+        assert_eq!(verifier.finish().findings.len(), 1);
+
+        let mut verifier = Verifier::new(public);
+        for message in &sent {
+            verifier.push(message);
+        }
+        assert_eq!(verifier.finish().findings, vec![]);
+    }
+
+    #[test]
+    fn rsid_file() {
+        let path = env::temp_dir().join(format!("syslog5424-rsid-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(next_rsid(&path).unwrap(), 1);
+        assert_eq!(next_rsid(&path).unwrap(), 2);
+        fs::write(&path, MAX_COUNTER.to_string()).unwrap();
+        assert_eq!(next_rsid(&path).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
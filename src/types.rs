@@ -1,13 +1,28 @@
 //! Types used to specify values in a RFC5424 message
 
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
 use {Error, NILVALUE};
 
+#[cfg(feature = "serde")]
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, Serializer};
+
 /// Syslog facility
 ///
+/// Displayed as its lowercase keyword (`local4`), see
+/// [`keyword`](#method.keyword). Parsing also accepts the aliases used in
+/// syslog.conf files, ignoring case, and `TryFrom<u8>` converts the numeric code.
+///
 /// * [Definition in RFC5424 Section 6.2.1](https://tools.ietf.org/html/rfc5424#section-6.2.1)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Facility {
     Kernel = 0,
+    #[default]
     User = 1,
     Mail = 2,
     Daemon = 3,
@@ -33,17 +48,106 @@ pub enum Facility {
     Local7 = 23,
 }
 
-impl Default for Facility {
-    fn default() -> Facility {
-        Facility::User
+const FACILITIES: [(Facility, &str); 24] = [
+    (Facility::Kernel, "kern"),
+    (Facility::User, "user"),
+    (Facility::Mail, "mail"),
+    (Facility::Daemon, "daemon"),
+    (Facility::Auth, "auth"),
+    (Facility::Syslog, "syslog"),
+    (Facility::LinePrinter, "lpr"),
+    (Facility::News, "news"),
+    (Facility::UUCP, "uucp"),
+    (Facility::Cron, "cron"),
+    (Facility::AuthPriv, "authpriv"),
+    (Facility::FTP, "ftp"),
+    (Facility::NTP, "ntp"),
+    (Facility::Security, "audit"),
+    (Facility::Console, "console"),
+    (Facility::ClockDaemon, "clock"),
+    (Facility::Local0, "local0"),
+    (Facility::Local1, "local1"),
+    (Facility::Local2, "local2"),
+    (Facility::Local3, "local3"),
+    (Facility::Local4, "local4"),
+    (Facility::Local5, "local5"),
+    (Facility::Local6, "local6"),
+    (Facility::Local7, "local7"),
+];
+
+impl Facility {
+    /// The lowercase keyword used for this facility in syslog configuration
+    /// files, such as `kern` or `local0`
+    pub fn keyword(self) -> &'static str {
+        FACILITIES[self as usize].1
+    }
+
+    /// All facilities, in the order of their numeric codes
+    pub fn all() -> impl Iterator<Item = Facility> {
+        FACILITIES.iter().map(|(f, _)| *f)
+    }
+}
+
+/// Aliases accepted when parsing, besides the keywords. `security` is the
+/// deprecated syslog.conf name of `auth`, not of facility 13 (`audit`).
+const FACILITY_ALIASES: [(Facility, &str); 2] =
+    [(Facility::Kernel, "kernel"), (Facility::Auth, "security")];
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.keyword())
     }
 }
 
+impl FromStr for Facility {
+    type Err = UnknownFacility;
+
+    fn from_str(s: &str) -> Result<Facility, UnknownFacility> {
+        let s = s.to_ascii_lowercase();
+        FACILITIES
+            .iter()
+            .chain(FACILITY_ALIASES.iter())
+            .find(|(_, k)| *k == s)
+            .map(|(f, _)| *f)
+            .ok_or(UnknownFacility)
+    }
+}
+
+impl TryFrom<u8> for Facility {
+    type Error = UnknownFacility;
+
+    fn try_from(code: u8) -> Result<Facility, UnknownFacility> {
+        FACILITIES
+            .get(code as usize)
+            .map(|(f, _)| *f)
+            .ok_or(UnknownFacility)
+    }
+}
+
+/// Error returned for names and numbers that aren't a [`Facility`](enum.Facility.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownFacility;
+
+impl fmt::Display for UnknownFacility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unknown syslog facility")
+    }
+}
+
+impl error::Error for UnknownFacility {}
+
 /// Syslog severity
 ///
 /// * [Definition in RFC5424 Section 6.2.1](https://tools.ietf.org/html/rfc5424#section-6.2.1)
 /// * [Severity Values A.3.](https://tools.ietf.org/html/rfc5424#appendix-A.3)
-#[derive(Debug, Clone, Copy)]
+///
+/// Severities are ordered by their numeric code, so *more* severe messages
+/// compare as *less*: `Severity::Error < Severity::Warning`. To check that a
+/// message is at least as severe as a `threshold`, use `severity <= threshold`.
+///
+/// Displayed as its lowercase keyword (`err`), and parsed from the keyword or one
+/// of its common aliases (`error`, `warn`, `panic`...), ignoring case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Severity {
     Emergency = 0,
     Alert = 1,
@@ -55,16 +159,118 @@ pub enum Severity {
     Debug = 7,
 }
 
+const SEVERITIES: [(Severity, &str); 8] = [
+    (Severity::Emergency, "emerg"),
+    (Severity::Alert, "alert"),
+    (Severity::Critical, "crit"),
+    (Severity::Error, "err"),
+    (Severity::Warning, "warning"),
+    (Severity::Notice, "notice"),
+    (Severity::Informational, "info"),
+    (Severity::Debug, "debug"),
+];
+
+impl Severity {
+    /// The lowercase keyword used for this severity in syslog configuration
+    /// files, such as `err` or `info`
+    pub fn keyword(self) -> &'static str {
+        SEVERITIES[self as usize].1
+    }
+
+    /// All severities, from `Emergency` to `Debug`
+    pub fn all() -> impl Iterator<Item = Severity> {
+        SEVERITIES.iter().map(|(s, _)| *s)
+    }
+}
+
+const SEVERITY_ALIASES: [(Severity, &str); 7] = [
+    (Severity::Emergency, "emergency"),
+    (Severity::Emergency, "panic"),
+    (Severity::Critical, "critical"),
+    (Severity::Error, "error"),
+    (Severity::Warning, "warn"),
+    (Severity::Informational, "informational"),
+    (Severity::Informational, "information"),
+];
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
+
+impl FromStr for Severity {
+    type Err = UnknownSeverity;
+
+    fn from_str(s: &str) -> Result<Severity, UnknownSeverity> {
+        let s = s.to_ascii_lowercase();
+        SEVERITIES
+            .iter()
+            .chain(SEVERITY_ALIASES.iter())
+            .find(|(_, k)| *k == s)
+            .map(|(sev, _)| *sev)
+            .ok_or(UnknownSeverity)
+    }
+}
+
+impl TryFrom<u8> for Severity {
+    type Error = UnknownSeverity;
+
+    fn try_from(code: u8) -> Result<Severity, UnknownSeverity> {
+        SEVERITIES
+            .get(code as usize)
+            .map(|(s, _)| *s)
+            .ok_or(UnknownSeverity)
+    }
+}
+
+/// Error returned for names and numbers that aren't a [`Severity`](enum.Severity.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownSeverity;
+
+impl fmt::Display for UnknownSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unknown syslog severity")
+    }
+}
+
+impl error::Error for UnknownSeverity {}
+
+/// The PRI value of a message: `facility * 8 + severity`
+///
+/// * [RFC5424 Section 6.2.1](https://tools.ietf.org/html/rfc5424#section-6.2.1)
+pub fn encode_priority(facility: Facility, severity: Severity) -> u8 {
+    facility as u8 * 8 + severity as u8
+}
+
+/// Split a PRI value into its facility and severity. Returns `None` for values
+/// larger than 191.
+pub fn decode_priority(pri: u8) -> Option<(Facility, Severity)> {
+    let facility = Facility::try_from(pri / 8).ok()?;
+    let severity = Severity::try_from(pri % 8).ok()?;
+    Some((facility, severity))
+}
+
 /// The message portion of a syslog message may be either UTF-8 or
 /// binary.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Message {
     Text(String),
     Binary(Vec<u8>),
 }
 
 /// Wrapper for `String` containing the Host Name. Limited to 255 ASCII chars.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct HostName(pub String);
 impl HostName {
     pub fn new(hostname: &str) -> Result<HostName, Error> {
@@ -79,7 +285,12 @@ impl Default for HostName {
 }
 
 /// Wrapper for `String` containing the App Name. Limited to 48 ASCII chars.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct AppName(pub String);
 impl AppName {
     pub fn new(name: &str) -> Result<AppName, Error> {
@@ -94,7 +305,12 @@ impl Default for AppName {
 }
 
 /// Wrapper for `String` containing the Process ID. Limited to 128 ASCII chars.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct ProcessId(pub String);
 impl ProcessId {
     pub fn new(id: &str) -> Result<ProcessId, Error> {
@@ -108,8 +324,41 @@ impl Default for ProcessId {
     }
 }
 
+/// Where the PROCID of formatted messages comes from. A PROCID returned by
+/// [`Rfc5424Data::pid`](../trait.Rfc5424Data.html#method.pid) is used instead,
+/// when there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessIdSource {
+    /// The same PROCID for every message
+    Static(ProcessId),
+    /// The id of the process, read every time a message is formatted. This stays
+    /// correct in the children of a `fork`.
+    Current,
+}
+
+impl ProcessIdSource {
+    /// The PROCID a message formatted now would get
+    pub fn get(&self) -> ProcessId {
+        match self {
+            ProcessIdSource::Static(pid) => pid.clone(),
+            ProcessIdSource::Current => ProcessId(::std::process::id().to_string()),
+        }
+    }
+}
+
+impl Default for ProcessIdSource {
+    fn default() -> ProcessIdSource {
+        ProcessIdSource::Static(ProcessId::default())
+    }
+}
+
 /// Wrapper for `String` containing the Message ID. Limited to 32 ASCII chars.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct MessageId(pub String);
 impl MessageId {
     pub fn new(id: &str) -> Result<MessageId, Error> {
@@ -123,6 +372,26 @@ impl Default for MessageId {
     }
 }
 
+macro_rules! header_conversions {
+    ($($name:ident),*) => {$(
+        impl TryFrom<String> for $name {
+            type Error = Error;
+
+            fn try_from(value: String) -> Result<$name, Error> {
+                $name::new(&value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> String {
+                value.0
+            }
+        }
+    )*};
+}
+
+header_conversions!(HostName, AppName, ProcessId, MessageId);
+
 /// Convert a string into a header value after verifying it is valid.
 ///
 /// # Errors
@@ -164,3 +433,151 @@ pub fn remove_invalid(name: &str) -> String {
         .take(32)
         .collect()
 }
+
+#[cfg(feature = "serde")]
+macro_rules! keyword_serde {
+    ($name:ident, $expecting:expr) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.keyword())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                struct KeywordVisitor;
+
+                impl<'de> Visitor<'de> for KeywordVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str($expecting)
+                    }
+
+                    fn visit_str<E: de::Error>(self, v: &str) -> Result<$name, E> {
+                        v.parse::<$name>()
+                            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+                    }
+                }
+
+                deserializer.deserialize_str(KeywordVisitor)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+keyword_serde!(Facility, "a syslog facility keyword such as `local0`");
+#[cfg(feature = "serde")]
+keyword_serde!(Severity, "a syslog severity keyword such as `err`");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "serde")]
+    use serde_json::{from_str, to_string};
+
+    #[test]
+    fn names() {
+        assert_eq!("local4".parse(), Ok(Facility::Local4));
+        assert_eq!("KERN".parse(), Ok(Facility::Kernel));
+        assert_eq!("kernel".parse(), Ok(Facility::Kernel));
+        assert_eq!("security".parse(), Ok(Facility::Auth));
+        assert_eq!("audit".parse(), Ok(Facility::Security));
+        assert_eq!("cron2".parse::<Facility>(), Err(UnknownFacility));
+        assert_eq!("local8".parse::<Facility>(), Err(UnknownFacility));
+        assert_eq!("warn".parse(), Ok(Severity::Warning));
+        assert_eq!("Error".parse(), Ok(Severity::Error));
+        assert_eq!("info".parse::<Severity>(), Ok(Severity::Informational));
+        assert_eq!("loud".parse::<Severity>(), Err(UnknownSeverity));
+
+        for f in Facility::all() {
+            assert_eq!(f.to_string().parse(), Ok(f));
+        }
+        for s in Severity::all() {
+            assert_eq!(s.to_string().parse(), Ok(s));
+        }
+        assert_eq!(Facility::all().count(), 24);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Facility::try_from(20), Ok(Facility::Local4));
+        assert_eq!(Facility::try_from(24), Err(UnknownFacility));
+        assert_eq!(Severity::try_from(7), Ok(Severity::Debug));
+        assert_eq!(Severity::try_from(8), Err(UnknownSeverity));
+
+        assert_eq!(encode_priority(Facility::Local4, Severity::Notice), 165);
+        assert_eq!(
+            decode_priority(165),
+            Some((Facility::Local4, Severity::Notice))
+        );
+        assert_eq!(
+            decode_priority(191),
+            Some((Facility::Local7, Severity::Debug))
+        );
+        assert_eq!(decode_priority(192), None);
+
+        assert!(Severity::Error < Severity::Warning);
+        let threshold = Severity::Warning;
+        let shown: Vec<_> = Severity::all().filter(|s| *s <= threshold).collect();
+        assert_eq!(shown.last(), Some(&Severity::Warning));
+        assert_eq!(shown.len(), 5);
+    }
+
+    #[test]
+    fn facility_round_trip() {
+        for (code, f) in Facility::all().enumerate() {
+            assert_eq!(f as usize, code);
+            assert_eq!(Facility::try_from(code as u8), Ok(f));
+            assert_eq!(f.keyword().parse(), Ok(f));
+            assert_eq!(f.to_string(), f.keyword());
+        }
+        assert_eq!(Facility::Security.to_string(), "audit");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn keywords() {
+        assert_eq!(to_string(&Facility::Local3).unwrap(), r#""local3""#);
+        assert_eq!(to_string(&Severity::Error).unwrap(), r#""err""#);
+        assert_eq!(from_str::<Facility>(r#""kern""#).unwrap(), Facility::Kernel);
+        assert_eq!(
+            from_str::<Severity>(r#""info""#).unwrap(),
+            Severity::Informational
+        );
+        assert_eq!(
+            from_str::<Severity>(r#""warn""#).unwrap(),
+            Severity::Warning
+        );
+        assert!(from_str::<Facility>(r#""local9""#).is_err());
+        for f in Facility::all() {
+            assert_eq!(from_str::<Facility>(&to_string(&f).unwrap()).unwrap(), f);
+        }
+        assert!(from_str::<Severity>("3").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn header_values() {
+        let host = HostName::new("mymachine.example.com").unwrap();
+        assert_eq!(to_string(&host).unwrap(), r#""mymachine.example.com""#);
+        assert_eq!(
+            from_str::<HostName>(r#""mymachine.example.com""#).unwrap(),
+            host
+        );
+        assert!(from_str::<AppName>(r#""two words""#).is_err());
+        assert!(from_str::<MessageId>(r#""""#).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn messages() {
+        let text = Message::Text("hi".into());
+        assert_eq!(to_string(&text).unwrap(), r#"{"text":"hi"}"#);
+        assert_eq!(
+            from_str::<Message>(r#"{"binary":[1,2]}"#).unwrap(),
+            Message::Binary(vec![1, 2])
+        );
+    }
+}